@@ -1,9 +1,12 @@
-use std::{sync::Arc, time::Instant};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
 
-use glam::Vec3;
 use winit::{
     application::ApplicationHandler,
-    dpi::LogicalSize,
+    dpi::{LogicalSize, PhysicalSize},
     event::{DeviceEvent, DeviceId, ElementState, KeyEvent, WindowEvent},
     event_loop::ActiveEventLoop,
     keyboard::{KeyCode, PhysicalKey},
@@ -11,11 +14,24 @@ use winit::{
 };
 
 use anyhow::Result;
+use egui::{Key, KeyboardShortcut, Modifiers};
+use glam::vec2;
 
 use crate::{
-    camera::Camera,
+    camera::{Camera, CameraMode, CameraRay, FovAxis, Projection},
+    camera_path::{CameraAnimation, CameraKeyframe, CameraPath, Turntable},
+    cpu_tracer::{cast_ray, pick},
+    environment::{Environment, EnvironmentMap},
     gfx_context::GfxContext,
-    scene::{Material, Scene, Sphere},
+    gizmo::{Gizmo, GizmoMode},
+    gltf_import::load_gltf,
+    history::Edit,
+    obj_import::load_obj,
+    pixel_filter::PixelFilter,
+    scene::{Material, Scene, SceneObject, Sphere},
+    scene_file::SceneFile,
+    sky::ProceduralSky,
+    tone_mapping::ToneMapper,
 };
 
 pub struct App {
@@ -27,6 +43,21 @@ pub struct App {
     camera: Camera,
     /// A descriptor of the scene currently being rendered.
     scene: Scene,
+    /// The object selected by clicking on it or in the ui, if any.
+    selection: Option<SceneObject>,
+    /// Whether the ui should scroll to the selected object, after it was picked in the image.
+    scroll_to_selection: bool,
+    /// The handles drawn over the selected object for moving and scaling it.
+    gizmo: Gizmo,
+    /// The camera as of the last camera move recorded in the history of the scene, which it is
+    /// compared against to record the next one.
+    recorded_camera: Camera,
+    /// How the camera moves when the scene is rendered as an image sequence, if it does.
+    animation: Option<CameraAnimation>,
+    /// The time along `animation` the camera was last moved to, in seconds.
+    animation_time: f32,
+    /// Whether `animation` is being played back, moving the camera every frame.
+    animation_playing: bool,
 
     /// The egui winit side state of the window to manage events.
     egui_state: egui_winit::State,
@@ -35,6 +66,17 @@ pub struct App {
     /// If the egui display is currently enabled.
     egui_enabled: bool,
 
+    /// The path, without an extension, that renders are saved to.
+    render_path: String,
+    /// The path that scene files are loaded from and saved to.
+    scene_path: String,
+    /// The path that obj files are imported from.
+    obj_path: String,
+    /// The path that gltf files are loaded from.
+    gltf_path: String,
+    /// The path that environment maps are loaded from.
+    environment_path: String,
+
     /// The time in seconds since the last frame, also known as delta time.
     dt: f32,
     /// The time of the last frame.
@@ -42,38 +84,70 @@ pub struct App {
 
     /// If the `window` currently has focus over the cursor.
     focused: bool,
+    /// How far the mouse has moved since the primary button was pressed over the image, or
+    /// `None` if it isn't pressed, which tells clicks picking objects apart from drags.
+    click_travel: Option<f64>,
 }
 
 pub enum AppHandler {
-    Running(App),
-    Initializing,
+    Running(Box<App>),
+    Initializing {
+        /// The scene file to load once the app has been created.
+        scene_path: Option<PathBuf>,
+    },
 }
 
 impl App {
-    async fn new(window: Window) -> Result<Self> {
-        use glam::vec3;
+    /// How far the mouse may move between pressing and releasing the primary button for it to
+    /// still count as a click, in raw mouse units.
+    const MAX_CLICK_TRAVEL: f64 = 4.0;
 
+    const UNDO_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
+    const REDO_SHORTCUT: KeyboardShortcut =
+        KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::Z);
+
+    async fn new(window: Window, scene_path: Option<PathBuf>) -> Result<Self> {
         let window = Arc::new(window);
 
-        let camera = Camera::new_facing(vec3(0.0, 1.0, 4.0), Vec3::NEG_Z);
+        let camera = Camera::default();
         let gfx_context = GfxContext::new(Arc::clone(&window), &camera).await?;
 
-        let scene = Scene::new(&gfx_context);
+        let scene = Scene::new();
 
         let (egui_ctx, egui_state) = Self::initialize_egui(&window);
 
-        Ok(Self {
+        let mut app = Self {
             gfx_context,
             window,
             camera,
             scene,
+            selection: None,
+            scroll_to_selection: false,
+            gizmo: Gizmo::default(),
+            recorded_camera: camera,
+            animation: None,
+            animation_time: 0.0,
+            animation_playing: false,
             egui_state,
             egui_ctx,
             egui_enabled: true,
+            render_path: String::from("render"),
+            scene_path: String::from("scene.ron"),
+            obj_path: String::from("model.obj"),
+            gltf_path: String::from("model.gltf"),
+            environment_path: String::from("environment.hdr"),
             dt: 0.0,
             last_frame: Instant::now(),
             focused: false,
-        })
+            click_travel: None,
+        };
+
+        if let Some(path) = scene_path {
+            app.load_scene(&path)?;
+            app.scene_path = path.display().to_string();
+        }
+
+        Ok(app)
     }
 
     fn initialize_egui(window: &Window) -> (egui::Context, egui_winit::State) {
@@ -112,6 +186,42 @@ impl App {
                 self.egui_enabled = !self.egui_enabled;
             }
 
+            WE::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyP),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.save_render();
+            }
+
+            WE::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyF),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.frame_selection();
+            }
+
+            WE::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(KeyCode::KeyT),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.gizmo.toggle_mode();
+            }
+
             WE::Resized(size) => self.gfx_context.resize(size),
 
             WE::CloseRequested => event_loop.exit(),
@@ -122,7 +232,11 @@ impl App {
 
     fn device_event(&mut self, event: DeviceEvent) {
         if let DeviceEvent::MouseMotion { delta } = event {
-            if self.egui_ctx.is_pointer_over_area() {
+            if let Some(travel) = &mut self.click_travel {
+                *travel += delta.0.abs() + delta.1.abs();
+            }
+
+            if self.egui_ctx.is_pointer_over_area() || self.gizmo.is_active() {
                 return;
             }
 
@@ -141,13 +255,41 @@ impl App {
         self.dt = self.last_frame.elapsed().as_secs_f32();
         self.last_frame = Instant::now();
 
-        self.scene.update_buffers(&self.gfx_context);
-        self.gfx_context.update_buffers(&mut self.camera);
+        if self.animation_playing {
+            self.play_animation();
+        }
+
+        self.gfx_context.selection = self.selection;
+        self.gfx_context
+            .update_buffers(&mut self.camera, &self.scene);
 
-        let hovering = self.egui_ctx.is_pointer_over_area();
+        let hovering = self.egui_ctx.is_pointer_over_area() || self.gizmo.is_active();
+        let mut focus_click = None;
+        let mut pick_click = None;
 
         self.egui_ctx.input(|i| {
-            if !hovering && i.pointer.primary_down() {
+            if !hovering && i.pointer.secondary_clicked() {
+                focus_click = i.pointer.interact_pos();
+            }
+
+            if !hovering && i.pointer.primary_pressed() {
+                self.click_travel = Some(0.0);
+            }
+
+            // the cursor is locked while dragging, so only the raw mouse motion tells a drag
+            if i.pointer.primary_released() {
+                if let Some(travel) = self.click_travel.take() {
+                    if travel < Self::MAX_CLICK_TRAVEL {
+                        pick_click = i.pointer.interact_pos();
+                    }
+                }
+            }
+
+            if !hovering {
+                self.camera.handle_scroll(i.smooth_scroll_delta.y);
+            }
+
+            if !hovering && (i.pointer.primary_down() || i.pointer.middle_down()) {
                 self.window.set_cursor_grab(CursorGrabMode::Locked).unwrap();
                 self.window.set_cursor_visible(false);
                 self.focused = true;
@@ -162,10 +304,36 @@ impl App {
             }
         });
 
-        if let Err(e) = self
-            .gfx_context
-            .render(&self.egui_ctx, egui_output, &self.scene)
-        {
+        if let Some(position) = focus_click {
+            self.autofocus(position);
+        }
+
+        if let Some(position) = pick_click {
+            self.pick_object(position);
+        }
+
+        // text fields have their own undo
+        if !self.egui_ctx.wants_keyboard_input() {
+            let (undo, redo) = self.egui_ctx.input_mut(|i| {
+                // undo matches with shift held as well, so redo has to be checked first
+                let redo = i.consume_shortcut(&Self::REDO_SHORTCUT);
+
+                (i.consume_shortcut(&Self::UNDO_SHORTCUT), redo)
+            });
+
+            let history = self.scene.history();
+            let position = history.position();
+
+            if undo && history.can_undo() {
+                self.go_to_edit(position - 1);
+            } else if redo && history.can_redo() {
+                self.go_to_edit(position + 1);
+            }
+        }
+
+        self.record_camera_move();
+
+        if let Err(e) = self.gfx_context.render(&self.egui_ctx, egui_output) {
             match e {
                 SE::Timeout => (),
                 SE::OutOfMemory => panic!("out of memory!"),
@@ -176,18 +344,364 @@ impl App {
         self.window.request_redraw();
     }
 
+    /// Advances the animation by the last frame's time, looping it or stopping at its end, and
+    /// moves the camera along.
+    fn play_animation(&mut self) {
+        let Some(animation) = &self.animation else {
+            self.animation_playing = false;
+            return;
+        };
+
+        let duration = animation.duration();
+        self.animation_time += self.dt;
+
+        if self.animation_time >= duration {
+            if animation.loops() && duration > 0.0 {
+                self.animation_time %= duration;
+            } else {
+                self.animation_time = duration;
+                self.animation_playing = false;
+            }
+        }
+
+        animation.apply(&mut self.camera, self.animation_time);
+    }
+
+    /// Makes the camera orbit around the selected object, from far enough away to see all of it.
+    fn frame_selection(&mut self) {
+        let Some(object) = self.selection else {
+            log::info!("there is no selected object to frame");
+            return;
+        };
+
+        let Some((center, radius)) = self.scene.bounding_sphere(object) else {
+            return;
+        };
+
+        let PhysicalSize { width, height } = self.window.inner_size();
+
+        self.camera
+            .frame(center, radius, width as f32 / height as f32);
+    }
+
+    /// The ray from the camera through a point on the window, given in egui points.
+    fn cursor_ray(&self, position: egui::Pos2) -> Option<CameraRay> {
+        let PhysicalSize { width, height } = self.window.inner_size();
+        let pixels = position.to_vec2() * self.egui_ctx.pixels_per_point();
+
+        let coord = vec2(
+            pixels.x / width as f32 * 2.0 - 1.0,
+            1.0 - pixels.y / height as f32 * 2.0,
+        );
+
+        self.camera.world_ray(coord, width as f32 / height as f32)
+    }
+
+    /// Selects the object under a point on the window, given in egui points, or clears the
+    /// selection if there is nothing there.
+    fn pick_object(&mut self, position: egui::Pos2) {
+        let Some(ray) = self.cursor_ray(position) else {
+            return;
+        };
+
+        self.selection = pick(&self.scene, ray.origin, ray.direction)
+            .filter(|&(_, distance)| distance <= ray.max_distance)
+            .map(|(object, _)| object);
+        self.scroll_to_selection = self.selection.is_some();
+    }
+
+    /// Focuses the camera on the surface under a point on the window, given in egui points.
+    fn autofocus(&mut self, position: egui::Pos2) {
+        let Some(ray) = self.cursor_ray(position) else {
+            return;
+        };
+
+        match cast_ray(&self.scene, ray.origin, ray.direction) {
+            Some(distance) => self.camera.focus_on(ray.origin + ray.direction * distance),
+            None => log::info!("there is nothing to focus on under the cursor"),
+        }
+    }
+
+    /// Removes an object from the scene, recording it in the history, and keeps the selection on
+    /// the same object as the ones after it move down.
+    fn remove_object(&mut self, object: SceneObject) {
+        let edit = match object {
+            SceneObject::Sphere(index) => Edit::RemoveSphere {
+                index,
+                sphere: self.scene.spheres()[index],
+            },
+            SceneObject::Mesh(index) => Edit::RemoveMesh {
+                index,
+                mesh: self.scene.meshes()[index].clone(),
+            },
+        };
+
+        if !Self::apply_edit(&mut self.scene, edit) {
+            return;
+        }
+
+        self.selection = match (self.selection, object) {
+            (Some(selection), _) if selection == object => None,
+            (Some(SceneObject::Sphere(i)), SceneObject::Sphere(removed)) if i > removed => {
+                Some(SceneObject::Sphere(i - 1))
+            }
+            (Some(SceneObject::Mesh(i)), SceneObject::Mesh(removed)) if i > removed => {
+                Some(SceneObject::Mesh(i - 1))
+            }
+            (selection, _) => selection,
+        };
+    }
+
+    /// Makes an edit to the scene and records it in the history, logging it if the edit doesn't
+    /// fit the scene. Returns whether the scene was changed.
+    fn apply_edit(scene: &mut Scene, edit: Edit) -> bool {
+        match scene.apply(edit) {
+            Ok(()) => true,
+            Err(e) => {
+                log::error!("failed to edit the scene: {e:#}");
+                false
+            }
+        }
+    }
+
+    /// Undoes or redoes edits until the given number of edits in the history are applied.
+    fn go_to_edit(&mut self, position: usize) {
+        self.scene.go_to_edit(position, &mut self.camera);
+
+        self.reset_recorded_camera();
+        self.selection = self.selection.filter(|&object| self.scene.contains(object));
+
+        self.gfx_context.reset_accumulation();
+    }
+
+    /// Records the camera having moved since the last time it was recorded in the history, unless
+    /// it is following the animation.
+    fn record_camera_move(&mut self) {
+        if self.animation_playing {
+            return;
+        }
+
+        let before = self.recorded_camera;
+        self.reset_recorded_camera();
+
+        if self.recorded_camera != before {
+            self.scene.record_camera_move(before, self.recorded_camera);
+        }
+    }
+
+    /// Takes the current camera as where the next camera move recorded in the history starts.
+    fn reset_recorded_camera(&mut self) {
+        self.recorded_camera = Camera {
+            moved: false,
+            ..self.camera
+        };
+    }
+
+    /// Saves the accumulated image to `render_path`, as both a tone mapped 8-bit png and a linear
+    /// exr.
+    fn save_render(&self) {
+        let png_path = format!("{}.png", self.render_path);
+        let exr_path = format!("{}.exr", self.render_path);
+
+        let result = self.gfx_context.read_accumulation().and_then(|image| {
+            image
+                .tone_mapped(&self.gfx_context.tone_mapping)
+                .save_png(&png_path)?;
+            image.save_exr(&exr_path)
+        });
+
+        match result {
+            Ok(()) => log::info!("saved render to {png_path} and {exr_path}"),
+            Err(e) => log::error!("failed to save render: {e:#}"),
+        }
+    }
+
+    /// Replaces the current scene, camera, sky and environment with the contents of a scene file.
+    fn load_scene(&mut self, path: &Path) -> Result<()> {
+        let file = SceneFile::load(path)?;
+        let environment = file.environment()?;
+
+        self.camera = file.camera;
+        self.gfx_context.render_uniform.sky_color = file.sky_color;
+        self.gfx_context.environment = environment;
+        self.gfx_context.procedural_sky = file.procedural_sky;
+        self.gfx_context.tone_mapping = file.tone_mapping;
+        self.scene
+            .replace(file.scene(), format!("loaded {}", path.display()));
+        self.selection = None;
+        self.animation = file.animation;
+        self.animation_time = 0.0;
+        self.animation_playing = false;
+        self.reset_recorded_camera();
+
+        self.gfx_context.reset_accumulation();
+
+        Ok(())
+    }
+
+    /// Adds the meshes and materials of an obj file to the scene.
+    fn import_obj(&mut self, path: &Path) -> Result<()> {
+        let (meshes, materials) = load_obj(path)?;
+
+        self.scene.apply(Edit::AddMeshes { meshes, materials })?;
+        self.gfx_context.reset_accumulation();
+
+        Ok(())
+    }
+
+    /// Replaces the current scene with the contents of a gltf file, along with the camera if it
+    /// has one.
+    fn load_gltf(&mut self, path: &Path) -> Result<()> {
+        let gltf = load_gltf(path)?;
+
+        let mut scene = Scene::with_objects(Vec::new(), Vec::new());
+        scene.add_meshes(gltf.meshes, gltf.materials);
+
+        self.scene
+            .replace(scene, format!("loaded {}", path.display()));
+        self.selection = None;
+
+        if let Some(camera) = gltf.camera {
+            self.camera = camera;
+        }
+
+        self.reset_recorded_camera();
+        self.gfx_context.reset_accumulation();
+
+        Ok(())
+    }
+
+    /// Replaces the environment lighting the scene with an environment map, keeping the rotation
+    /// and intensity of the current one.
+    fn load_environment(&mut self, path: &Path) -> Result<()> {
+        let map = Arc::new(EnvironmentMap::load(path)?);
+
+        let environment = match self.gfx_context.environment.take() {
+            Some(environment) => Environment { map, ..environment },
+            None => Environment::new(map),
+        };
+
+        self.gfx_context.environment = Some(environment);
+        self.gfx_context.reset_accumulation();
+
+        Ok(())
+    }
+
+    /// Writes the current scene, camera, sky, environment, tone mapping and animation to a scene
+    /// file.
+    fn save_scene(&self, path: &Path) -> Result<()> {
+        let sky_color = self.gfx_context.render_uniform.sky_color;
+        let environment = self.gfx_context.environment.as_ref();
+        let procedural_sky = self.gfx_context.procedural_sky;
+
+        SceneFile::new(
+            &self.scene,
+            self.camera,
+            sky_color,
+            environment,
+            procedural_sky,
+            self.gfx_context.tone_mapping,
+            self.animation.clone(),
+        )
+        .save(path)
+    }
+
     fn ui(&mut self) -> egui::FullOutput {
         use egui::*;
 
         let raw_input = self.egui_state.take_egui_input(&self.window);
 
         if !self.egui_enabled {
+            self.gizmo.hide();
+
             return self.egui_ctx.run(raw_input, |_| {});
         }
 
-        self.egui_ctx.run(raw_input, |ctx| {
+        let mut save_render = false;
+        let mut load_scene = false;
+        let mut save_scene = false;
+        let mut import_obj = false;
+        let mut load_gltf = false;
+        let mut load_environment = false;
+        let mut clear_environment = false;
+        let mut environment_changed = false;
+        let mut sky_changed = false;
+        let mut scene_edited = false;
+        let mut removed_object = None;
+        let mut removed_material = None;
+        let mut go_to_edit = None;
+
+        let output = self.egui_ctx.run(raw_input, |ctx| {
             Window::new("render info").show(ctx, |ui| {
-                ui.label(&format!("frame time: {:0.3}", self.dt * 1000.0));
+                ui.label(format!("frame time: {:0.3}", self.dt * 1000.0));
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    let target_samples = &mut self.gfx_context.target_samples;
+                    let mut limited = target_samples.is_some();
+
+                    ui.label("target samples: ");
+                    ui.checkbox(&mut limited, "");
+
+                    if limited != target_samples.is_some() {
+                        *target_samples = limited.then_some(1024);
+                    }
+
+                    if let Some(target) = target_samples {
+                        ui.add(DragValue::new(target).range(1..=1 << 20));
+                    }
+                });
+
+                let samples = self.gfx_context.samples_accumulated();
+                let elapsed = self.gfx_context.accumulation_time().as_secs_f32();
+
+                if let Some(target) = self.gfx_context.target_samples {
+                    let progress = samples as f32 / target as f32;
+                    let remaining = target.saturating_sub(samples) as f32;
+                    let eta = elapsed / samples.max(1) as f32 * remaining;
+
+                    ui.add(
+                        ProgressBar::new(progress.min(1.0))
+                            .text(format!("{samples}/{target} samples")),
+                    );
+                    ui.label(format!("elapsed: {elapsed:.1}s, eta: {eta:.1}s"));
+                } else {
+                    ui.label(format!("samples: {samples}, elapsed: {elapsed:.1}s"));
+                }
+
+                let previous_sampling = (
+                    self.gfx_context.samples_per_frame,
+                    self.gfx_context.pixel_filter,
+                );
+
+                ui.horizontal(|ui| {
+                    ui.label("samples per frame: ");
+                    ui.add(DragValue::new(&mut self.gfx_context.samples_per_frame).range(1..=64));
+                });
+
+                ui.horizontal(|ui| {
+                    let pixel_filter = &mut self.gfx_context.pixel_filter;
+
+                    ui.label("pixel filter: ");
+
+                    ComboBox::from_id_salt("pixel filter")
+                        .selected_text(pixel_filter.name())
+                        .show_ui(ui, |ui| {
+                            for filter in PixelFilter::ALL {
+                                ui.selectable_value(pixel_filter, filter, filter.name());
+                            }
+                        });
+                });
+
+                if previous_sampling
+                    != (
+                        self.gfx_context.samples_per_frame,
+                        self.gfx_context.pixel_filter,
+                    )
+                {
+                    self.gfx_context.reset_accumulation();
+                }
 
                 ui.separator();
 
@@ -206,6 +720,159 @@ impl App {
                     ui.add(DragValue::new(&mut self.camera.pitch).speed(0.1));
                 });
 
+                ui.horizontal(|ui| {
+                    let mut mode = self.camera.mode;
+
+                    ui.label("camera mode: ");
+                    ui.radio_value(&mut mode, CameraMode::Fly, "fly");
+                    ui.radio_value(&mut mode, CameraMode::Orbit, "orbit");
+
+                    self.camera.set_mode(mode);
+                });
+
+                if self.camera.mode == CameraMode::Orbit {
+                    ui.label("drag to orbit, middle or shift drag to pan, scroll to dolly");
+                    ui.label("F frames the selected object");
+                }
+
+                let camera = &mut self.camera;
+                let previous_projection = (
+                    camera.projection,
+                    camera.fov,
+                    camera.fov_axis,
+                    camera.orthographic_height,
+                    camera.near,
+                    camera.far,
+                );
+
+                ui.horizontal(|ui| {
+                    ui.label("projection: ");
+
+                    ComboBox::from_id_salt("projection")
+                        .selected_text(camera.projection.name())
+                        .show_ui(ui, |ui| {
+                            for projection in Projection::ALL {
+                                ui.selectable_value(
+                                    &mut camera.projection,
+                                    projection,
+                                    projection.name(),
+                                );
+                            }
+                        });
+                });
+
+                match camera.projection {
+                    Projection::Perspective | Projection::Fisheye => {
+                        let max_fov = camera.max_fov();
+
+                        ui.horizontal(|ui| {
+                            ui.label("field of view: ");
+                            ui.add(Slider::new(&mut camera.fov, 1.0..=max_fov).suffix("°"));
+
+                            ui.radio_value(&mut camera.fov_axis, FovAxis::Vertical, "vertical");
+                            ui.radio_value(&mut camera.fov_axis, FovAxis::Horizontal, "horizontal");
+                        });
+                    }
+                    Projection::Orthographic => {
+                        ui.horizontal(|ui| {
+                            ui.label("view height: ");
+                            ui.add(
+                                DragValue::new(&mut camera.orthographic_height)
+                                    .speed(0.01)
+                                    .range(0.01..=f32::MAX),
+                            );
+                        });
+                    }
+                    Projection::Equirectangular => {}
+                }
+
+                if camera.projection == Projection::Perspective {
+                    ui.horizontal(|ui| {
+                        let mut focal_length = camera.focal_length();
+
+                        ui.label("focal length: ");
+
+                        let response = ui.add(
+                            DragValue::new(&mut focal_length)
+                                .speed(0.1)
+                                .range(1.0..=2000.0)
+                                .suffix(" mm"),
+                        );
+
+                        if response.changed() {
+                            camera.set_focal_length(focal_length);
+                        }
+                    });
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label("clip planes: ");
+                    ui.add(
+                        DragValue::new(&mut camera.near)
+                            .speed(0.001)
+                            .range(0.0001..=f32::MAX),
+                    );
+                    ui.add(
+                        DragValue::new(&mut camera.far)
+                            .speed(1.0)
+                            .range(0.001..=f32::MAX),
+                    );
+                });
+
+                if previous_projection
+                    != (
+                        camera.projection,
+                        camera.fov,
+                        camera.fov_axis,
+                        camera.orthographic_height,
+                        camera.near,
+                        camera.far,
+                    )
+                {
+                    camera.moved = true;
+                }
+
+                let previous_lens = (
+                    camera.aperture_radius,
+                    camera.focus_distance,
+                    camera.aperture_blades,
+                );
+
+                ui.horizontal(|ui| {
+                    ui.label("aperture radius: ");
+                    ui.add(
+                        DragValue::new(&mut camera.aperture_radius)
+                            .speed(0.001)
+                            .range(0.0..=f32::MAX),
+                    );
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("focus distance: ");
+                    ui.add(
+                        DragValue::new(&mut camera.focus_distance)
+                            .speed(0.01)
+                            .range(0.01..=f32::MAX),
+                    );
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("aperture blades: ");
+                    ui.add(DragValue::new(&mut camera.aperture_blades).range(0..=16));
+                });
+
+                ui.label("right click to focus on a point");
+
+                if previous_lens
+                    != (
+                        camera.aperture_radius,
+                        camera.focus_distance,
+                        camera.aperture_blades,
+                    )
+                {
+                    camera.moved = true;
+                }
+
                 ui.separator();
 
                 ui.horizontal(|ui| {
@@ -220,6 +887,84 @@ impl App {
                     color.z = color_array[2];
                 });
 
+                let procedural_sky = &mut self.gfx_context.procedural_sky;
+                let previous_sky = *procedural_sky;
+
+                ui.horizontal(|ui| {
+                    let mut enabled = procedural_sky.is_some();
+
+                    ui.label("procedural sky: ");
+                    ui.checkbox(&mut enabled, "");
+
+                    if enabled != procedural_sky.is_some() {
+                        *procedural_sky = enabled.then(ProceduralSky::default);
+                    }
+                });
+
+                if let Some(sky) = procedural_sky {
+                    ui.horizontal(|ui| {
+                        ui.label("sun elevation: ");
+                        ui.add(Slider::new(&mut sky.sun_elevation, 0.0..=90.0).suffix("°"));
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("sun azimuth: ");
+                        ui.add(Slider::new(&mut sky.sun_azimuth, -180.0..=180.0).suffix("°"));
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("turbidity: ");
+                        ui.add(Slider::new(
+                            &mut sky.turbidity,
+                            ProceduralSky::MIN_TURBIDITY..=ProceduralSky::MAX_TURBIDITY,
+                        ));
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("sun size: ");
+                        ui.add(
+                            Slider::new(&mut sky.sun_size, 0.1..=20.0)
+                                .logarithmic(true)
+                                .suffix("°"),
+                        );
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("sun intensity: ");
+                        ui.add(Slider::new(&mut sky.sun_intensity, 0.0..=16.0).logarithmic(true));
+                    });
+                }
+
+                sky_changed = *procedural_sky != previous_sky;
+
+                ui.horizontal(|ui| {
+                    ui.label("environment path: ");
+                    ui.text_edit_singleline(&mut self.environment_path);
+                });
+
+                ui.horizontal(|ui| {
+                    load_environment = ui.button("load environment").clicked();
+                    clear_environment = ui.button("clear environment").clicked();
+                });
+
+                if let Some(environment) = &mut self.gfx_context.environment {
+                    let previous = (environment.rotation, environment.intensity);
+
+                    ui.horizontal(|ui| {
+                        ui.label("environment rotation: ");
+                        ui.add(Slider::new(&mut environment.rotation, -180.0..=180.0).suffix("°"));
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("environment intensity: ");
+                        ui.add(
+                            Slider::new(&mut environment.intensity, 0.0..=16.0).logarithmic(true),
+                        );
+                    });
+
+                    environment_changed = previous != (environment.rotation, environment.intensity);
+                }
+
                 ui.separator();
 
                 ui.horizontal(|ui| {
@@ -233,54 +978,396 @@ impl App {
                         self.gfx_context.reset_accumulation();
                     }
                 });
-            });
 
-            Window::new("spheres").show(ctx, |ui| {
-                if ui.button("add sphere to scene").clicked() {
-                    self.scene.add_sphere(Sphere::random());
-                }
+                ui.horizontal(|ui| {
+                    ui.label("render scale: ");
+
+                    let mut scale = self.gfx_context.render_scale();
+                    ui.add(Slider::new(&mut scale, 0.25..=2.0).logarithmic(true));
+
+                    if scale != self.gfx_context.render_scale() {
+                        self.gfx_context.set_render_scale(scale);
+                    }
+                });
 
                 ui.separator();
 
-                let materials_len = self.scene.materials_mut().len() as u32 - 1;
+                let tone_mapping = &mut self.gfx_context.tone_mapping;
 
-                for sphere in self.scene.spheres_mut() {
-                    ui.horizontal(|ui| {
-                        let position = &mut sphere.position;
+                ui.horizontal(|ui| {
+                    ui.label("tone mapper: ");
+
+                    ComboBox::from_id_salt("tone mapper")
+                        .selected_text(tone_mapping.tone_mapper.name())
+                        .show_ui(ui, |ui| {
+                            for tone_mapper in ToneMapper::ALL {
+                                ui.selectable_value(
+                                    &mut tone_mapping.tone_mapper,
+                                    tone_mapper,
+                                    tone_mapper.name(),
+                                );
+                            }
+                        });
+                });
 
-                        ui.label("position: ");
-                        ui.add(DragValue::new(&mut position.x).speed(0.01));
-                        ui.add(DragValue::new(&mut position.y).speed(0.01));
-                        ui.add(DragValue::new(&mut position.z).speed(0.01));
+                ui.horizontal(|ui| {
+                    ui.label("exposure: ");
+                    ui.add(Slider::new(&mut tone_mapping.exposure, -8.0..=8.0).suffix(" ev"));
+                });
+
+                ui.add_enabled_ui(tone_mapping.tone_mapper != ToneMapper::None, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("white point: ");
+                        ui.add(
+                            Slider::new(&mut tone_mapping.white_point, 1.0..=64.0)
+                                .logarithmic(true),
+                        );
                     });
+                });
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.label("render path: ");
+                    ui.text_edit_singleline(&mut self.render_path);
+                });
+
+                save_render = ui.button("save render (P)").clicked();
+            });
+
+            Window::new("scene").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("scene path: ");
+                    ui.text_edit_singleline(&mut self.scene_path);
+                });
+
+                ui.horizontal(|ui| {
+                    load_scene = ui.button("load scene").clicked();
+                    save_scene = ui.button("save scene").clicked();
+                });
 
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.label("obj path: ");
+                    ui.text_edit_singleline(&mut self.obj_path);
+                });
+
+                import_obj = ui.button("import obj").clicked();
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.label("gltf path: ");
+                    ui.text_edit_singleline(&mut self.gltf_path);
+                });
+
+                load_gltf = ui.button("load gltf").clicked();
+
+                ui.separator();
+
+                let gizmo = &mut self.gizmo;
+
+                ui.horizontal(|ui| {
+                    ui.label("gizmo: ");
+
+                    for mode in GizmoMode::ALL {
+                        ui.radio_value(&mut gizmo.mode, mode, mode.name());
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut gizmo.snapping, "snap to steps of ");
+                    ui.add(
+                        DragValue::new(&mut gizmo.translate_step)
+                            .speed(0.01)
+                            .range(0.001..=f32::MAX),
+                    );
+                    ui.label("and scales of ");
+                    ui.add(
+                        DragValue::new(&mut gizmo.scale_step)
+                            .speed(0.01)
+                            .range(0.01..=f32::MAX),
+                    );
+                });
+
+                ui.label("T switches the gizmo mode, holding ctrl while dragging toggles snapping");
+            });
+
+            Window::new("camera animation").show(ctx, |ui| {
+                let camera = &mut self.camera;
+
+                ui.label(match &self.animation {
+                    None => String::from("no animation"),
+                    Some(CameraAnimation::Path(path)) => {
+                        format!("path through {} keyframes", path.keyframes().len())
+                    }
+                    Some(CameraAnimation::Turntable(_)) => String::from("turntable"),
+                });
+
+                ui.horizontal(|ui| {
+                    if ui.button("add keyframe").clicked() {
+                        if !matches!(self.animation, Some(CameraAnimation::Path(_))) {
+                            self.animation = Some(CameraAnimation::Path(CameraPath::default()));
+                        }
+
+                        if let Some(CameraAnimation::Path(path)) = &mut self.animation {
+                            // a second after the last keyframe
+                            let time = if path.keyframes().is_empty() {
+                                0.0
+                            } else {
+                                path.duration() + 1.0
+                            };
+
+                            path.push(CameraKeyframe::from_camera(camera, time));
+                            self.animation_time = time;
+                        }
+                    }
+
+                    if ui.button("turntable around focus").clicked() {
+                        let target = camera.eye + camera.forward() * camera.focus_distance;
+
+                        self.animation = Some(CameraAnimation::Turntable(Turntable::from_camera(
+                            camera, target,
+                        )));
+                        self.animation_time = 0.0;
+                    }
+
+                    if ui.button("clear").clicked() {
+                        self.animation = None;
+                        self.animation_time = 0.0;
+                        self.animation_playing = false;
+                    }
+                });
+
+                let Some(animation) = &mut self.animation else {
+                    return;
+                };
+
+                if let CameraAnimation::Turntable(turntable) = animation {
                     ui.horizontal(|ui| {
-                        ui.label("radius: ");
-                        ui.add(DragValue::new(&mut sphere.radius).speed(0.01));
+                        ui.label("turn duration: ");
+                        ui.add(
+                            DragValue::new(&mut turntable.duration)
+                                .speed(0.1)
+                                .range(0.1..=f32::MAX)
+                                .suffix(" s"),
+                        );
                     });
 
                     ui.horizontal(|ui| {
-                        ui.label("material index: ");
-                        ui.add(Slider::new(&mut sphere.material_index, 0..=materials_len));
+                        ui.label("radius and height: ");
+                        ui.add(
+                            DragValue::new(&mut turntable.radius)
+                                .speed(0.01)
+                                .range(0.0..=f32::MAX),
+                        );
+                        ui.add(DragValue::new(&mut turntable.height).speed(0.01));
                     });
+                }
 
-                    ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("time: ");
+
+                    let scrubbed = ui
+                        .add(
+                            Slider::new(&mut self.animation_time, 0.0..=animation.duration())
+                                .suffix(" s"),
+                        )
+                        .changed();
+
+                    if scrubbed {
+                        animation.apply(camera, self.animation_time);
+                    }
+
+                    ui.checkbox(&mut self.animation_playing, "play");
+                });
+
+                ui.label("render it with `render --frames <count>`");
+            });
+
+            let scroll_to_selection = std::mem::take(&mut self.scroll_to_selection);
+
+            let spheres_window = Window::new("spheres").show(ctx, |ui| {
+                if ui.button("add sphere to scene").clicked() {
+                    let edit = Edit::AddSphere {
+                        index: self.scene.spheres().len(),
+                        sphere: Sphere::random(),
+                    };
+
+                    scene_edited |= Self::apply_edit(&mut self.scene, edit);
                 }
+
+                ui.separator();
+
+                let materials_len = (self.scene.materials().len() as u32).saturating_sub(1);
+
+                ScrollArea::vertical().show(ui, |ui| {
+                    for i in 0..self.scene.spheres().len() {
+                        let before = self.scene.spheres()[i];
+                        let mut sphere = before;
+
+                        let object = Some(SceneObject::Sphere(i));
+                        let response =
+                            ui.selectable_value(&mut self.selection, object, format!("sphere {i}"));
+
+                        if scroll_to_selection && self.selection == object {
+                            response.scroll_to_me(Some(Align::Center));
+                        }
+
+                        ui.horizontal(|ui| {
+                            let position = &mut sphere.position;
+
+                            ui.label("position: ");
+                            ui.add(DragValue::new(&mut position.x).speed(0.01));
+                            ui.add(DragValue::new(&mut position.y).speed(0.01));
+                            ui.add(DragValue::new(&mut position.z).speed(0.01));
+                        });
+
+                        ui.horizontal(|ui| {
+                            ui.label("radius: ");
+                            ui.add(DragValue::new(&mut sphere.radius).speed(0.01));
+                        });
+
+                        ui.horizontal(|ui| {
+                            ui.label("material index: ");
+                            ui.add(Slider::new(&mut sphere.material_index, 0..=materials_len));
+                        });
+
+                        if ui.button("remove").clicked() {
+                            removed_object = Some(SceneObject::Sphere(i));
+                        }
+
+                        if sphere != before {
+                            let edit = Edit::ModifySphere {
+                                index: i,
+                                before,
+                                after: sphere,
+                            };
+
+                            scene_edited |= Self::apply_edit(&mut self.scene, edit);
+                        }
+
+                        ui.separator();
+                    }
+                });
             });
 
+            let meshes_window = Window::new("meshes").show(ctx, |ui| {
+                let materials_len = (self.scene.materials().len() as u32).saturating_sub(1);
+
+                ScrollArea::vertical().show(ui, |ui| {
+                    for i in 0..self.scene.meshes().len() {
+                        let mesh = &self.scene.meshes()[i];
+                        let before = mesh.material_index;
+                        let mut material_index = before;
+
+                        let object = Some(SceneObject::Mesh(i));
+                        let response = ui.selectable_value(
+                            &mut self.selection,
+                            object,
+                            format!("{} ({} triangles)", mesh.name, mesh.triangle_count()),
+                        );
+
+                        if scroll_to_selection && self.selection == object {
+                            response.scroll_to_me(Some(Align::Center));
+                        }
+
+                        ui.horizontal(|ui| {
+                            ui.label("material index: ");
+                            ui.add(Slider::new(&mut material_index, 0..=materials_len));
+                        });
+
+                        if ui.button("remove").clicked() {
+                            removed_object = Some(SceneObject::Mesh(i));
+                        }
+
+                        // only modify the mesh when needed, as that uploads all the meshes again
+                        if material_index != before {
+                            let edit = Edit::SetMeshMaterial {
+                                index: i,
+                                before,
+                                after: material_index,
+                            };
+
+                            scene_edited |= Self::apply_edit(&mut self.scene, edit);
+                        }
+
+                        ui.separator();
+                    }
+                });
+            });
+
+            // bring the properties of an object picked in the image to the front
+            let selected_window = match self.selection {
+                Some(SceneObject::Sphere(_)) => spheres_window,
+                Some(SceneObject::Mesh(_)) => meshes_window,
+                None => None,
+            };
+
+            if let Some(window) = selected_window.filter(|_| scroll_to_selection) {
+                ctx.move_to_top(window.response.layer_id);
+            }
+
+            match self.selection {
+                Some(object) => {
+                    scene_edited |= self.gizmo.show(ctx, &self.camera, &mut self.scene, object);
+                }
+                None => self.gizmo.hide(),
+            }
+
             Window::new("materials").show(ctx, |ui| {
                 if ui.button("add material to scene").clicked() {
-                    self.scene.add_material(Material::random());
+                    let edit = Edit::AddMaterial {
+                        index: self.scene.materials().len(),
+                        material: Material::random(),
+                    };
+
+                    scene_edited |= Self::apply_edit(&mut self.scene, edit);
                 }
 
                 ui.separator();
 
-                for mat in self.scene.materials_mut() {
+                let materials_len = self.scene.materials().len();
+
+                for i in 0..materials_len {
+                    let before = self.scene.materials()[i];
+                    let mut mat = before;
+
+                    ui.label(format!("material {i}"));
+
                     ui.horizontal(|ui| {
                         ui.label("roughness: ");
                         ui.add(Slider::new(&mut mat.roughness, 0.0..=1.0));
                     });
 
+                    ui.horizontal(|ui| {
+                        ui.label("metallic: ");
+                        ui.add(Slider::new(&mut mat.metallic, 0.0..=1.0));
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("transmission: ");
+                        ui.add(Slider::new(&mut mat.transmission, 0.0..=1.0));
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("ior: ");
+                        ui.add(Slider::new(&mut mat.ior, 1.0..=3.0));
+                    });
+
+                    ui.horizontal(|ui| {
+                        let color = &mut mat.absorption_color;
+                        let mut color_array = color.to_array();
+
+                        ui.label("absorption color: ");
+                        ui.color_edit_button_rgb(&mut color_array);
+
+                        color.x = color_array[0];
+                        color.y = color_array[1];
+                        color.z = color_array[2];
+                    });
+
                     ui.horizontal(|ui| {
                         let color = &mut mat.albedo;
                         let mut color_array = color.to_array();
@@ -309,21 +1396,187 @@ impl App {
                         ui.label("emission strength: ");
                         ui.add(Slider::new(&mut mat.emission_strength, 0.0..=1.0));
                     });
+
+                    // removing a material in use would leave objects without one
+                    let removable = materials_len > 1 && !self.scene.material_in_use(i);
+
+                    let remove = ui
+                        .add_enabled(removable, Button::new("remove"))
+                        .on_disabled_hover_text("only materials no object uses can be removed");
+
+                    if remove.clicked() {
+                        removed_material = Some(i);
+                    }
+
+                    if mat != before {
+                        let edit = Edit::ModifyMaterial {
+                            index: i,
+                            before,
+                            after: mat,
+                        };
+
+                        scene_edited |= Self::apply_edit(&mut self.scene, edit);
+                    }
+
                     ui.separator();
                 }
             });
-        })
+
+            Window::new("history").show(ctx, |ui| {
+                let history = self.scene.history();
+                let position = history.position();
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(history.can_undo(), Button::new("undo (ctrl+z)"))
+                        .clicked()
+                    {
+                        go_to_edit = Some(position - 1);
+                    }
+
+                    if ui
+                        .add_enabled(history.can_redo(), Button::new("redo (ctrl+shift+z)"))
+                        .clicked()
+                    {
+                        go_to_edit = Some(position + 1);
+                    }
+                });
+
+                ui.label("click an edit to go back to it, undone edits are greyed out");
+                ui.label("loading a scene or a gltf file clears the history");
+
+                ui.separator();
+
+                ScrollArea::vertical().show(ui, |ui| {
+                    // loading a file replaces the scene the earlier edits were made to
+                    let start = match history.cleared_by() {
+                        Some(reason) => ui
+                            .selectable_label(position == 0, format!("start: {reason}"))
+                            .on_hover_text("earlier edits were cleared"),
+                        None => ui.selectable_label(position == 0, "start"),
+                    };
+
+                    if start.clicked() {
+                        go_to_edit = Some(0);
+                    }
+
+                    for (i, edit) in history.edits().iter().enumerate() {
+                        let mut text = RichText::new(edit.description());
+
+                        if i >= position {
+                            text = text.weak();
+                        }
+
+                        if ui.selectable_label(position == i + 1, text).clicked() {
+                            go_to_edit = Some(i + 1);
+                        }
+                    }
+                });
+            });
+        });
+
+        if save_render {
+            self.save_render();
+        }
+
+        if load_scene {
+            let path = PathBuf::from(&self.scene_path);
+
+            match self.load_scene(&path) {
+                Ok(()) => log::info!("loaded scene from {}", path.display()),
+                Err(e) => log::error!("failed to load scene: {e:#}"),
+            }
+        }
+
+        if save_scene {
+            let path = PathBuf::from(&self.scene_path);
+
+            match self.save_scene(&path) {
+                Ok(()) => log::info!("saved scene to {}", path.display()),
+                Err(e) => log::error!("failed to save scene: {e:#}"),
+            }
+        }
+
+        if import_obj {
+            let path = PathBuf::from(&self.obj_path);
+
+            match self.import_obj(&path) {
+                Ok(()) => log::info!("imported obj from {}", path.display()),
+                Err(e) => log::error!("failed to import obj: {e:#}"),
+            }
+        }
+
+        if load_environment {
+            let path = PathBuf::from(&self.environment_path);
+
+            match self.load_environment(&path) {
+                Ok(()) => log::info!("loaded environment map from {}", path.display()),
+                Err(e) => log::error!("failed to load environment map: {e:#}"),
+            }
+        }
+
+        if clear_environment {
+            self.gfx_context.environment = None;
+            self.gfx_context.reset_accumulation();
+        }
+
+        if sky_changed {
+            self.gfx_context.reset_accumulation();
+        }
+
+        if environment_changed {
+            self.gfx_context.reset_accumulation();
+        }
+
+        if let Some(object) = removed_object {
+            self.remove_object(object);
+            scene_edited = true;
+        }
+
+        if let Some(index) = removed_material {
+            let edit = Edit::RemoveMaterial {
+                index,
+                material: self.scene.materials()[index],
+            };
+
+            scene_edited |= Self::apply_edit(&mut self.scene, edit);
+        }
+
+        if scene_edited {
+            self.gfx_context.reset_accumulation();
+        }
+
+        if let Some(position) = go_to_edit {
+            self.go_to_edit(position);
+        }
+
+        if load_gltf {
+            let path = PathBuf::from(&self.gltf_path);
+
+            match self.load_gltf(&path) {
+                Ok(()) => log::info!("loaded gltf from {}", path.display()),
+                Err(e) => log::error!("failed to load gltf: {e:#}"),
+            }
+        }
+
+        output
     }
 }
 
 impl AppHandler {
-    pub fn new() -> Self {
-        Self::Initializing
+    pub fn new(scene_path: Option<PathBuf>) -> Self {
+        Self::Initializing { scene_path }
     }
 }
 
 impl ApplicationHandler for AppHandler {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        let Self::Initializing { scene_path } = self else {
+            return;
+        };
+
+        let scene_path = scene_path.take();
+
         let window = event_loop
             .create_window(
                 Window::default_attributes()
@@ -334,9 +1587,10 @@ impl ApplicationHandler for AppHandler {
 
         window.request_redraw();
 
-        let app = pollster::block_on(App::new(window)).expect("failed to initialize app");
+        let app =
+            pollster::block_on(App::new(window, scene_path)).expect("failed to initialize app");
 
-        *self = AppHandler::Running(app);
+        *self = AppHandler::Running(Box::new(app));
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
@@ -0,0 +1,220 @@
+// the same layout as in shader.wgsl
+struct RenderUniform {
+	inverse_projection: mat4x4<f32>,
+	inverse_view: mat4x4<f32>,
+
+	sky_color: vec3<f32>,
+	time: f32,
+	screen_dimensions: vec2<u32>,
+	frames_accumulated: u32,
+	accumulate: u32,
+
+	sun_direction: vec3<f32>,
+	turbidity: f32,
+	sun_angular_radius: f32,
+	sun_intensity: f32,
+	procedural_sky: u32,
+
+	tile_offset: vec2<u32>,
+	image_dimensions: vec2<u32>,
+
+	samples_per_frame: u32,
+	pixel_filter: u32,
+
+	aperture_radius: f32,
+	focus_distance: f32,
+	aperture_blades: u32,
+
+	projection: u32,
+	near: f32,
+	far: f32,
+	field_of_view: vec2<f32>,
+
+	selected_triangles: vec2<u32>,
+	selected_sphere: i32,
+}
+
+@group(0) @binding(0)
+var<uniform> render_info: RenderUniform;
+
+@group(1) @binding(0)
+var<storage, read_write> accumulation: array<vec4<f32>>;
+
+@group(1) @binding(1)
+var<storage, read_write> selection_mask: array<u32>;
+
+// the color and width in traced pixels of the outline around the selected object
+const OUTLINE_COLOR = vec3<f32>(1.0, 0.45, 0.05);
+const OUTLINE_WIDTH = 2;
+
+const TONE_MAPPER_NONE = 0u;
+const TONE_MAPPER_REINHARD = 1u;
+const TONE_MAPPER_ACES_FILMIC = 2u;
+const TONE_MAPPER_AGX = 3u;
+
+struct DisplayUniform {
+	// two to the power of the exposure
+	exposure_scale: f32,
+	white_point: f32,
+	tone_mapper: u32,
+}
+
+@group(2) @binding(0)
+var<uniform> display: DisplayUniform;
+
+struct VertexOutput {
+	@builtin(position) clip_position: vec4<f32>,
+	// the position on the traced image, from the top left corner at zero to the bottom right at one
+	@location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) in_vertex_index: u32,
+) -> VertexOutput {
+    var positions = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(-1.0, -1.0),
+    );
+
+    var out: VertexOutput;
+
+    out.clip_position = vec4<f32>(positions[in_vertex_index], 0.0, 1.0);
+    out.uv = positions[in_vertex_index] * vec2<f32>(0.5, -0.5) + 0.5;
+
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let screen_dimensions = render_info.screen_dimensions;
+
+    let pixel = min(vec2<u32>(in.uv * vec2<f32>(screen_dimensions)), screen_dimensions - 1u);
+    let color = accumulation[pixel.y * screen_dimensions.x + pixel.x];
+
+		// the samples are weighed by the pixel filter, with the sum of their weights in the last
+		// component
+    var radiance = vec3<f32>(0.0);
+
+    if color.a > 0.0 {
+        radiance = color.rgb / color.a;
+    }
+
+    if is_outline(pixel) {
+        return vec4<f32>(OUTLINE_COLOR, 1.0);
+    }
+
+    return vec4<f32>(tone_map(radiance), 1.0);
+}
+
+// whether the pixel lies just outside of the selected object, as seen in the selection mask
+fn is_outline(pixel: vec2<u32>) -> bool {
+    let screen_dimensions = vec2<i32>(render_info.screen_dimensions);
+    let triangles = render_info.selected_triangles;
+
+    if render_info.selected_sphere < 0 && triangles.x >= triangles.y {
+        return false;
+    }
+
+    let center = vec2<i32>(pixel);
+
+    if selection_mask[center.y * screen_dimensions.x + center.x] != 0u {
+        return false;
+    }
+
+    for (var y = -OUTLINE_WIDTH; y <= OUTLINE_WIDTH; y++) {
+        for (var x = -OUTLINE_WIDTH; x <= OUTLINE_WIDTH; x++) {
+            let neighbor = center + vec2<i32>(x, y);
+
+            if any(neighbor < vec2<i32>(0)) || any(neighbor >= screen_dimensions) {
+                continue;
+            }
+
+            if selection_mask[neighbor.y * screen_dimensions.x + neighbor.x] != 0u {
+                return true;
+            }
+        }
+    }
+
+    return false;
+}
+
+// maps linear radiance to a linear display color in the range [0, 1]
+fn tone_map(radiance: vec3<f32>) -> vec3<f32> {
+    let color = max(radiance, vec3<f32>(0.0)) * display.exposure_scale;
+    let white = max(display.white_point, 1e-4);
+
+    var mapped = color;
+
+    switch display.tone_mapper {
+        case TONE_MAPPER_REINHARD: {
+            mapped = color * (1.0 + color / (white * white)) / (1.0 + color);
+        }
+        case TONE_MAPPER_ACES_FILMIC: {
+            mapped = aces_filmic(color) / aces_filmic(vec3<f32>(white));
+        }
+        case TONE_MAPPER_AGX: {
+            mapped = agx(color) / agx(vec3<f32>(white));
+        }
+        default: {}
+    }
+
+    return clamp(mapped, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn aces_filmic(color: vec3<f32>) -> vec3<f32> {
+		// sRGB to the rendering space of the reference rendering transform, given row by row, so
+		// vectors are multiplied from the left
+    let input = mat3x3<f32>(
+        vec3<f32>(0.59719, 0.35458, 0.04823),
+        vec3<f32>(0.07600, 0.90834, 0.01566),
+        vec3<f32>(0.02840, 0.13383, 0.83777),
+    );
+
+		// the output transform back to sRGB, given row by row
+    let output = mat3x3<f32>(
+        vec3<f32>(1.60475, -0.53108, -0.07367),
+        vec3<f32>(-0.10208, 1.10813, -0.00605),
+        vec3<f32>(-0.00327, -0.07276, 1.07602),
+    );
+
+    let v = color * input;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.432951) + 0.238081;
+
+    return (a / b) * output;
+}
+
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    let inset = mat3x3<f32>(
+        vec3<f32>(0.842479, 0.0423282, 0.0423757),
+        vec3<f32>(0.0784336, 0.878469, 0.0784336),
+        vec3<f32>(0.0792237, 0.0791661, 0.879143),
+    );
+
+    let outset = mat3x3<f32>(
+        vec3<f32>(1.196879, -0.0528969, -0.0529716),
+        vec3<f32>(-0.0980209, 1.151903, -0.0980435),
+        vec3<f32>(-0.0990297, -0.0989612, 1.151074),
+    );
+
+		// the log encoding of the inset color, normalized over the range of exposures it covers
+    let encoded = clamp(log2(max(inset * color, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    let x = (encoded - min_ev) / (max_ev - min_ev);
+
+		// a polynomial fit of the sigmoid contrast curve
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2
+        + 0.1191 * x - 0.00232;
+
+		// the curve produces display encoded values, so decode them back to linear
+    return pow(max(outset * curve, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
@@ -0,0 +1,583 @@
+use glam::Vec3;
+
+use crate::scene::{Sphere, Triangle, Vertex};
+
+/// A bounding volume hierarchy over the spheres and triangles of a scene, built with the surface
+/// area heuristic and flattened into an array of nodes, as traversed by `trace_ray`.
+#[derive(Debug, Clone)]
+pub(crate) struct Bvh {
+    /// The nodes of the hierarchy, starting with the root. Children always come after their
+    /// parent, so the bounds can be refit with a single backwards pass.
+    nodes: Vec<BvhNode>,
+    /// References to every primitive, ordered so that every leaf covers a contiguous range.
+    /// Triangles have [`Bvh::TRIANGLE_BIT`] set, while spheres are referenced by their index alone.
+    primitives: Vec<u32>,
+
+    /// The bounds of every sphere the hierarchy was built or last refit with.
+    sphere_bounds: Vec<Aabb>,
+    /// The bounds of every triangle the hierarchy was built or last refit with.
+    triangle_bounds: Vec<Aabb>,
+}
+
+/// A node of a [`Bvh`], as laid out on the GPU.
+///
+/// Interior nodes have no primitives, and their children are stored next to each other starting
+/// at `first_index`. A node with neither primitives nor children only occurs as the root of an
+/// empty hierarchy.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+pub(crate) struct BvhNode {
+    /// The minimum corner of the bounds of everything below the node.
+    pub minimum: Vec3,
+    /// The index of the first child for interior nodes, or of the first primitive for leaves.
+    pub first_index: u32,
+    /// The maximum corner of the bounds of everything below the node.
+    pub maximum: Vec3,
+    /// The number of primitives in a leaf, or zero for interior nodes.
+    pub primitive_count: u32,
+}
+
+/// An axis aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Aabb {
+    minimum: Vec3,
+    maximum: Vec3,
+}
+
+/// A bucket of primitives whose centroids fall into the same slice of a node, used to evaluate
+/// split candidates without sorting.
+#[derive(Debug, Clone, Copy)]
+struct Bin {
+    bounds: Aabb,
+    count: u32,
+}
+
+impl Bvh {
+    /// Marks a primitive reference as pointing to a triangle rather than a sphere.
+    pub const TRIANGLE_BIT: u32 = 1 << 31;
+    /// The deepest a node can be below the root, which keeps the traversal stack in the shader,
+    /// `BVH_STACK_SIZE`, from overflowing as long as it is at least one larger.
+    const MAX_DEPTH: u32 = 31;
+    /// The number of bins split candidates are evaluated with, along every axis.
+    const BINS: usize = 16;
+    /// The cost of visiting a node, relative to intersecting a single primitive.
+    const TRAVERSAL_COST: f32 = 1.0;
+
+    /// Builds a hierarchy over the given spheres, and the triangles indexing into `vertices`.
+    pub fn new(spheres: &[Sphere], vertices: &[Vertex], triangles: &[Triangle]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            primitives: Vec::new(),
+            sphere_bounds: spheres.iter().map(Aabb::from_sphere).collect(),
+            triangle_bounds: triangles
+                .iter()
+                .map(|triangle| Aabb::from_triangle(triangle, vertices))
+                .collect(),
+        };
+
+        bvh.build();
+        bvh
+    }
+
+    pub fn nodes(&self) -> &[BvhNode] {
+        &self.nodes
+    }
+
+    pub fn primitives(&self) -> &[u32] {
+        &self.primitives
+    }
+
+    /// Brings the hierarchy up to date with the spheres, returning whether anything changed.
+    ///
+    /// Moved or resized spheres only refit the bounds of the existing nodes, while adding or
+    /// removing spheres rebuilds the whole hierarchy.
+    pub fn update_spheres(&mut self, spheres: &[Sphere]) -> bool {
+        let sphere_bounds: Vec<Aabb> = spheres.iter().map(Aabb::from_sphere).collect();
+
+        if sphere_bounds == self.sphere_bounds {
+            return false;
+        }
+
+        let rebuild = sphere_bounds.len() != self.sphere_bounds.len();
+        self.sphere_bounds = sphere_bounds;
+
+        if rebuild {
+            self.build();
+        } else {
+            self.refit();
+        }
+
+        true
+    }
+
+    /// Refits the bounds of the existing nodes to vertices which moved, without changing the
+    /// triangles, which is much cheaper than building a new hierarchy.
+    pub fn update_vertices(&mut self, vertices: &[Vertex], triangles: &[Triangle]) {
+        debug_assert_eq!(triangles.len(), self.triangle_bounds.len());
+
+        self.triangle_bounds = triangles
+            .iter()
+            .map(|triangle| Aabb::from_triangle(triangle, vertices))
+            .collect();
+
+        self.refit();
+    }
+
+    /// Rebuilds the hierarchy from scratch over all the primitives.
+    fn build(&mut self) {
+        let sphere_count = self.sphere_bounds.len() as u32;
+        let triangle_count = self.triangle_bounds.len() as u32;
+
+        self.primitives = (0..sphere_count)
+            .chain((0..triangle_count).map(|i| i | Self::TRIANGLE_BIT))
+            .collect();
+
+        self.nodes.clear();
+        self.nodes.push(BvhNode {
+            minimum: Vec3::ZERO,
+            first_index: 0,
+            maximum: Vec3::ZERO,
+            primitive_count: self.primitives.len() as u32,
+        });
+
+        if !self.primitives.is_empty() {
+            self.subdivide(0, 0);
+        }
+    }
+
+    /// Computes the bounds of a leaf, and splits it in two if that is cheaper to traverse.
+    fn subdivide(&mut self, node_index: usize, depth: u32) {
+        let first = self.nodes[node_index].first_index as usize;
+        let count = self.nodes[node_index].primitive_count as usize;
+        let range = first..first + count;
+
+        let (bounds, centroid_bounds) = self.primitives[range.clone()].iter().fold(
+            (Aabb::EMPTY, Aabb::EMPTY),
+            |(bounds, centroid_bounds), &primitive| {
+                let primitive_bounds = self.primitive_bounds(primitive);
+
+                (
+                    bounds.union(primitive_bounds),
+                    centroid_bounds.grow(primitive_bounds.centroid()),
+                )
+            },
+        );
+
+        self.nodes[node_index].minimum = bounds.minimum;
+        self.nodes[node_index].maximum = bounds.maximum;
+
+        if count <= 1 || depth >= Self::MAX_DEPTH {
+            return;
+        }
+
+        let Some((axis, split_bin, split_cost)) = self.find_split(range.clone(), centroid_bounds)
+        else {
+            return;
+        };
+
+        let leaf_cost = count as f32;
+
+        if split_cost / bounds.surface_area() + Self::TRAVERSAL_COST >= leaf_cost {
+            return;
+        }
+
+        // partition the primitives in place, with the ones left of the split first
+        let mut middle = first;
+
+        for i in range {
+            let centroid = self.primitive_bounds(self.primitives[i]).centroid();
+
+            if Self::bin_index(centroid_bounds, axis, centroid) < split_bin {
+                self.primitives.swap(i, middle);
+                middle += 1;
+            }
+        }
+
+        let left_count = middle - first;
+
+        if left_count == 0 || left_count == count {
+            return;
+        }
+
+        let left_index = self.nodes.len();
+
+        self.nodes.push(BvhNode {
+            minimum: Vec3::ZERO,
+            first_index: first as u32,
+            maximum: Vec3::ZERO,
+            primitive_count: left_count as u32,
+        });
+        self.nodes.push(BvhNode {
+            minimum: Vec3::ZERO,
+            first_index: middle as u32,
+            maximum: Vec3::ZERO,
+            primitive_count: (count - left_count) as u32,
+        });
+
+        self.nodes[node_index].first_index = left_index as u32;
+        self.nodes[node_index].primitive_count = 0;
+
+        self.subdivide(left_index, depth + 1);
+        self.subdivide(left_index + 1, depth + 1);
+    }
+
+    /// Finds the cheapest split of the primitives according to the surface area heuristic,
+    /// returning its axis, the first bin on the right side, and its unnormalized cost.
+    fn find_split(
+        &self,
+        range: std::ops::Range<usize>,
+        centroid_bounds: Aabb,
+    ) -> Option<(usize, usize, f32)> {
+        let mut best: Option<(usize, usize, f32)> = None;
+
+        for axis in 0..3 {
+            if centroid_bounds.maximum[axis] <= centroid_bounds.minimum[axis] {
+                continue;
+            }
+
+            let mut bins = [Bin {
+                bounds: Aabb::EMPTY,
+                count: 0,
+            }; Self::BINS];
+
+            for &primitive in &self.primitives[range.clone()] {
+                let primitive_bounds = self.primitive_bounds(primitive);
+                let bin =
+                    &mut bins[Self::bin_index(centroid_bounds, axis, primitive_bounds.centroid())];
+
+                bin.bounds = bin.bounds.union(primitive_bounds);
+                bin.count += 1;
+            }
+
+            // sweep from the right to know the cost of everything past every split
+            let mut right_costs = [0.0; Self::BINS];
+            let mut right = Bin {
+                bounds: Aabb::EMPTY,
+                count: 0,
+            };
+
+            for i in (1..Self::BINS).rev() {
+                right.bounds = right.bounds.union(bins[i].bounds);
+                right.count += bins[i].count;
+                right_costs[i] = right.bounds.surface_area() * right.count as f32;
+            }
+
+            let mut left = Bin {
+                bounds: Aabb::EMPTY,
+                count: 0,
+            };
+
+            for split in 1..Self::BINS {
+                left.bounds = left.bounds.union(bins[split - 1].bounds);
+                left.count += bins[split - 1].count;
+
+                let cost = left.bounds.surface_area() * left.count as f32 + right_costs[split];
+
+                if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                    best = Some((axis, split, cost));
+                }
+            }
+        }
+
+        best
+    }
+
+    /// Recomputes the bounds of every node, bottom up, without changing the structure.
+    fn refit(&mut self) {
+        for node_index in (0..self.nodes.len()).rev() {
+            let node = self.nodes[node_index];
+            let first = node.first_index as usize;
+
+            let bounds = if node.primitive_count > 0 {
+                self.primitives[first..first + node.primitive_count as usize]
+                    .iter()
+                    .fold(Aabb::EMPTY, |bounds, &primitive| {
+                        bounds.union(self.primitive_bounds(primitive))
+                    })
+            } else if first != 0 {
+                Aabb::from_node(&self.nodes[first]).union(Aabb::from_node(&self.nodes[first + 1]))
+            } else {
+                continue;
+            };
+
+            self.nodes[node_index].minimum = bounds.minimum;
+            self.nodes[node_index].maximum = bounds.maximum;
+        }
+    }
+
+    fn primitive_bounds(&self, primitive: u32) -> Aabb {
+        if primitive & Self::TRIANGLE_BIT != 0 {
+            self.triangle_bounds[(primitive & !Self::TRIANGLE_BIT) as usize]
+        } else {
+            self.sphere_bounds[primitive as usize]
+        }
+    }
+
+    /// The bin a centroid falls into along the axis.
+    fn bin_index(centroid_bounds: Aabb, axis: usize, centroid: Vec3) -> usize {
+        let extent = centroid_bounds.maximum[axis] - centroid_bounds.minimum[axis];
+        let offset = (centroid[axis] - centroid_bounds.minimum[axis]) / extent;
+
+        ((offset * Self::BINS as f32) as usize).min(Self::BINS - 1)
+    }
+}
+
+impl Aabb {
+    /// Bounds containing nothing, which any point or other bounds can be added to.
+    const EMPTY: Self = Self {
+        minimum: Vec3::INFINITY,
+        maximum: Vec3::NEG_INFINITY,
+    };
+
+    fn from_sphere(sphere: &Sphere) -> Self {
+        let center = sphere.position.truncate();
+
+        Self {
+            minimum: center - Vec3::splat(sphere.radius),
+            maximum: center + Vec3::splat(sphere.radius),
+        }
+    }
+
+    fn from_triangle(triangle: &Triangle, vertices: &[Vertex]) -> Self {
+        triangle
+            .indices
+            .to_array()
+            .into_iter()
+            .fold(Self::EMPTY, |bounds, index| {
+                bounds.grow(vertices[index as usize].position)
+            })
+    }
+
+    fn from_node(node: &BvhNode) -> Self {
+        Self {
+            minimum: node.minimum,
+            maximum: node.maximum,
+        }
+    }
+
+    fn grow(self, point: Vec3) -> Self {
+        Self {
+            minimum: self.minimum.min(point),
+            maximum: self.maximum.max(point),
+        }
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self {
+            minimum: self.minimum.min(other.minimum),
+            maximum: self.maximum.max(other.maximum),
+        }
+    }
+
+    fn centroid(self) -> Vec3 {
+        (self.minimum + self.maximum) * 0.5
+    }
+
+    /// The surface area of the bounds, or zero if they are empty.
+    fn surface_area(self) -> f32 {
+        let extent = (self.maximum - self.minimum).max(Vec3::ZERO);
+
+        2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{uvec3, vec3};
+
+    use super::*;
+
+    /// A random point in the cube from -10 to 10, from a simple deterministic generator.
+    fn random_point(seed: &mut u32) -> Vec3 {
+        let mut next = || {
+            *seed ^= *seed << 13;
+            *seed ^= *seed >> 17;
+            *seed ^= *seed << 5;
+
+            (*seed as f32 / u32::MAX as f32) * 20.0 - 10.0
+        };
+
+        vec3(next(), next(), next())
+    }
+
+    fn random_scene(
+        sphere_count: u32,
+        triangle_count: u32,
+    ) -> (Vec<Sphere>, Vec<Vertex>, Vec<Triangle>) {
+        let mut seed = 0x2545f491;
+
+        let spheres = (0..sphere_count)
+            .map(|_| Sphere::new(random_point(&mut seed), 0.5, 0))
+            .collect();
+
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+
+        for i in 0..triangle_count {
+            let corner = random_point(&mut seed);
+
+            for offset in [Vec3::ZERO, Vec3::X, Vec3::Y] {
+                vertices.push(Vertex::new(corner + offset, Vec3::Z));
+            }
+
+            triangles.push(Triangle {
+                indices: uvec3(i * 3, i * 3 + 1, i * 3 + 2),
+                material_index: 0,
+            });
+        }
+
+        (spheres, vertices, triangles)
+    }
+
+    fn contains(outer: Aabb, inner: Aabb) -> bool {
+        outer.minimum.cmple(inner.minimum).all() && outer.maximum.cmpge(inner.maximum).all()
+    }
+
+    /// Walks the hierarchy, checking that the bounds of every node contain everything below it,
+    /// and returns how often every primitive is referenced along with the depth of the deepest
+    /// node.
+    fn check(bvh: &Bvh) -> (Vec<u32>, u32) {
+        let mut references = vec![0; bvh.sphere_bounds.len() + bvh.triangle_bounds.len()];
+        let mut max_depth = 0;
+        let mut stack = vec![(0, 0)];
+
+        while let Some((node_index, depth)) = stack.pop() {
+            let node = &bvh.nodes[node_index];
+            let bounds = Aabb::from_node(node);
+            let first = node.first_index as usize;
+
+            max_depth = max_depth.max(depth);
+
+            if node.primitive_count > 0 {
+                for &primitive in &bvh.primitives[first..first + node.primitive_count as usize] {
+                    assert!(contains(bounds, bvh.primitive_bounds(primitive)));
+
+                    let index = if primitive & Bvh::TRIANGLE_BIT != 0 {
+                        bvh.sphere_bounds.len() + (primitive & !Bvh::TRIANGLE_BIT) as usize
+                    } else {
+                        primitive as usize
+                    };
+
+                    references[index] += 1;
+                }
+            } else if first != 0 {
+                // children come after their parent
+                assert!(first > node_index);
+
+                for child in [first, first + 1] {
+                    assert!(contains(bounds, Aabb::from_node(&bvh.nodes[child])));
+                    stack.push((child, depth + 1));
+                }
+            }
+        }
+
+        (references, max_depth)
+    }
+
+    #[test]
+    fn every_primitive_is_referenced_once() {
+        let (spheres, vertices, triangles) = random_scene(300, 700);
+        let bvh = Bvh::new(&spheres, &vertices, &triangles);
+
+        let (references, _) = check(&bvh);
+
+        assert!(references.iter().all(|&count| count == 1));
+        assert_eq!(bvh.primitives().len(), 1000);
+        // the hierarchy actually split the primitives up
+        assert!(bvh.nodes().len() > 1);
+    }
+
+    #[test]
+    fn empty_hierarchies_have_an_empty_root() {
+        let bvh = Bvh::new(&[], &[], &[]);
+
+        assert_eq!(bvh.nodes().len(), 1);
+        assert_eq!(bvh.nodes()[0].primitive_count, 0);
+        assert_eq!(bvh.nodes()[0].first_index, 0);
+        assert!(bvh.primitives().is_empty());
+    }
+
+    #[test]
+    fn depth_stays_within_the_limit() {
+        // every sphere twice as far out and as large as the last one, which splits off one sphere
+        // per level until the limit stops it
+        let spread: Vec<Sphere> = (-60..60)
+            .map(|i| Sphere::new(Vec3::X * 2f32.powi(i), 2f32.powi(i) * 0.01, 0))
+            .collect();
+
+        let (references, max_depth) = check(&Bvh::new(&spread, &[], &[]));
+
+        assert!(references.iter().all(|&count| count == 1));
+        assert_eq!(max_depth, Bvh::MAX_DEPTH);
+
+        // many coincident spheres, next to a few which can be split off
+        let coincident: Vec<Sphere> = (0..1000)
+            .map(|i| match i % 100 {
+                0 => Sphere::new(Vec3::splat(i as f32), 0.5, 0),
+                _ => Sphere::new(Vec3::ONE, 0.5, 0),
+            })
+            .collect();
+
+        let (references, max_depth) = check(&Bvh::new(&coincident, &[], &[]));
+
+        assert!(references.iter().all(|&count| count == 1));
+        assert!(max_depth <= Bvh::MAX_DEPTH);
+    }
+
+    #[test]
+    fn refitting_contains_moved_spheres() {
+        let (mut spheres, vertices, triangles) = random_scene(200, 200);
+        let mut bvh = Bvh::new(&spheres, &vertices, &triangles);
+        let node_count = bvh.nodes().len();
+
+        assert!(!bvh.update_spheres(&spheres));
+
+        let mut seed = 0x9e3779b9;
+
+        for sphere in &mut spheres {
+            let position = sphere.position.truncate() + random_point(&mut seed);
+            *sphere = Sphere::new(position, sphere.radius * 2.0, 0);
+        }
+
+        assert!(bvh.update_spheres(&spheres));
+        // moving spheres keeps the structure
+        assert_eq!(bvh.nodes().len(), node_count);
+
+        let (references, _) = check(&bvh);
+
+        assert!(references.iter().all(|&count| count == 1));
+
+        for sphere in &spheres {
+            let root = Aabb::from_node(&bvh.nodes()[0]);
+            assert!(contains(root, Aabb::from_sphere(sphere)));
+        }
+    }
+
+    #[test]
+    fn refitting_contains_moved_vertices() {
+        let (spheres, mut vertices, triangles) = random_scene(50, 300);
+        let mut bvh = Bvh::new(&spheres, &vertices, &triangles);
+        let primitives = bvh.primitives().to_vec();
+
+        // scale the first half of the triangles away from the origin, as the gizmo does
+        for vertex in &mut vertices[..450] {
+            vertex.position *= vec3(3.0, 0.5, 2.0);
+        }
+
+        bvh.update_vertices(&vertices, &triangles);
+
+        // refitting keeps the structure
+        assert_eq!(bvh.primitives(), primitives);
+
+        let (references, _) = check(&bvh);
+
+        assert!(references.iter().all(|&count| count == 1));
+
+        for (i, triangle) in triangles.iter().enumerate() {
+            assert_eq!(
+                bvh.primitive_bounds(i as u32 | Bvh::TRIANGLE_BIT),
+                Aabb::from_triangle(triangle, &vertices)
+            );
+        }
+    }
+}
@@ -1,8 +1,78 @@
 use egui::{InputState, Modifiers};
-use glam::{Mat4, Vec3};
+use std::f32::consts::PI;
 
-/// An fps camera in 3d space, with up always being in the positive Y direction.
+use glam::{vec2, vec3, Mat4, Vec2, Vec3, Vec4Swizzles};
+use serde::{Deserialize, Serialize};
+
+/// How the camera maps the directions it sees onto the image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Projection {
+    /// A regular pinhole or thin lens camera, which keeps straight lines straight.
+    #[default]
+    Perspective,
+    /// Parallel rays along the view direction, without any perspective.
+    Orthographic,
+    /// An equidistant fisheye, where the angle away from the view direction grows linearly
+    /// towards the edges of the image, covering up to the whole sphere around the camera.
+    Fisheye,
+    /// A full 360° by 180° panorama in the equirectangular layout used by VR viewers, ignoring the
+    /// field of view. It is meant for images twice as wide as they are tall.
+    Equirectangular,
+}
+
+/// The side of the image which the field of view spans.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FovAxis {
+    #[default]
+    Vertical,
+    Horizontal,
+}
+
+/// A ray leaving the camera in view space, where the camera looks down the negative Z axis.
 #[derive(Debug, Clone, Copy)]
+pub struct CameraRay {
+    /// Where the ray starts, on the near clip plane.
+    pub origin: Vec3,
+    pub direction: Vec3,
+    /// How far the ray travels before reaching the far clip plane.
+    pub max_distance: f32,
+}
+
+impl Projection {
+    /// Every projection, in the order they are listed in the ui.
+    pub const ALL: [Self; 4] = [
+        Self::Perspective,
+        Self::Orthographic,
+        Self::Fisheye,
+        Self::Equirectangular,
+    ];
+
+    /// The name shown in the ui.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Perspective => "perspective",
+            Self::Orthographic => "orthographic",
+            Self::Fisheye => "fisheye",
+            Self::Equirectangular => "equirectangular 360°",
+        }
+    }
+}
+
+/// How the camera is moved around with the mouse and keyboard.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CameraMode {
+    /// Flying freely with WASD, looking around by dragging.
+    #[default]
+    Fly,
+    /// Circling around the pivot by dragging, panning by dragging with the middle button or
+    /// with shift held, and moving towards the pivot by scrolling.
+    Orbit,
+}
+
+/// A camera in 3d space, with up always being in the positive Y direction, either flown around
+/// like in an fps or orbiting around a pivot point.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Camera {
     /// The position.
     pub eye: glam::Vec3,
@@ -12,10 +82,46 @@ pub struct Camera {
     /// The euler angle defining rotation around the x axis.
     pub pitch: f32,
 
+    /// How the camera is moved around.
+    pub mode: CameraMode,
+    /// The point an orbiting camera circles around, which it is always looking at.
+    pub pivot: Vec3,
+
+    /// How directions are mapped onto the image.
+    pub projection: Projection,
+    /// The angle the image spans along `fov_axis` in degrees, for the perspective and fisheye
+    /// projections.
+    pub fov: f32,
+    /// The side of the image which `fov` spans.
+    pub fov_axis: FovAxis,
+    /// The height of the image in world units, for the orthographic projection.
+    pub orthographic_height: f32,
+    /// The distance in front of the camera below which nothing is seen.
+    pub near: f32,
+    /// The distance in front of the camera beyond which nothing is seen.
+    pub far: f32,
+
+    /// The radius of the lens, where anything but zero blurs everything away from the focus
+    /// distance. A pinhole camera, with everything in focus, has an aperture of zero. Only the
+    /// perspective and orthographic projections have a lens.
+    pub aperture_radius: f32,
+    /// The distance in front of the camera which is in perfect focus.
+    pub focus_distance: f32,
+    /// The number of blades shaping the aperture into a polygon, which shows in the shape of
+    /// out of focus highlights, or zero for a circular aperture.
+    pub aperture_blades: u32,
+
     /// Has the camera moved since the last frame.
+    #[serde(skip)]
     pub moved: bool,
 }
 
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new_facing(Vec3::new(0.0, 1.0, 4.0), Vec3::NEG_Z)
+    }
+}
+
 impl Camera {
     pub fn new_facing(position: Vec3, forward: Vec3) -> Self {
         let Vec3 { x, y, z } = forward.normalize();
@@ -27,6 +133,17 @@ impl Camera {
             eye: position,
             yaw,
             pitch,
+            mode: CameraMode::Fly,
+            pivot: position + forward.normalize() * 4.0,
+            projection: Projection::Perspective,
+            fov: 45.0,
+            fov_axis: FovAxis::Vertical,
+            orthographic_height: 4.0,
+            near: 0.1,
+            far: 1000.0,
+            aperture_radius: 0.0,
+            focus_distance: 4.0,
+            aperture_blades: 0,
             moved: false,
         }
     }
@@ -40,17 +157,262 @@ impl Camera {
         .normalize()
     }
 
+    /// Switches how the camera is moved around. An orbiting camera starts circling around the
+    /// point it is focused on.
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        if mode == CameraMode::Orbit && self.mode != CameraMode::Orbit {
+            self.pivot = self.eye + self.forward() * self.focus_distance.max(0.01);
+        }
+
+        self.mode = mode;
+    }
+
+    /// Moves an orbiting camera to look at a sphere from far enough away to fit it in the image,
+    /// and focuses on its center.
+    pub fn frame(&mut self, center: Vec3, radius: f32, aspect_ratio: f32) {
+        // a bit of room around the edges
+        let radius = radius.max(1e-3) * 1.1;
+
+        let distance = match self.projection {
+            Projection::Orthographic => {
+                self.orthographic_height = 2.0 * radius * (1.0 / aspect_ratio).max(1.0);
+
+                2.0 * radius + self.near
+            }
+            Projection::Equirectangular => 2.0 * radius,
+            Projection::Perspective | Projection::Fisheye => {
+                let half_fov = self.field_of_view(aspect_ratio).min_element() * 0.5;
+
+                radius / half_fov.min(PI / 3.0).sin()
+            }
+        };
+
+        self.set_mode(CameraMode::Orbit);
+        self.pivot = center;
+        self.focus_distance = distance;
+        self.eye = center - self.forward() * distance;
+        self.moved = true;
+    }
+
+    /// The distance from an orbiting camera to its pivot.
+    fn orbit_distance(&self) -> f32 {
+        self.eye.distance(self.pivot)
+    }
+
+    /// Turns the camera to look at a point, keeping its position.
+    pub fn look_at(&mut self, target: Vec3) {
+        let Vec3 { x, y, z } = (target - self.eye).normalize_or(self.forward());
+
+        self.pitch = y.asin().to_degrees();
+        self.yaw = f32::atan2(z, x).to_degrees();
+        self.moved = true;
+    }
+
+    /// The projection matrix of the perspective and orthographic projections. The others can't be
+    /// expressed as a matrix, and get the perspective one.
     pub fn calculate_projection(&self, aspect_ratio: f32) -> Mat4 {
-        Mat4::perspective_rh(45.0f32.to_radians(), aspect_ratio, 0.1, 1000.0)
+        let (near, far) = self.clip_planes();
+
+        match self.projection {
+            Projection::Orthographic => {
+                let half_height = self.orthographic_height * 0.5;
+                let half_width = half_height * aspect_ratio;
+
+                Mat4::orthographic_rh(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    near,
+                    far,
+                )
+            }
+            _ => {
+                let fov_y = self.field_of_view(aspect_ratio).y;
+
+                Mat4::perspective_rh(fov_y, aspect_ratio, near, far)
+            }
+        }
     }
 
     pub fn calculate_view(&self) -> Mat4 {
         Mat4::look_to_rh(self.eye, self.forward().normalize(), Vec3::Y)
     }
 
+    /// The near and far clip distances, kept apart from each other and from the camera.
+    pub fn clip_planes(&self) -> (f32, f32) {
+        let near = self.near.max(1e-4);
+
+        (near, self.far.max(near * 2.0))
+    }
+
+    /// The full horizontal and vertical angles the image spans in radians, for the perspective
+    /// and fisheye projections.
+    pub fn field_of_view(&self, aspect_ratio: f32) -> Vec2 {
+        let fov = self.fov.clamp(0.1, self.max_fov()).to_radians();
+
+        // the other side of a fisheye grows linearly, while a perspective grows with the tangent
+        let scale_side = |fov: f32, scale: f32| match self.projection {
+            Projection::Fisheye => fov * scale,
+            _ => 2.0 * ((fov * 0.5).tan() * scale).atan(),
+        };
+
+        match self.fov_axis {
+            FovAxis::Vertical => vec2(scale_side(fov, aspect_ratio), fov),
+            FovAxis::Horizontal => vec2(fov, scale_side(fov, 1.0 / aspect_ratio)),
+        }
+    }
+
+    /// The largest field of view the projection supports, in degrees.
+    pub fn max_fov(&self) -> f32 {
+        match self.projection {
+            Projection::Fisheye => 360.0,
+            _ => 179.0,
+        }
+    }
+
+    /// The focal length in millimeters of a lens giving the same field of view on a 36mm by 24mm
+    /// full frame sensor.
+    pub fn focal_length(&self) -> f32 {
+        let sensor_size = self.sensor_size();
+
+        sensor_size / (2.0 * (self.fov.to_radians() * 0.5).tan())
+    }
+
+    /// Sets the field of view to that of a lens with the given focal length in millimeters on a
+    /// full frame sensor.
+    pub fn set_focal_length(&mut self, focal_length: f32) {
+        let sensor_size = self.sensor_size();
+
+        self.fov = (2.0 * (sensor_size / (2.0 * focal_length.max(1e-3))).atan()).to_degrees();
+    }
+
+    /// The size of a full frame sensor along `fov_axis`, in millimeters.
+    fn sensor_size(&self) -> f32 {
+        match self.fov_axis {
+            FovAxis::Vertical => 24.0,
+            FovAxis::Horizontal => 36.0,
+        }
+    }
+
+    /// The ray through the center of the lens at the given normalized device coordinate in view
+    /// space, as in the shader's `camera_ray`, or `None` if the coordinate is outside of the
+    /// image circle of a fisheye.
+    pub fn view_ray(&self, coord: Vec2, aspect_ratio: f32) -> Option<CameraRay> {
+        let direction = match self.projection {
+            Projection::Perspective | Projection::Orthographic => {
+                let inverse_projection = self.calculate_projection(aspect_ratio).inverse();
+
+                let near_point = inverse_projection * coord.extend(0.0).extend(1.0);
+                let far_point = inverse_projection * coord.extend(1.0).extend(1.0);
+
+                let origin = near_point.xyz() / near_point.w;
+                let to_far = far_point.xyz() / far_point.w - origin;
+
+                return Some(CameraRay {
+                    origin,
+                    direction: to_far.normalize(),
+                    max_distance: to_far.length(),
+                });
+            }
+            Projection::Fisheye => {
+                let angles = coord * self.field_of_view(aspect_ratio) * 0.5;
+                let theta = angles.length();
+
+                if theta > PI {
+                    return None;
+                }
+
+                let phi = angles.y.atan2(angles.x);
+
+                vec3(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    -theta.cos(),
+                )
+            }
+            Projection::Equirectangular => {
+                let longitude = coord.x * PI;
+                let latitude = coord.y * PI * 0.5;
+
+                vec3(
+                    longitude.sin() * latitude.cos(),
+                    latitude.sin(),
+                    -longitude.cos() * latitude.cos(),
+                )
+            }
+        };
+
+        let (near, far) = self.clip_planes();
+
+        Some(CameraRay {
+            origin: direction * near,
+            direction,
+            max_distance: far - near,
+        })
+    }
+
+    /// The ray through the center of the lens at the given normalized device coordinate in world
+    /// space, if there is one.
+    pub fn world_ray(&self, coord: Vec2, aspect_ratio: f32) -> Option<CameraRay> {
+        let inverse_view = self.calculate_view().inverse();
+
+        self.view_ray(coord, aspect_ratio).map(|ray| CameraRay {
+            origin: inverse_view.transform_point3(ray.origin),
+            direction: inverse_view.transform_vector3(ray.direction),
+            ..ray
+        })
+    }
+
+    /// The normalized device coordinate a point in world space is seen at, the inverse of
+    /// [`Camera::world_ray`], or `None` if the point is behind the near plane of a perspective or
+    /// orthographic camera, or right at the camera.
+    pub fn project_point(&self, point: Vec3, aspect_ratio: f32) -> Option<Vec2> {
+        let view_point = self.calculate_view().transform_point3(point);
+
+        match self.projection {
+            Projection::Perspective | Projection::Orthographic => {
+                if -view_point.z < self.clip_planes().0 {
+                    return None;
+                }
+
+                let clip = self.calculate_projection(aspect_ratio) * view_point.extend(1.0);
+
+                Some(clip.xy() / clip.w)
+            }
+            Projection::Fisheye => {
+                let direction = view_point.try_normalize()?;
+
+                let theta = direction.truncate().length().atan2(-direction.z);
+                let phi = direction.y.atan2(direction.x);
+
+                Some(vec2(phi.cos(), phi.sin()) * theta / (self.field_of_view(aspect_ratio) * 0.5))
+            }
+            Projection::Equirectangular => {
+                let direction = view_point.try_normalize()?;
+
+                let longitude = direction.x.atan2(-direction.z);
+                let latitude = direction.y.clamp(-1.0, 1.0).asin();
+
+                Some(vec2(longitude / PI, latitude / (PI * 0.5)))
+            }
+        }
+    }
+
+    /// Focuses the lens on the plane in front of the camera through the given point.
+    pub fn focus_on(&mut self, point: Vec3) {
+        self.focus_distance = (point - self.eye).dot(self.forward());
+        self.moved = true;
+    }
+
     pub fn handle_keyboard(&mut self, input: &InputState, dt: f32) {
         use egui::Key;
 
+        // an orbiting camera is only moved with the mouse
+        if self.mode == CameraMode::Orbit {
+            return;
+        }
+
         let forward = self.forward();
         let right = forward.cross(Vec3::Y);
 
@@ -93,6 +455,11 @@ impl Camera {
     }
 
     pub fn handle_mouse(&mut self, input: &InputState, delta: (f64, f64)) {
+        if self.mode == CameraMode::Orbit {
+            self.handle_orbit_mouse(input, delta);
+            return;
+        }
+
         if input.pointer.primary_down() {
             let mouse_sensitivity = 0.1;
 
@@ -109,4 +476,56 @@ impl Camera {
             }
         }
     }
+
+    fn handle_orbit_mouse(&mut self, input: &InputState, delta: (f64, f64)) {
+        let dx = delta.0 as f32;
+        let dy = delta.1 as f32;
+
+        if dx == 0.0 && dy == 0.0 {
+            return;
+        }
+
+        let distance = self.orbit_distance();
+        let panning = input.pointer.middle_down()
+            || (input.pointer.primary_down() && input.modifiers.contains(Modifiers::SHIFT));
+
+        if panning {
+            let forward = self.forward();
+            let right = forward.cross(Vec3::Y).normalize();
+            let up = right.cross(forward);
+
+            // the pivot follows the cursor at about the same speed at any distance
+            let pan_sensitivity = 0.002 * distance;
+            let offset = (up * dy - right * dx) * pan_sensitivity;
+
+            self.eye += offset;
+            self.pivot += offset;
+        } else if input.pointer.primary_down() {
+            let mouse_sensitivity = 0.2;
+
+            self.yaw += dx * mouse_sensitivity;
+            self.pitch = (self.pitch - dy * mouse_sensitivity).clamp(-89.0, 89.0);
+
+            self.eye = self.pivot - self.forward() * distance;
+        } else {
+            return;
+        }
+
+        self.moved = true;
+    }
+
+    /// Moves an orbiting camera towards or away from its pivot by the amount scrolled, in points.
+    pub fn handle_scroll(&mut self, scroll: f32) {
+        if self.mode != CameraMode::Orbit || scroll == 0.0 {
+            return;
+        }
+
+        // every notch of the wheel moves the same fraction of the way
+        let scale = (-scroll * 0.002).exp();
+        let distance = (self.orbit_distance() * scale).max(0.01);
+
+        self.eye = self.pivot - self.forward() * distance;
+        self.orthographic_height *= scale;
+        self.moved = true;
+    }
 }
@@ -0,0 +1,273 @@
+use std::{
+    f32::consts::TAU,
+    ops::{Add, Mul},
+};
+
+use glam::{vec3, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Camera;
+
+/// The placement and field of view of the camera at a point in time along a [`CameraPath`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraKeyframe {
+    /// The time of the keyframe, in seconds from the start of the path.
+    pub time: f32,
+    pub eye: Vec3,
+    /// The rotation around the y axis in degrees, which isn't wrapped, so that a path can turn
+    /// around more than once.
+    pub yaw: f32,
+    pub pitch: f32,
+    /// The field of view in degrees, as in [`Camera::fov`].
+    pub fov: f32,
+}
+
+/// A camera moving smoothly through a series of keyframes, along a Catmull-Rom spline.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CameraPath {
+    /// The keyframes, sorted by time.
+    keyframes: Vec<CameraKeyframe>,
+}
+
+/// A camera circling around a target point at a constant speed, always looking at it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Turntable {
+    /// The point the camera circles around and looks at.
+    pub target: Vec3,
+    /// The horizontal distance from the target to the camera.
+    pub radius: f32,
+    /// The height of the camera above the target.
+    pub height: f32,
+    /// The angle around the vertical axis the camera starts at, in degrees, where zero is along
+    /// the positive X axis.
+    pub start_angle: f32,
+    /// The time a full turn takes, in seconds.
+    pub duration: f32,
+}
+
+/// The way the camera moves over the course of an image sequence.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CameraAnimation {
+    Path(CameraPath),
+    Turntable(Turntable),
+}
+
+impl CameraKeyframe {
+    /// A keyframe holding the current placement of the camera.
+    pub fn from_camera(camera: &Camera, time: f32) -> Self {
+        Self {
+            time,
+            eye: camera.eye,
+            yaw: camera.yaw,
+            pitch: camera.pitch,
+            fov: camera.fov,
+        }
+    }
+
+    /// The yaw, pitch and field of view, which are interpolated together.
+    fn angles(&self) -> Vec3 {
+        vec3(self.yaw, self.pitch, self.fov)
+    }
+}
+
+impl CameraPath {
+    /// Creates a path through the given keyframes, in any order.
+    pub fn new(mut keyframes: Vec<CameraKeyframe>) -> Self {
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        Self { keyframes }
+    }
+
+    pub fn keyframes(&self) -> &[CameraKeyframe] {
+        &self.keyframes
+    }
+
+    /// Appends a keyframe after the last one. Its yaw is turned by whole turns to be as close as
+    /// possible to the previous keyframe, so that the camera takes the short way around.
+    pub fn push(&mut self, mut keyframe: CameraKeyframe) {
+        if let Some(last) = self.keyframes.last() {
+            keyframe.time = keyframe.time.max(last.time);
+            keyframe.yaw += ((last.yaw - keyframe.yaw) / 360.0).round() * 360.0;
+        }
+
+        self.keyframes.push(keyframe);
+    }
+
+    /// The time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// The interpolated keyframe at the given time, which holds the first and last keyframes
+    /// before and after the path. Returns `None` if the path has no keyframes.
+    pub fn sample(&self, time: f32) -> Option<CameraKeyframe> {
+        let keyframes = &self.keyframes;
+
+        let (first, last) = (keyframes.first()?, keyframes.last()?);
+
+        if time <= first.time {
+            return Some(CameraKeyframe { time, ..*first });
+        }
+
+        if time >= last.time {
+            return Some(CameraKeyframe { time, ..*last });
+        }
+
+        // the segment between the keyframes `i` and `i + 1`, with a nonzero length since `time`
+        // lies strictly inside it
+        let i = keyframes.partition_point(|keyframe| keyframe.time <= time) - 1;
+        let (start, end) = (&keyframes[i], &keyframes[i + 1]);
+
+        let length = end.time - start.time;
+        let t = (time - start.time) / length;
+
+        let eye = hermite(
+            start.eye,
+            end.eye,
+            self.tangent(i, |keyframe| keyframe.eye) * length,
+            self.tangent(i + 1, |keyframe| keyframe.eye) * length,
+            t,
+        );
+
+        let angles = hermite(
+            start.angles(),
+            end.angles(),
+            self.tangent(i, CameraKeyframe::angles) * length,
+            self.tangent(i + 1, CameraKeyframe::angles) * length,
+            t,
+        );
+
+        Some(CameraKeyframe {
+            time,
+            eye,
+            yaw: angles.x,
+            pitch: angles.y,
+            fov: angles.z,
+        })
+    }
+
+    /// The rate of change of a value at a keyframe, from its neighbours on either side, or just
+    /// the one neighbour at the ends of the path.
+    fn tangent(&self, index: usize, value: impl Fn(&CameraKeyframe) -> Vec3) -> Vec3 {
+        let previous = &self.keyframes[index.saturating_sub(1)];
+        let next = &self.keyframes[(index + 1).min(self.keyframes.len() - 1)];
+
+        let duration = next.time - previous.time;
+
+        if duration > 0.0 {
+            (value(next) - value(previous)) / duration
+        } else {
+            Vec3::ZERO
+        }
+    }
+}
+
+impl Turntable {
+    /// A turntable around the target starting at the current position of the camera.
+    pub fn from_camera(camera: &Camera, target: Vec3) -> Self {
+        let offset = camera.eye - target;
+
+        Self {
+            target,
+            radius: offset.x.hypot(offset.z),
+            height: offset.y,
+            start_angle: offset.z.atan2(offset.x).to_degrees(),
+            ..Default::default()
+        }
+    }
+
+    /// The position of the camera at the given time.
+    pub fn eye(&self, time: f32) -> Vec3 {
+        let turns = time / self.duration.max(1e-3);
+        let angle = self.start_angle.to_radians() + turns * TAU;
+
+        self.target
+            + vec3(
+                self.radius * angle.cos(),
+                self.height,
+                self.radius * angle.sin(),
+            )
+    }
+}
+
+impl Default for Turntable {
+    fn default() -> Self {
+        Self {
+            target: Vec3::ZERO,
+            radius: 4.0,
+            height: 1.0,
+            start_angle: 90.0,
+            duration: 10.0,
+        }
+    }
+}
+
+impl CameraAnimation {
+    /// The time the animation takes, in seconds.
+    pub fn duration(&self) -> f32 {
+        match self {
+            Self::Path(path) => path.duration(),
+            Self::Turntable(turntable) => turntable.duration.max(0.0),
+        }
+    }
+
+    /// Whether the animation ends where it started, so it can be played over and over.
+    pub fn loops(&self) -> bool {
+        matches!(self, Self::Turntable(_))
+    }
+
+    /// Moves the camera to where it is at the given time, keeping its other settings.
+    pub fn apply(&self, camera: &mut Camera, time: f32) {
+        match self {
+            Self::Path(path) => {
+                let Some(keyframe) = path.sample(time) else {
+                    return;
+                };
+
+                camera.eye = keyframe.eye;
+                camera.yaw = keyframe.yaw;
+                camera.pitch = keyframe.pitch;
+                camera.fov = keyframe.fov;
+            }
+            Self::Turntable(turntable) => {
+                camera.eye = turntable.eye(time);
+                camera.look_at(turntable.target);
+            }
+        }
+
+        camera.moved = true;
+    }
+
+    /// The time of a frame when the animation is rendered as a sequence of `frame_count` frames,
+    /// spread evenly from its start to its end. The end of a looping animation is left out, as it
+    /// would repeat the first frame.
+    pub fn frame_time(&self, frame: u32, frame_count: u32) -> f32 {
+        let intervals = if self.loops() {
+            frame_count
+        } else {
+            frame_count.saturating_sub(1)
+        };
+
+        if intervals == 0 {
+            return 0.0;
+        }
+
+        self.duration() * frame as f32 / intervals as f32
+    }
+}
+
+/// A cubic hermite spline between two values with the given tangents, scaled to the length of
+/// the segment.
+fn hermite<T>(start: T, end: T, start_tangent: T, end_tangent: T, t: f32) -> T
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    start * (2.0 * t3 - 3.0 * t2 + 1.0)
+        + start_tangent * (t3 - 2.0 * t2 + t)
+        + end * (-2.0 * t3 + 3.0 * t2)
+        + end_tangent * (t3 - t2)
+}
@@ -0,0 +1,174 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+
+use crate::{
+    cpu_tracer::CpuRenderer,
+    pixel_filter::PixelFilter,
+    renderer::{render_to_image, RenderSettings, Renderer},
+    scene_file::SceneFile,
+};
+
+/// A gpu path tracer.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// What to do, opens the interactive viewer if omitted.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Open a scene in the interactive viewer.
+    View(ViewArgs),
+    /// Render a scene to an image without opening a window.
+    Render(RenderArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ViewArgs {
+    /// The scene file to open.
+    pub scene: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct RenderArgs {
+    /// The scene file to render.
+    pub scene: PathBuf,
+
+    /// The width of the image in pixels.
+    #[arg(long, default_value_t = 1920, value_parser = clap::value_parser!(u32).range(1..))]
+    pub width: u32,
+    /// The height of the image in pixels.
+    #[arg(long, default_value_t = 1080, value_parser = clap::value_parser!(u32).range(1..))]
+    pub height: u32,
+    /// The number of samples accumulated for every pixel, rounded up to a whole number of frames.
+    #[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u32).range(1..))]
+    pub spp: u32,
+    /// The number of jittered samples traced for every pixel in each frame.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub samples_per_frame: u32,
+    /// The filter weighing the samples of every pixel.
+    #[arg(long, value_enum, default_value_t = PixelFilter::Box)]
+    pub filter: PixelFilter,
+    /// Trace the image in square tiles of this many pixels, one after another.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub tile_size: Option<u32>,
+
+    /// The image to write, either a png or an exr depending on the extension.
+    #[arg(short, long, default_value = "render.png")]
+    pub output: PathBuf,
+    /// Render a sequence of this many images along the camera animation of the scene, each with
+    /// its frame number appended to the output file name.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub frames: Option<u32>,
+
+    /// Trace on the CPU instead of the GPU, for machines without a usable graphics adapter.
+    #[arg(long)]
+    pub cpu: bool,
+}
+
+impl RenderArgs {
+    /// Renders the scene headlessly, accumulating `spp` samples before writing the image, or
+    /// every image of the sequence.
+    pub fn run(&self) -> Result<()> {
+        let file = SceneFile::load(&self.scene)?;
+
+        let settings = RenderSettings {
+            width: self.width,
+            height: self.height,
+            samples_per_pixel: self.spp,
+            samples_per_frame: self.samples_per_frame,
+            pixel_filter: self.filter,
+            sky_color: file.sky_color,
+            environment: file.environment()?,
+            procedural_sky: file.procedural_sky,
+            tile_size: self.tile_size,
+            tone_mapping: file.tone_mapping,
+        };
+
+        if let Some(frame_count) = self.frames {
+            return self.render_sequence(&file, settings, frame_count);
+        }
+
+        let image = if self.cpu {
+            CpuRenderer::new(settings).render(&file.scene(), &file.camera)?
+        } else {
+            render_to_image(&file.scene(), &file.camera, &settings)?
+        };
+
+        image.save_tone_mapped(&self.output, &file.tone_mapping)?;
+
+        log::info!("saved render to {}", self.output.display());
+
+        Ok(())
+    }
+
+    /// Renders the frames of the scene's camera animation one after another, spread evenly over
+    /// its duration, reusing the renderer between them.
+    fn render_sequence(
+        &self,
+        file: &SceneFile,
+        settings: RenderSettings,
+        frame_count: u32,
+    ) -> Result<()> {
+        let animation = file
+            .animation
+            .as_ref()
+            .context("the scene has no camera animation to render a sequence along")?;
+
+        let scene = file.scene();
+
+        // sequences usually go into a directory of their own
+        if let Some(directory) = self.output.parent() {
+            fs::create_dir_all(directory)
+                .with_context(|| format!("failed to create {}", directory.display()))?;
+        }
+
+        let mut gpu_renderer = if self.cpu {
+            None
+        } else {
+            Some(pollster::block_on(Renderer::new(settings.clone()))?)
+        };
+        let cpu_renderer = CpuRenderer::new(settings);
+
+        for frame in 0..frame_count {
+            let mut camera = file.camera;
+            animation.apply(&mut camera, animation.frame_time(frame, frame_count));
+
+            let image = match &mut gpu_renderer {
+                Some(renderer) => renderer.render(&scene, &camera)?,
+                None => cpu_renderer.render(&scene, &camera)?,
+            };
+
+            let path = sequence_path(&self.output, frame);
+            image.save_tone_mapped(&path, &file.tone_mapping)?;
+
+            log::info!(
+                "saved frame {}/{frame_count} to {}",
+                frame + 1,
+                path.display()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// The path of a frame in an image sequence, with the zero padded frame number appended to the
+/// file name, such as `render_0007.png`.
+fn sequence_path(output: &Path, frame: u32) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+
+    let file_name = match output.extension() {
+        Some(extension) => format!("{stem}_{frame:04}.{}", extension.to_string_lossy()),
+        None => format!("{stem}_{frame:04}"),
+    };
+
+    output.with_file_name(file_name)
+}
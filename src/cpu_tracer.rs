@@ -0,0 +1,1491 @@
+use std::{
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use anyhow::Result;
+use glam::{vec2, vec3, Mat3, Mat4, Vec2, Vec3, Vec3Swizzles, Vec4Swizzles};
+
+use crate::{
+    bvh::{Bvh, BvhNode},
+    camera::{Camera, CameraRay, Projection},
+    environment::Environment,
+    export::RadianceImage,
+    renderer::RenderSettings,
+    scene::{Material, Scene, SceneObject, Sphere, Triangle, Vertex},
+    sky::ProceduralSky,
+};
+
+/// A path tracer running on the CPU, following the same rules as `shader.wgsl`.
+///
+/// It is much slower than the [`Renderer`](crate::Renderer), but serves as a ground truth to
+/// compare the GPU output against, and works on machines without a usable graphics adapter.
+#[derive(Debug, Clone)]
+pub struct CpuRenderer {
+    /// The settings used for every render.
+    settings: RenderSettings,
+    /// The number of worker threads tiles are distributed over.
+    threads: usize,
+}
+
+/// The per render state shared by every pixel, mirroring `RenderUniform` in the shader.
+struct TraceContext<'a> {
+    scene: &'a Scene,
+    /// The vertices of every mesh, as uploaded to the GPU.
+    vertices: Vec<Vertex>,
+    /// The triangles of every mesh, as uploaded to the GPU.
+    triangles: Vec<Triangle>,
+    /// The hierarchy over every sphere and triangle, as uploaded to the GPU.
+    bvh: Bvh,
+    /// The indices of the emissive spheres, as uploaded to the GPU.
+    lights: Vec<u32>,
+    /// The camera rays are traced from.
+    camera: Camera,
+    aspect_ratio: f32,
+    inverse_view: Mat4,
+    sky_color: Vec3,
+    /// The environment map lighting the scene in place of `sky_color`, if any.
+    environment: Option<&'a Environment>,
+    /// The procedural sky lighting the scene in place of `sky_color`, if any. An environment map
+    /// takes precedence over it.
+    procedural_sky: Option<ProceduralSky>,
+}
+
+/// A square block of pixels, rendered as a whole by one thread.
+#[derive(Debug, Clone, Copy)]
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+/// Converts the luminance of the sky model, in kilocandela per square meter, into scene radiance,
+/// as in the shader.
+const SKY_LUMINANCE_SCALE: f32 = 0.04;
+/// The illuminance of the sun above the atmosphere, in kilolux.
+const SUN_ILLUMINANCE: f32 = 128.0;
+
+/// The squared roughness below which surfaces are too smooth to sample reliably, as in the shader.
+const MIN_ALPHA: f32 = 0.001;
+
+struct Ray {
+    origin: Vec3,
+    direction: Vec3,
+}
+
+struct HitPayload {
+    /// The distance along the ray to the hit.
+    hit_distance: f32,
+    position: Vec3,
+    /// Always facing against the ray.
+    normal: Vec3,
+    material_index: u32,
+    /// Whether the ray hit the outside of the surface, rather than leaving it from the inside.
+    front_face: bool,
+    /// The index of the sphere that was hit, or `None` for triangles.
+    sphere_index: Option<usize>,
+    /// The index of the triangle that was hit, or `None` for spheres.
+    triangle_index: Option<usize>,
+}
+
+struct BsdfSample {
+    direction: Vec3,
+    /// The bsdf times the cosine term divided by the probability of the direction, or zero if the
+    /// path was absorbed.
+    weight: Vec3,
+    /// The probability of the direction for the lobes lights are sampled for, or zero otherwise.
+    pdf: f32,
+}
+
+struct LightSample {
+    direction: Vec3,
+    /// The light arriving from the direction, if nothing is in the way.
+    radiance: Vec3,
+    /// The probability of the direction for this light alone, or zero if it can't be sampled.
+    pdf: f32,
+}
+
+struct BrdfEvaluation {
+    /// The brdf times the cosine term.
+    value: Vec3,
+    /// The probability of [`sample_brdf`] choosing the direction, or zero if it is below the
+    /// surface.
+    pdf: f32,
+}
+
+impl CpuRenderer {
+    /// The width and height of the tiles the image is split into.
+    const TILE_SIZE: u32 = 16;
+    /// The number of times a ray can bounce, the same as in `per_pixel`.
+    const BOUNCES: u32 = 5;
+
+    /// Creates a new [`CpuRenderer`], using every available thread.
+    pub fn new(settings: RenderSettings) -> Self {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+
+        Self { settings, threads }
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    /// Replaces the settings used for the following renders.
+    pub fn set_settings(&mut self, settings: RenderSettings) {
+        self.settings = settings;
+    }
+
+    /// Sets the number of worker threads, clamped to at least one.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    /// Renders the scene as seen from the camera, blocking until all the samples are accumulated.
+    pub fn render(&self, scene: &Scene, camera: &Camera) -> Result<RadianceImage> {
+        self.settings.validate()?;
+        scene.validate()?;
+
+        let RenderSettings { width, height, .. } = self.settings;
+        let aspect_ratio = width as f32 / height as f32;
+
+        let (vertices, triangles) = scene.flatten_meshes();
+        let bvh = Bvh::new(scene.spheres(), &vertices, &triangles);
+
+        let ctx = TraceContext {
+            scene,
+            vertices,
+            triangles,
+            bvh,
+            lights: scene.emissive_spheres(),
+            camera: *camera,
+            aspect_ratio,
+            inverse_view: camera.calculate_view().inverse(),
+            sky_color: self.settings.sky_color,
+            environment: self.settings.environment.as_ref(),
+            procedural_sky: self.settings.procedural_sky,
+        };
+
+        let tiles = self.tiles();
+        let next_tile = AtomicUsize::new(0);
+        let pixels = Mutex::new(vec![Vec3::ZERO; width as usize * height as usize]);
+
+        thread::scope(|s| {
+            for _ in 0..self.threads.min(tiles.len()) {
+                s.spawn(|| {
+                    while let Some(&tile) = tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) {
+                        self.write_tile(&ctx, tile, &pixels);
+                    }
+                });
+            }
+        });
+
+        Ok(RadianceImage {
+            width,
+            height,
+            pixels: pixels.into_inner().unwrap(),
+        })
+    }
+
+    /// Renders a tile, and copies it into its place in the image.
+    fn write_tile(&self, ctx: &TraceContext, tile: Tile, pixels: &Mutex<Vec<Vec3>>) {
+        let width = self.settings.width as usize;
+
+        let radiance = self.render_tile(ctx, tile);
+        let mut pixels = pixels.lock().unwrap();
+
+        for (row, colors) in radiance.chunks_exact(tile.width as usize).enumerate() {
+            let start = (tile.y as usize + row) * width + tile.x as usize;
+            pixels[start..start + colors.len()].copy_from_slice(colors);
+        }
+    }
+
+    /// Splits the image into tiles, in rows from top to bottom.
+    fn tiles(&self) -> Vec<Tile> {
+        let RenderSettings { width, height, .. } = self.settings;
+
+        (0..height)
+            .step_by(Self::TILE_SIZE as usize)
+            .flat_map(|y| {
+                (0..width)
+                    .step_by(Self::TILE_SIZE as usize)
+                    .map(move |x| Tile {
+                        x,
+                        y,
+                        width: Self::TILE_SIZE.min(width - x),
+                        height: Self::TILE_SIZE.min(height - y),
+                    })
+            })
+            .collect()
+    }
+
+    /// Accumulates all the samples of every pixel in the tile, returning their filtered average
+    /// radiance.
+    fn render_tile(&self, ctx: &TraceContext, tile: Tile) -> Vec<Vec3> {
+        let RenderSettings {
+            width,
+            height,
+            samples_per_frame,
+            pixel_filter,
+            ..
+        } = self.settings;
+
+        // as many samples as the gpu traces over all its frames
+        let samples = self.settings.frame_count() * samples_per_frame.max(1);
+        let radius = pixel_filter.radius();
+
+        let mut radiance = Vec::with_capacity(tile.width as usize * tile.height as usize);
+
+        for y in tile.y..tile.y + tile.height {
+            for x in tile.x..tile.x + tile.width {
+                let pixel_index = y * width + x;
+
+                let (total, total_weight) =
+                    (0..samples).fold((Vec3::ZERO, 0.0), |(total, total_weight), sample| {
+                        let mut rng = initial_seed(pixel_index, sample);
+
+                        // a uniformly jittered position around the pixel center, as in `cs_main`
+                        let offset_x = (next_random(&mut rng) * 2.0 - 1.0) * radius;
+                        let offset_y = (next_random(&mut rng) * 2.0 - 1.0) * radius;
+                        let offset = vec2(offset_x, offset_y);
+                        let position = vec2(x as f32, y as f32) + 0.5 + offset;
+
+                        // in normalized device coordinates, with y pointing up
+                        let coord = vec2(
+                            position.x / width as f32 * 2.0 - 1.0,
+                            1.0 - position.y / height as f32 * 2.0,
+                        );
+
+                        let sample = per_pixel(ctx, coord, &mut rng);
+                        let weight = pixel_filter.weight(offset);
+
+                        // a rare degenerate path can come out as nan or infinity, as in `cs_main`
+                        if !sample.is_finite() {
+                            return (total, total_weight);
+                        }
+
+                        (total + sample * weight, total_weight + weight)
+                    });
+
+                radiance.push(if total_weight > 0.0 {
+                    total / total_weight
+                } else {
+                    Vec3::ZERO
+                });
+            }
+        }
+
+        radiance
+    }
+}
+
+/// Casts a single ray into the scene, returning the distance to the closest surface it hits.
+pub(crate) fn cast_ray(scene: &Scene, origin: Vec3, direction: Vec3) -> Option<f32> {
+    pick(scene, origin, direction).map(|(_, distance)| distance)
+}
+
+/// Casts a single ray into the scene, returning the closest object it hits along with the
+/// distance to it.
+pub(crate) fn pick(scene: &Scene, origin: Vec3, direction: Vec3) -> Option<(SceneObject, f32)> {
+    let (vertices, triangles) = scene.flatten_meshes();
+    let bvh = Bvh::new(scene.spheres(), &vertices, &triangles);
+
+    let ctx = TraceContext {
+        scene,
+        vertices,
+        triangles,
+        bvh,
+        lights: Vec::new(),
+        camera: Camera::default(),
+        aspect_ratio: 1.0,
+        inverse_view: Mat4::IDENTITY,
+        sky_color: Vec3::ZERO,
+        environment: None,
+        procedural_sky: None,
+    };
+
+    let ray = Ray {
+        origin,
+        direction: direction.normalize(),
+    };
+
+    let hit = trace_ray(&ctx, &ray)?;
+
+    let object = match (hit.sphere_index, hit.triangle_index) {
+        (Some(sphere_index), _) => SceneObject::Sphere(sphere_index),
+        (None, Some(triangle_index)) => {
+            SceneObject::Mesh(scene.triangle_mesh(triangle_index as u32)?)
+        }
+        (None, None) => return None,
+    };
+
+    Some((object, hit.hit_distance))
+}
+
+fn per_pixel(ctx: &TraceContext, coord: Vec2, rng: &mut u32) -> Vec3 {
+    // outside of the image circle of a fisheye
+    let Some(mut camera_ray) = ctx.camera.view_ray(coord, ctx.aspect_ratio) else {
+        return Vec3::ZERO;
+    };
+
+    // rays through a thin lens converge on the plane at the focus distance in front of the camera
+    let has_lens = matches!(
+        ctx.camera.projection,
+        Projection::Perspective | Projection::Orthographic
+    );
+
+    if has_lens && ctx.camera.aperture_radius > 0.0 {
+        camera_ray = thin_lens_ray(ctx, camera_ray, rng);
+    }
+
+    // cast into world space
+    let origin = ctx.inverse_view.transform_point3(camera_ray.origin);
+    let direction = ctx.inverse_view.transform_vector3(camera_ray.direction);
+
+    let mut ray = Ray { origin, direction };
+
+    let mut light = Vec3::ZERO;
+    let mut contribution = Vec3::ONE;
+
+    // the probability of the last bounce direction, for weighing hitting a light against sampling
+    // it directly, or zero if lights weren't sampled at the last bounce
+    let mut previous_pdf = 0.0;
+
+    for bounce in 0..CpuRenderer::BOUNCES {
+        // anything beyond the far clip plane is cut away from the camera's view
+        let hit = trace_ray(ctx, &ray)
+            .filter(|hit| bounce > 0 || hit.hit_distance <= camera_ray.max_distance);
+
+        let Some(hit) = hit else {
+            light += sky_radiance(ctx, ray.direction, previous_pdf) * contribution;
+            break;
+        };
+
+        let material = &ctx.scene.materials()[hit.material_index as usize];
+
+        // light traveling through the inside of a transmissive material is absorbed along the way
+        if !hit.front_face {
+            contribution *= material
+                .absorption_color
+                .max(Vec3::splat(1e-4))
+                .powf(hit.hit_distance);
+        }
+
+        let emission_weight = match hit.sphere_index {
+            Some(sphere_index) if previous_pdf > 0.0 => {
+                let light_pdf =
+                    sphere_light_pdf(ctx, &ctx.scene.spheres()[sphere_index], ray.origin);
+                power_heuristic(previous_pdf, light_pdf)
+            }
+            _ => 1.0,
+        };
+
+        light +=
+            material.emission_color * material.emission_strength * contribution * emission_weight;
+
+        // only the reflective part of the material is lit directly
+        let brdf_probability = 1.0 - material.transmission;
+
+        if brdf_probability > 0.0 && light_count(ctx) > 0 {
+            light +=
+                sample_direct_light(ctx, material, &hit, -ray.direction, brdf_probability, rng)
+                    * contribution;
+        }
+
+        let scatter = sample_bsdf(material, &hit, -ray.direction, rng);
+        previous_pdf = scatter.pdf;
+
+        if scatter.weight == Vec3::ZERO {
+            break;
+        }
+
+        contribution *= scatter.weight;
+
+        // offset to the side of the surface the ray continues on, so that it doesn't hit itself
+        let side = if scatter.direction.dot(hit.normal) > 0.0 {
+            1.0
+        } else {
+            -1.0
+        };
+
+        ray.origin = hit.position + hit.normal * side * 0.0001;
+        ray.direction = scatter.direction;
+    }
+
+    light
+}
+
+/// Moves a ray through the center of the lens to a random point on the lens, aimed at the same
+/// point on the focus plane and clipped by the same planes, as in the shader.
+fn thin_lens_ray(ctx: &TraceContext, ray: CameraRay, rng: &mut u32) -> CameraRay {
+    let camera = &ctx.camera;
+
+    // where the ray crosses the lens and the focus plane
+    let lens_center = ray.origin - ray.direction * (ray.origin.z / ray.direction.z);
+    let focus_point =
+        ray.origin + ray.direction * ((-camera.focus_distance - ray.origin.z) / ray.direction.z);
+
+    let lens_position =
+        lens_center + (sample_aperture(camera, rng) * camera.aperture_radius).extend(0.0);
+    let direction = (focus_point - lens_position).normalize();
+
+    // the depths of the clip planes
+    let near = ray.origin.z;
+    let far = ray.origin.z + ray.direction.z * ray.max_distance;
+
+    let near_distance = (near - lens_position.z) / direction.z;
+    let far_distance = (far - lens_position.z) / direction.z;
+
+    CameraRay {
+        origin: lens_position + direction * near_distance,
+        direction,
+        max_distance: far_distance - near_distance,
+    }
+}
+
+fn trace_ray(ctx: &TraceContext, ray: &Ray) -> Option<HitPayload> {
+    let mut closest_sphere = None;
+    let mut closest_triangle = None;
+    let mut hit_distance = f32::INFINITY;
+    let mut barycentrics = Vec2::ZERO;
+
+    let inverse_direction = ray.direction.recip();
+    let nodes = ctx.bvh.nodes();
+
+    let mut stack = Vec::with_capacity(32);
+
+    if intersect_aabb(ray, inverse_direction, &nodes[0]) < hit_distance {
+        stack.push(0);
+    }
+
+    while let Some(node_index) = stack.pop() {
+        let node = &nodes[node_index];
+        let first = node.first_index as usize;
+
+        if node.primitive_count > 0 {
+            for &primitive in &ctx.bvh.primitives()[first..first + node.primitive_count as usize] {
+                if primitive & Bvh::TRIANGLE_BIT != 0 {
+                    let triangle_index = (primitive & !Bvh::TRIANGLE_BIT) as usize;
+
+                    let Some(hit) = intersect_triangle(ctx, ray, &ctx.triangles[triangle_index])
+                    else {
+                        continue;
+                    };
+
+                    if hit.x >= 0.0 && hit.x < hit_distance {
+                        hit_distance = hit.x;
+                        barycentrics = hit.yz();
+                        closest_triangle = Some(triangle_index);
+                        closest_sphere = None;
+                    }
+                } else {
+                    let sphere = &ctx.scene.spheres()[primitive as usize];
+
+                    let Some(t) = intersect_sphere(ray, sphere) else {
+                        continue;
+                    };
+
+                    if t >= 0.0 && t < hit_distance {
+                        hit_distance = t;
+                        closest_sphere = Some(primitive as usize);
+                        closest_triangle = None;
+                    }
+                }
+            }
+
+            continue;
+        }
+
+        // the root of an empty hierarchy
+        if first == 0 {
+            continue;
+        }
+
+        let mut near = (first, intersect_aabb(ray, inverse_direction, &nodes[first]));
+        let mut far = (
+            first + 1,
+            intersect_aabb(ray, inverse_direction, &nodes[first + 1]),
+        );
+
+        if far.1 < near.1 {
+            std::mem::swap(&mut near, &mut far);
+        }
+
+        // push the nearer child last, so that it is visited first and can cull the other one
+        if far.1 < hit_distance {
+            stack.push(far.0);
+        }
+
+        if near.1 < hit_distance {
+            stack.push(near.0);
+        }
+    }
+
+    if let Some(i) = closest_triangle {
+        return Some(closest_triangle_hit(
+            ctx,
+            ray,
+            hit_distance,
+            i,
+            barycentrics,
+        ));
+    }
+
+    closest_sphere.map(|i| closest_hit(ctx, ray, hit_distance, i))
+}
+
+/// The distance along the ray to where it first hits the sphere, from either the outside or the
+/// inside.
+fn intersect_sphere(ray: &Ray, sphere: &Sphere) -> Option<f32> {
+    let origin = ray.origin - sphere.position.xyz();
+
+    let a = ray.direction.dot(ray.direction);
+    let b = 2.0 * origin.dot(ray.direction);
+    let c = origin.dot(origin) - sphere.radius * sphere.radius;
+
+    let discriminant = (b * b) - (4.0 * a * c);
+
+    if discriminant <= 0.0 {
+        return None;
+    }
+
+    let near = (-b - discriminant.sqrt()) / (2.0 * a);
+
+    if near >= 0.0 {
+        return Some(near);
+    }
+
+    Some((-b + discriminant.sqrt()) / (2.0 * a))
+}
+
+/// Slab test against the bounds of a node, returning the distance along the ray to where it enters
+/// them, or infinity on a miss.
+fn intersect_aabb(ray: &Ray, inverse_direction: Vec3, node: &BvhNode) -> f32 {
+    let t0 = (node.minimum - ray.origin) * inverse_direction;
+    let t1 = (node.maximum - ray.origin) * inverse_direction;
+
+    let entry = t0.min(t1).max_element();
+    let exit = t0.max(t1).min_element();
+
+    if entry > exit || exit < 0.0 {
+        return f32::INFINITY;
+    }
+
+    entry.max(0.0)
+}
+
+/// Möller–Trumbore intersection, returning the distance along the ray followed by the barycentric
+/// coordinates of the second and third corners.
+fn intersect_triangle(ctx: &TraceContext, ray: &Ray, triangle: &Triangle) -> Option<Vec3> {
+    let v0 = ctx.vertices[triangle.indices.x as usize].position;
+    let v1 = ctx.vertices[triangle.indices.y as usize].position;
+    let v2 = ctx.vertices[triangle.indices.z as usize].position;
+
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+
+    let p = ray.direction.cross(edge2);
+    let determinant = edge1.dot(p);
+
+    // parallel to the triangle, or a degenerate triangle
+    if determinant.abs() < 1e-8 {
+        return None;
+    }
+
+    let inverse_determinant = 1.0 / determinant;
+    let s = ray.origin - v0;
+
+    let u = s.dot(p) * inverse_determinant;
+
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(edge1);
+    let v = ray.direction.dot(q) * inverse_determinant;
+
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    Some(vec3(edge2.dot(q) * inverse_determinant, u, v))
+}
+
+fn closest_hit(
+    ctx: &TraceContext,
+    ray: &Ray,
+    hit_distance: f32,
+    object_index: usize,
+) -> HitPayload {
+    let sphere = &ctx.scene.spheres()[object_index];
+    let origin = ray.origin - sphere.position.xyz();
+
+    let position = origin + ray.direction * hit_distance;
+
+    let normal = position.normalize();
+    let front_face = normal.dot(ray.direction) < 0.0;
+
+    HitPayload {
+        hit_distance,
+        position: position + sphere.position.xyz(),
+        normal: if front_face { normal } else { -normal },
+        material_index: sphere.material_index,
+        front_face,
+        sphere_index: Some(object_index),
+        triangle_index: None,
+    }
+}
+
+fn closest_triangle_hit(
+    ctx: &TraceContext,
+    ray: &Ray,
+    hit_distance: f32,
+    triangle_index: usize,
+    barycentrics: Vec2,
+) -> HitPayload {
+    let triangle = &ctx.triangles[triangle_index];
+
+    let n0 = ctx.vertices[triangle.indices.x as usize].normal;
+    let n1 = ctx.vertices[triangle.indices.y as usize].normal;
+    let n2 = ctx.vertices[triangle.indices.z as usize].normal;
+
+    let normal =
+        (n0 * (1.0 - barycentrics.x - barycentrics.y) + n1 * barycentrics.x + n2 * barycentrics.y)
+            .normalize();
+
+    // triangles are two sided, with the outside being the side the normals face
+    let front_face = normal.dot(ray.direction) < 0.0;
+
+    HitPayload {
+        hit_distance,
+        position: ray.origin + ray.direction * hit_distance,
+        normal: if front_face { normal } else { -normal },
+        material_index: triangle.material_index,
+        front_face,
+        sphere_index: None,
+        triangle_index: Some(triangle_index),
+    }
+}
+
+/// Samples a direction to continue a path in, either passing through a transmissive surface or
+/// reflecting off of it.
+fn sample_bsdf(material: &Material, hit: &HitPayload, view: Vec3, rng: &mut u32) -> BsdfSample {
+    if next_random(rng) < material.transmission {
+        return sample_dielectric(material, hit, view, rng);
+    }
+
+    let mut sample = sample_brdf(material, hit.normal, view, rng);
+    sample.pdf *= 1.0 - material.transmission;
+
+    sample
+}
+
+/// The number of lights sampled directly, with the environment map or the sun of the procedural
+/// sky counting as one.
+fn light_count(ctx: &TraceContext) -> usize {
+    ctx.lights.len() + (ctx.environment.is_some() || ctx.procedural_sky.is_some()) as usize
+}
+
+/// Picks a random light and samples a direction towards it, returning the light reaching the
+/// surface from it if it is not occluded, weighted against the brdf also finding it.
+fn sample_direct_light(
+    ctx: &TraceContext,
+    material: &Material,
+    hit: &HitPayload,
+    view: Vec3,
+    brdf_probability: f32,
+    rng: &mut u32,
+) -> Vec3 {
+    let light_count = light_count(ctx);
+    let light_index = ((next_random(rng) * light_count as f32) as usize).min(light_count - 1);
+    // the sky comes after every sphere
+    let is_sky = light_index == ctx.lights.len();
+
+    let sample = match (is_sky, ctx.environment, ctx.procedural_sky) {
+        (true, Some(environment), _) => sample_environment(environment, rng),
+        (true, None, Some(sky)) => sample_sun(&sky, rng),
+        _ => {
+            let sphere = &ctx.scene.spheres()[ctx.lights[light_index] as usize];
+            sample_sphere_light(ctx, sphere, hit.position, rng)
+        }
+    };
+
+    if sample.pdf <= 0.0 {
+        return Vec3::ZERO;
+    }
+
+    let evaluation = evaluate_brdf(material, hit.normal, view, sample.direction);
+
+    if evaluation.pdf <= 0.0 {
+        return Vec3::ZERO;
+    }
+
+    let shadow_ray = Ray {
+        origin: hit.position + hit.normal * 0.0001,
+        direction: sample.direction,
+    };
+
+    let shadow_hit = trace_ray(ctx, &shadow_ray);
+
+    let visible = if is_sky {
+        // the sky is only visible if nothing is hit at all
+        shadow_hit.is_none()
+    } else {
+        shadow_hit.and_then(|hit| hit.sphere_index) == Some(ctx.lights[light_index] as usize)
+    };
+
+    if !visible {
+        return Vec3::ZERO;
+    }
+
+    let light_pdf = sample.pdf / light_count as f32;
+    let weight = power_heuristic(light_pdf, evaluation.pdf * brdf_probability);
+
+    sample.radiance * evaluation.value * brdf_probability * weight / light_pdf
+}
+
+/// Samples the cone of directions the sphere covers from the position uniformly.
+fn sample_sphere_light(
+    ctx: &TraceContext,
+    sphere: &Sphere,
+    position: Vec3,
+    rng: &mut u32,
+) -> LightSample {
+    let to_center = sphere.position.xyz() - position;
+    let distance_squared = to_center.length_squared();
+    let radius_squared = sphere.radius * sphere.radius;
+
+    // the surface is inside of the light
+    if distance_squared <= radius_squared {
+        return LightSample {
+            direction: Vec3::ZERO,
+            radiance: Vec3::ZERO,
+            pdf: 0.0,
+        };
+    }
+
+    let sin_max_squared = radius_squared / distance_squared;
+    let cos_max = (1.0 - sin_max_squared).max(0.0).sqrt();
+    let one_minus_cos_max = sin_max_squared / (1.0 + cos_max);
+
+    let cos_theta = 1.0 - next_random(rng) * one_minus_cos_max;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * next_random(rng);
+
+    let direction = orthonormal_basis(to_center.normalize())
+        * vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+
+    let material = &ctx.scene.materials()[sphere.material_index as usize];
+
+    LightSample {
+        direction,
+        radiance: material.emission_color * material.emission_strength,
+        pdf: 1.0 / (2.0 * PI * one_minus_cos_max),
+    }
+}
+
+/// The probability of [`sample_direct_light`] choosing a direction that hits the sphere from the
+/// position.
+fn sphere_light_pdf(ctx: &TraceContext, sphere: &Sphere, position: Vec3) -> f32 {
+    let to_center = sphere.position.xyz() - position;
+    let distance_squared = to_center.length_squared();
+    let radius_squared = sphere.radius * sphere.radius;
+
+    if distance_squared <= radius_squared {
+        return 0.0;
+    }
+
+    let sin_max_squared = radius_squared / distance_squared;
+    let cos_max = (1.0 - sin_max_squared).max(0.0).sqrt();
+    let one_minus_cos_max = sin_max_squared / (1.0 + cos_max);
+
+    1.0 / (2.0 * PI * one_minus_cos_max * light_count(ctx) as f32)
+}
+
+/// The light arriving along a ray that doesn't hit anything, given the probability of the bounce
+/// that sent it, with the parts of the sky that are also sampled directly weighted against it.
+fn sky_radiance(ctx: &TraceContext, direction: Vec3, previous_pdf: f32) -> Vec3 {
+    if let Some(environment) = ctx.environment {
+        let weight = if previous_pdf > 0.0 {
+            let light_pdf = environment_pdf(environment, direction) / light_count(ctx) as f32;
+            power_heuristic(previous_pdf, light_pdf)
+        } else {
+            1.0
+        };
+
+        return environment_radiance(environment, direction) * weight;
+    }
+
+    if let Some(sky) = &ctx.procedural_sky {
+        let sun = sun_radiance(sky, direction);
+
+        let weight = if previous_pdf > 0.0 && sun.cmpgt(Vec3::ZERO).any() {
+            power_heuristic(previous_pdf, sun_pdf(sky) / light_count(ctx) as f32)
+        } else {
+            1.0
+        };
+
+        return preetham_sky(sky, direction) + sun * weight;
+    }
+
+    ctx.sky_color
+}
+
+/// The light arriving from the environment map along the direction.
+fn environment_radiance(environment: &Environment, direction: Vec3) -> Vec3 {
+    let map = &environment.map;
+    let (x, y) = environment_pixel(environment, direction_to_equirect(environment, direction));
+
+    map.pixel(x, y) * environment.intensity
+}
+
+/// Picks a direction with a probability proportional to the brightness of the environment map,
+/// by picking a row and then a column within it, as in "Physically Based Rendering" by Pharr et al.
+fn sample_environment(environment: &Environment, rng: &mut u32) -> LightSample {
+    let map = &environment.map;
+
+    let row = search_cdf(map.marginal_cdf(), next_random(rng));
+    let column = search_cdf(map.conditional_cdf(row), next_random(rng));
+
+    // pick a point within the pixel uniformly
+    let uv = (vec2(column as f32, row as f32) + vec2(next_random(rng), next_random(rng)))
+        / vec2(map.width() as f32, map.height() as f32);
+    let direction = equirect_to_direction(environment, uv);
+
+    LightSample {
+        direction,
+        radiance: environment_radiance(environment, direction),
+        pdf: environment_pdf(environment, direction),
+    }
+}
+
+/// The probability of [`sample_environment`] choosing the direction.
+fn environment_pdf(environment: &Environment, direction: Vec3) -> f32 {
+    let map = &environment.map;
+    let uv = direction_to_equirect(environment, direction);
+    let (x, y) = environment_pixel(environment, uv);
+
+    let row_probability = cdf_step(map.marginal_cdf(), y);
+    let column_probability = cdf_step(map.conditional_cdf(y), x);
+
+    // the density over the image, converted to one over the sphere of directions
+    let image_pdf = row_probability * column_probability * (map.width() * map.height()) as f32;
+    let sin_theta = (uv.y * PI).sin();
+
+    if sin_theta <= 0.0 {
+        return 0.0;
+    }
+
+    image_pdf / (2.0 * PI * PI * sin_theta)
+}
+
+/// The light scattered towards the direction by a clear sky, from the Perez distribution fit to
+/// the turbidity and the position of the sun in "A Practical Analytic Model for Daylight" by
+/// Preetham et al.
+fn preetham_sky(sky: &ProceduralSky, direction: Vec3) -> Vec3 {
+    let turbidity = sky.model_turbidity();
+    let sun_direction = sky.sun_direction();
+
+    // the model only covers the upper hemisphere, so everything below shows the horizon
+    let cos_theta = direction.y.max(0.001);
+    let cos_gamma = direction.dot(sun_direction).clamp(-1.0, 1.0);
+    let gamma = cos_gamma.acos();
+
+    let theta_sun = sun_direction.y.clamp(0.0, 1.0).acos();
+    let theta_sun_2 = theta_sun * theta_sun;
+    let theta_sun_3 = theta_sun_2 * theta_sun;
+    let turbidity_2 = turbidity * turbidity;
+
+    let chi = (4.0 / 9.0 - turbidity / 120.0) * (PI - 2.0 * theta_sun);
+    let zenith_luminance = (4.0453 * turbidity - 4.9710) * chi.tan() - 0.2155 * turbidity + 2.4192;
+
+    let zenith_x = turbidity_2
+        * (0.00166 * theta_sun_3 - 0.00375 * theta_sun_2 + 0.00209 * theta_sun)
+        + turbidity
+            * (-0.02903 * theta_sun_3 + 0.06377 * theta_sun_2 - 0.03202 * theta_sun + 0.00394)
+        + (0.11693 * theta_sun_3 - 0.21196 * theta_sun_2 + 0.06052 * theta_sun + 0.25886);
+    let zenith_y = turbidity_2
+        * (0.00275 * theta_sun_3 - 0.00610 * theta_sun_2 + 0.00317 * theta_sun)
+        + turbidity
+            * (-0.04214 * theta_sun_3 + 0.08970 * theta_sun_2 - 0.04153 * theta_sun + 0.00516)
+        + (0.15346 * theta_sun_3 - 0.26756 * theta_sun_2 + 0.06670 * theta_sun + 0.26688);
+
+    // the coefficients of the distribution for the luminance and both chromaticities
+    let coefficients = PerezCoefficients {
+        a: vec3(0.1787, -0.0193, -0.0167) * turbidity + vec3(-1.4630, -0.2592, -0.2608),
+        b: vec3(-0.3554, -0.0665, -0.0950) * turbidity + vec3(0.4275, 0.0008, 0.0092),
+        c: vec3(-0.0227, -0.0004, -0.0079) * turbidity + vec3(5.3251, 0.2125, 0.2102),
+        d: vec3(0.1206, -0.0641, -0.0441) * turbidity + vec3(-2.5771, -0.8989, -1.6537),
+        e: vec3(-0.0670, -0.0033, -0.0109) * turbidity + vec3(0.3703, 0.0452, 0.0529),
+    };
+
+    let distribution = coefficients.perez(cos_theta, gamma, cos_gamma);
+    let zenith_distribution = coefficients.perez(1.0, theta_sun, theta_sun.cos());
+
+    let yxy = vec3(zenith_luminance, zenith_x, zenith_y) * distribution / zenith_distribution;
+    let luminance = yxy.x * SKY_LUMINANCE_SCALE;
+
+    let xyz = vec3(
+        yxy.y / yxy.z * luminance,
+        luminance,
+        (1.0 - yxy.y - yxy.z) / yxy.z * luminance,
+    );
+
+    xyz_to_linear_srgb(xyz).max(Vec3::ZERO)
+}
+
+/// The coefficients of the Perez sky distribution, for all three channels at once.
+struct PerezCoefficients {
+    a: Vec3,
+    b: Vec3,
+    c: Vec3,
+    d: Vec3,
+    e: Vec3,
+}
+
+impl PerezCoefficients {
+    fn perez(&self, cos_theta: f32, gamma: f32, cos_gamma: f32) -> Vec3 {
+        let Self { a, b, c, d, e } = *self;
+
+        (1.0 + a * (b / cos_theta).exp())
+            * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+fn xyz_to_linear_srgb(xyz: Vec3) -> Vec3 {
+    Mat3::from_cols(
+        vec3(3.2406, -0.9689, 0.0557),
+        vec3(-1.5372, 1.8758, -0.2040),
+        vec3(-0.4986, 0.0415, 1.0570),
+    ) * xyz
+}
+
+/// The light arriving from the sun disk along the direction, or zero outside of it.
+fn sun_radiance(sky: &ProceduralSky, direction: Vec3) -> Vec3 {
+    if direction.dot(sky.sun_direction()) < sky.sun_angular_radius().cos() {
+        return Vec3::ZERO;
+    }
+
+    sun_disk_radiance(sky)
+}
+
+/// The light arriving from every point of the sun disk.
+fn sun_disk_radiance(sky: &ProceduralSky) -> Vec3 {
+    // spread the illuminance over the disk, so that its size doesn't change how bright it is
+    let sin_radius = sky.sun_angular_radius().sin();
+    let illuminance = SUN_ILLUMINANCE * SKY_LUMINANCE_SCALE * sky.sun_intensity;
+
+    sun_transmittance(sky, sky.sun_direction().y) * illuminance / (PI * sin_radius * sin_radius)
+}
+
+/// The fraction of red, green and blue sunlight making it through the atmosphere, from Rayleigh
+/// scattering by air and Mie scattering by aerosols, as in the appendix of Preetham et al.
+fn sun_transmittance(sky: &ProceduralSky, cos_theta: f32) -> Vec3 {
+    let theta = cos_theta.clamp(0.0, 1.0).acos();
+    // the relative length of the path through the atmosphere
+    let air_mass = 1.0 / (theta.cos() + 0.15 * (93.885 - theta.to_degrees()).powf(-1.253));
+
+    // the wavelengths of red, green and blue light in micrometers
+    let wavelengths = vec3(0.680, 0.550, 0.440);
+
+    let beta = 0.04608366 * sky.model_turbidity() - 0.04586026;
+    let rayleigh = (-0.008735 * wavelengths.powf(-4.08) * air_mass).exp();
+    let aerosol = (-beta * wavelengths.powf(-1.3) * air_mass).exp();
+
+    rayleigh * aerosol
+}
+
+/// Samples the cone of directions the sun disk covers uniformly.
+fn sample_sun(sky: &ProceduralSky, rng: &mut u32) -> LightSample {
+    let sin_half_radius = (sky.sun_angular_radius() * 0.5).sin();
+    let one_minus_cos_max = 2.0 * sin_half_radius * sin_half_radius;
+
+    let cos_theta = 1.0 - next_random(rng) * one_minus_cos_max;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * next_random(rng);
+
+    let direction = orthonormal_basis(sky.sun_direction())
+        * vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+
+    LightSample {
+        direction,
+        radiance: sun_disk_radiance(sky),
+        pdf: sun_pdf(sky),
+    }
+}
+
+/// The probability of [`sample_sun`] choosing any direction within the sun disk.
+fn sun_pdf(sky: &ProceduralSky) -> f32 {
+    let sin_half_radius = (sky.sun_angular_radius() * 0.5).sin();
+
+    1.0 / (4.0 * PI * sin_half_radius * sin_half_radius)
+}
+
+/// Maps a direction to a position on the environment map, with the top of the image straight up
+/// and its center along the negative Z axis before rotating.
+fn direction_to_equirect(environment: &Environment, direction: Vec3) -> Vec2 {
+    let phi = direction.x.atan2(-direction.z) - environment.rotation.to_radians();
+    let theta = direction.y.clamp(-1.0, 1.0).acos();
+
+    vec2((phi / (2.0 * PI) + 0.5).rem_euclid(1.0), theta / PI)
+}
+
+/// The inverse of [`direction_to_equirect`].
+fn equirect_to_direction(environment: &Environment, uv: Vec2) -> Vec3 {
+    let phi = (uv.x - 0.5) * 2.0 * PI + environment.rotation.to_radians();
+    let theta = uv.y * PI;
+
+    vec3(
+        theta.sin() * phi.sin(),
+        theta.cos(),
+        -theta.sin() * phi.cos(),
+    )
+}
+
+fn environment_pixel(environment: &Environment, uv: Vec2) -> (u32, u32) {
+    let (width, height) = (environment.map.width(), environment.map.height());
+
+    (
+        ((uv.x * width as f32) as u32).min(width - 1),
+        ((uv.y * height as f32) as u32).min(height - 1),
+    )
+}
+
+/// Finds the first entry of a cumulative distribution that is larger than `u`.
+fn search_cdf(cdf: &[f32], u: f32) -> u32 {
+    cdf.partition_point(|&value| value <= u).min(cdf.len() - 1) as u32
+}
+
+/// The probability of picking an entry of a cumulative distribution.
+fn cdf_step(cdf: &[f32], index: u32) -> f32 {
+    let index = index as usize;
+    let previous = if index > 0 { cdf[index - 1] } else { 0.0 };
+
+    cdf[index] - previous
+}
+
+/// Weighs a sample from one strategy against another that could have produced it, as in "Optimally
+/// Combining Sampling Techniques for Monte Carlo Rendering" by Veach and Guibas.
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let squared = pdf * pdf;
+    squared / (squared + other_pdf * other_pdf)
+}
+
+/// Samples a rough dielectric interface, either reflecting off of a microfacet or refracting
+/// through it depending on the fresnel term, following "Microfacet Models for Refraction through
+/// Rough Surfaces" by Walter et al.
+fn sample_dielectric(
+    material: &Material,
+    hit: &HitPayload,
+    view: Vec3,
+    rng: &mut u32,
+) -> BsdfSample {
+    let alpha = (material.roughness * material.roughness).max(MIN_ALPHA);
+    // the ratio of the index of refraction on the side of the ray to the one on the other side
+    let eta = if hit.front_face {
+        1.0 / material.ior
+    } else {
+        material.ior
+    };
+
+    let normal = hit.normal;
+    let microfacet = orthonormal_basis(normal) * sample_ggx(alpha, rng);
+
+    let v_dot_m = view.dot(microfacet);
+
+    let absorbed = BsdfSample {
+        direction: Vec3::ZERO,
+        weight: Vec3::ZERO,
+        pdf: 0.0,
+    };
+
+    if v_dot_m <= 0.0 {
+        return absorbed;
+    }
+
+    // the fresnel term is one on total internal reflection, so the refraction is always valid
+    let reflected = next_random(rng) < fresnel_dielectric(v_dot_m, eta);
+
+    let direction = if reflected {
+        (-view).reflect(microfacet)
+    } else {
+        (-view).refract(microfacet, eta)
+    };
+
+    let n_dot_l = normal.dot(direction);
+
+    // ended up on the wrong side of the macro surface
+    if (n_dot_l > 0.0) != reflected || n_dot_l == 0.0 {
+        return absorbed;
+    }
+
+    let n_dot_v = normal.dot(view).max(1e-4);
+    let n_dot_m = normal.dot(microfacet).max(1e-4);
+    let masking = smith_ggx_masking(n_dot_v, alpha) * smith_ggx_masking(n_dot_l.abs(), alpha);
+
+    BsdfSample {
+        direction,
+        weight: Vec3::splat(v_dot_m * masking / (n_dot_v * n_dot_m)),
+        pdf: 0.0,
+    }
+}
+
+/// The unpolarized fresnel reflectance of an interface between two dielectrics.
+fn fresnel_dielectric(cos_incident: f32, eta: f32) -> f32 {
+    let sin_transmitted_squared = eta * eta * (1.0 - cos_incident * cos_incident);
+
+    if sin_transmitted_squared >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_transmitted = (1.0 - sin_transmitted_squared).sqrt();
+
+    let perpendicular =
+        (eta * cos_incident - cos_transmitted) / (eta * cos_incident + cos_transmitted);
+    let parallel = (cos_incident - eta * cos_transmitted) / (cos_incident + eta * cos_transmitted);
+
+    0.5 * (perpendicular * perpendicular + parallel * parallel)
+}
+
+/// Samples a direction to continue a path in, choosing between the lambertian diffuse lobe and
+/// the ggx specular lobe by how much light each of them reflects.
+fn sample_brdf(material: &Material, normal: Vec3, view: Vec3, rng: &mut u32) -> BsdfSample {
+    let alpha = (material.roughness * material.roughness).max(MIN_ALPHA);
+    let basis = orthonormal_basis(normal);
+
+    let direction = if next_random(rng) < specular_probability(material, normal, view) {
+        let half_vector = basis * sample_ggx(alpha, rng);
+        (-view).reflect(half_vector)
+    } else {
+        basis * sample_cosine_hemisphere(rng)
+    };
+
+    let evaluation = evaluate_brdf(material, normal, view, direction);
+
+    // reflected below the surface
+    if evaluation.pdf <= 0.0 {
+        return BsdfSample {
+            direction,
+            weight: Vec3::ZERO,
+            pdf: 0.0,
+        };
+    }
+
+    BsdfSample {
+        direction,
+        weight: evaluation.value / evaluation.pdf,
+        pdf: evaluation.pdf,
+    }
+}
+
+fn evaluate_brdf(material: &Material, normal: Vec3, view: Vec3, direction: Vec3) -> BrdfEvaluation {
+    let n_dot_l = normal.dot(direction);
+
+    if n_dot_l <= 0.0 {
+        return BrdfEvaluation {
+            value: Vec3::ZERO,
+            pdf: 0.0,
+        };
+    }
+
+    let alpha = (material.roughness * material.roughness).max(MIN_ALPHA);
+    let specular_color = Vec3::splat(0.04).lerp(material.albedo, material.metallic);
+    let diffuse_color = material.albedo * (1.0 - material.metallic);
+
+    let n_dot_v = normal.dot(view).max(1e-4);
+
+    let half_vector = (view + direction).normalize();
+    let n_dot_h = normal.dot(half_vector).max(0.0);
+    let v_dot_h = view.dot(half_vector).max(1e-4);
+
+    let fresnel = fresnel_schlick(specular_color, v_dot_h);
+    let distribution = ggx_distribution(n_dot_h, alpha);
+    let masking = smith_ggx_masking(n_dot_v, alpha) * smith_ggx_masking(n_dot_l, alpha);
+
+    let specular = fresnel * distribution * masking / (4.0 * n_dot_v * n_dot_l);
+    let diffuse = (1.0 - fresnel) * diffuse_color / PI;
+
+    // the probability of the direction with either lobe, weighted by how often it is chosen
+    let specular_pdf = distribution * n_dot_h / (4.0 * v_dot_h);
+    let diffuse_pdf = n_dot_l / PI;
+    let specular_probability = specular_probability(material, normal, view);
+
+    BrdfEvaluation {
+        value: (diffuse + specular) * n_dot_l,
+        pdf: diffuse_pdf + (specular_pdf - diffuse_pdf) * specular_probability,
+    }
+}
+
+/// How often the specular lobe is sampled rather than the diffuse lobe, by how much light each of
+/// them reflects.
+fn specular_probability(material: &Material, normal: Vec3, view: Vec3) -> f32 {
+    let specular_color = Vec3::splat(0.04).lerp(material.albedo, material.metallic);
+    let diffuse_color = material.albedo * (1.0 - material.metallic);
+
+    let n_dot_v = normal.dot(view).max(1e-4);
+
+    let specular_weight = luminance(fresnel_schlick(specular_color, n_dot_v));
+    let diffuse_weight = luminance(diffuse_color) * (1.0 - specular_weight);
+
+    specular_weight / (specular_weight + diffuse_weight).max(1e-4)
+}
+
+fn fresnel_schlick(specular_color: Vec3, cos_theta: f32) -> Vec3 {
+    specular_color + (1.0 - specular_color) * (1.0 - cos_theta).powi(5)
+}
+
+fn ggx_distribution(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha_squared = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha_squared - 1.0) + 1.0;
+
+    alpha_squared / (PI * denominator * denominator)
+}
+
+/// The separable smith masking function of a single direction.
+fn smith_ggx_masking(n_dot_x: f32, alpha: f32) -> f32 {
+    let alpha_squared = alpha * alpha;
+
+    2.0 * n_dot_x / (n_dot_x + (alpha_squared + (1.0 - alpha_squared) * n_dot_x * n_dot_x).sqrt())
+}
+
+/// Samples a microfacet normal proportionally to the ggx distribution times its cosine, in tangent
+/// space.
+fn sample_ggx(alpha: f32, rng: &mut u32) -> Vec3 {
+    let u = next_random(rng);
+    let phi = 2.0 * PI * next_random(rng);
+
+    let cos_theta = ((1.0 - u) / (1.0 + (alpha * alpha - 1.0) * u)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+
+    vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// Samples a direction proportionally to its cosine with the normal, in tangent space.
+/// Samples a point on the unit disk, or on the regular polygon inscribed in it when the aperture
+/// has blades.
+fn sample_aperture(camera: &Camera, rng: &mut u32) -> Vec2 {
+    let u = next_random(rng);
+    let v = next_random(rng);
+    let blades = camera.aperture_blades;
+
+    if blades < 3 {
+        let phi = 2.0 * PI * v;
+        return u.sqrt() * vec2(phi.cos(), phi.sin());
+    }
+
+    // pick one of the triangles between the center and an edge of the polygon, then a point in it
+    let scaled = v * blades as f32;
+    let blade = scaled.floor();
+    let angle = 2.0 * PI / blades as f32;
+
+    let start = vec2((blade * angle).cos(), (blade * angle).sin());
+    let end = vec2(((blade + 1.0) * angle).cos(), ((blade + 1.0) * angle).sin());
+
+    u.sqrt() * start.lerp(end, scaled - blade)
+}
+
+fn sample_cosine_hemisphere(rng: &mut u32) -> Vec3 {
+    let radius = next_random(rng).sqrt();
+    let phi = 2.0 * PI * next_random(rng);
+
+    let x = radius * phi.cos();
+    let y = radius * phi.sin();
+
+    vec3(x, y, (1.0 - x * x - y * y).max(0.0).sqrt())
+}
+
+/// Builds a basis around the normal to bring tangent space directions into world space, following
+/// "Building an Orthonormal Basis, Revisited" by Duff et al.
+fn orthonormal_basis(normal: Vec3) -> Mat3 {
+    let sign_z = if normal.z >= 0.0 { 1.0 } else { -1.0 };
+    let a = -1.0 / (sign_z + normal.z);
+    let b = normal.x * normal.y * a;
+
+    let tangent = vec3(
+        1.0 + sign_z * normal.x * normal.x * a,
+        sign_z * b,
+        -sign_z * normal.x,
+    );
+    let bitangent = vec3(b, sign_z + normal.y * normal.y * a, -normal.y);
+
+    Mat3::from_cols(tangent, bitangent, normal)
+}
+
+fn luminance(color: Vec3) -> f32 {
+    color.dot(vec3(0.2126, 0.7152, 0.0722))
+}
+
+/// Creates a distinct seed for every sample of every pixel.
+fn initial_seed(pixel_index: u32, sample: u32) -> u32 {
+    let mut seed = pixel_index;
+    next_random(&mut seed);
+
+    seed ^ sample.wrapping_mul(0x9e3779b9)
+}
+
+fn next_random(rng: &mut u32) -> f32 {
+    *rng ^= *rng >> 16;
+    *rng = rng.wrapping_mul(0x85ebca6b);
+    *rng ^= *rng >> 13;
+    *rng = rng.wrapping_mul(0xc2b2ae35);
+    *rng ^= *rng >> 16;
+
+    (*rng & 0x007fffff) as f32 / 0x00800000 as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A context tracing the scene from the default camera, lit only by a uniform sky.
+    fn context(scene: &Scene, sky_color: Vec3) -> TraceContext<'_> {
+        let (vertices, triangles) = scene.flatten_meshes();
+        let camera = Camera::default();
+
+        TraceContext {
+            scene,
+            bvh: Bvh::new(scene.spheres(), &vertices, &triangles),
+            vertices,
+            triangles,
+            lights: scene.emissive_spheres(),
+            camera,
+            aspect_ratio: 1.0,
+            inverse_view: camera.calculate_view().inverse(),
+            sky_color,
+            environment: None,
+            procedural_sky: None,
+        }
+    }
+
+    fn settings(width: u32, height: u32, sky_color: Vec3) -> RenderSettings {
+        RenderSettings {
+            width,
+            height,
+            samples_per_pixel: 4,
+            sky_color,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rays_missing_everything_see_the_sky_color() {
+        let sky_color = vec3(0.2, 0.4, 0.6);
+        let scene = Scene::with_objects(Vec::new(), vec![Material::default()]);
+
+        let image = CpuRenderer::new(settings(8, 8, sky_color))
+            .render(&scene, &Camera::default())
+            .unwrap();
+
+        for pixel in image.pixels {
+            assert!(pixel.abs_diff_eq(sky_color, 1e-6), "{pixel} != {sky_color}");
+        }
+    }
+
+    #[test]
+    fn rays_hit_the_near_side_of_a_sphere() {
+        let scene = Scene::with_objects(
+            vec![Sphere::new(vec3(0.0, 0.0, -5.0), 1.0, 0)],
+            vec![Material::default()],
+        );
+        let ctx = context(&scene, Vec3::ZERO);
+
+        let hit = trace_ray(
+            &ctx,
+            &Ray {
+                origin: Vec3::ZERO,
+                direction: Vec3::NEG_Z,
+            },
+        )
+        .unwrap();
+
+        assert!((hit.hit_distance - 4.0).abs() < 1e-5);
+        assert!(hit.position.abs_diff_eq(vec3(0.0, 0.0, -4.0), 1e-5));
+        assert!(hit.normal.abs_diff_eq(Vec3::Z, 1e-5));
+        assert!(hit.front_face);
+        assert_eq!(hit.sphere_index, Some(0));
+        assert_eq!(hit.triangle_index, None);
+
+        // leaving the sphere from its center, the normal still faces against the ray
+        let hit = trace_ray(
+            &ctx,
+            &Ray {
+                origin: vec3(0.0, 0.0, -5.0),
+                direction: Vec3::X,
+            },
+        )
+        .unwrap();
+
+        assert!((hit.hit_distance - 1.0).abs() < 1e-5);
+        assert!(hit.position.abs_diff_eq(vec3(1.0, 0.0, -5.0), 1e-5));
+        assert!(hit.normal.abs_diff_eq(Vec3::NEG_X, 1e-5));
+        assert!(!hit.front_face);
+
+        let miss = trace_ray(
+            &ctx,
+            &Ray {
+                origin: Vec3::ZERO,
+                direction: Vec3::Z,
+            },
+        );
+
+        assert!(miss.is_none());
+    }
+
+    #[test]
+    fn emissive_spheres_seen_directly_show_their_emission() {
+        let emission = vec3(2.0, 1.0, 0.5);
+        let camera = Camera::default();
+
+        // a black sphere right in front of the camera, glowing in a black sky
+        let scene = Scene::with_objects(
+            vec![Sphere::new(camera.eye + Vec3::NEG_Z * 5.0, 1.0, 0)],
+            vec![Material::new(Vec3::ZERO, 1.0, 0.0).with_emission(emission, 1.0)],
+        );
+
+        let image = CpuRenderer::new(settings(3, 3, Vec3::ZERO))
+            .render(&scene, &camera)
+            .unwrap();
+        let center = image.pixels[4];
+
+        assert!(center.abs_diff_eq(emission, 1e-3), "{center} != {emission}");
+    }
+
+    #[test]
+    fn renders_are_deterministic() {
+        let scene = Scene::with_objects(
+            vec![
+                Sphere::new(vec3(0.0, 0.5, 0.0), 0.5, 0),
+                Sphere::new(vec3(1.0, 0.5, -1.0), 0.5, 1),
+                Sphere::new(vec3(0.0, -100.0, 0.0), 100.0, 2),
+            ],
+            vec![
+                Material::new(vec3(0.8, 0.2, 0.2), 0.3, 0.0),
+                Material::new(Vec3::ONE, 0.0, 0.0).with_emission(Vec3::ONE, 4.0),
+                Material::new(vec3(0.5, 0.5, 0.5), 1.0, 0.0).with_transmission(0.5, 1.5, Vec3::ONE),
+            ],
+        );
+        let camera = Camera::default();
+
+        let mut renderer = CpuRenderer::new(settings(24, 16, vec3(0.1, 0.2, 0.3)));
+        let first = renderer.render(&scene, &camera).unwrap();
+
+        // the seeds only depend on the pixel and sample, not on which thread traces them
+        renderer.set_threads(1);
+        let second = renderer.render(&scene, &camera).unwrap();
+
+        assert_eq!(first.pixels, second.pixels);
+
+        let ctx = context(&scene, vec3(0.1, 0.2, 0.3));
+        let mut rng = initial_seed(7, 3);
+        let mut same_rng = initial_seed(7, 3);
+
+        assert_eq!(
+            per_pixel(&ctx, vec2(0.1, -0.2), &mut rng),
+            per_pixel(&ctx, vec2(0.1, -0.2), &mut same_rng),
+        );
+    }
+}
@@ -0,0 +1,504 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Context, Result};
+use glam::Vec3;
+use half::f16;
+use serde::{Deserialize, Serialize};
+use wgpu::{util::*, *};
+
+/// An equirectangular image of the light arriving from every direction, lighting the scene in
+/// place of a flat sky color.
+///
+/// Along with the radiance, the map keeps the distributions used to importance sample its bright
+/// regions, built once when it is loaded.
+#[derive(Clone, PartialEq)]
+pub struct EnvironmentMap {
+    /// The file the map was loaded from.
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    /// The radiance of every pixel, in rows from the top of the sky to the bottom.
+    pixels: Vec<Vec3>,
+    /// The cumulative distribution of picking each column, given the row, for every row.
+    conditional_cdf: Vec<f32>,
+    /// The cumulative distribution of picking each row.
+    marginal_cdf: Vec<f32>,
+}
+
+/// An environment map along with how it is placed around the scene.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Environment {
+    /// The map being rendered, shared as it can be very large.
+    pub map: Arc<EnvironmentMap>,
+    /// The rotation of the map around the vertical axis, in degrees.
+    pub rotation: f32,
+    /// The factor the radiance of the map is multiplied by.
+    pub intensity: f32,
+}
+
+/// An environment map referenced by a scene file, loaded with [`EnvironmentSettings::load`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnvironmentSettings {
+    /// The `.hdr` or `.exr` image to load, relative to the working directory.
+    pub path: PathBuf,
+    /// The rotation of the map around the vertical axis, in degrees.
+    pub rotation: f32,
+    /// The factor the radiance of the map is multiplied by.
+    pub intensity: f32,
+}
+
+/// The GPU side copy of an [`Environment`], bound as a single bind group.
+#[derive(Debug)]
+pub(crate) struct EnvironmentBuffers {
+    /// A handle to the rotation and intensity of the environment.
+    uniform_buffer: wgpu::Buffer,
+    /// The radiance of the map, or a single black pixel without one.
+    radiance_texture: wgpu::Texture,
+    /// The conditional distribution of every row of the map.
+    conditional_cdf_texture: wgpu::Texture,
+    /// The marginal distribution over the rows of the map.
+    marginal_cdf_texture: wgpu::Texture,
+
+    /// The map currently in the textures.
+    map: Option<Arc<EnvironmentMap>>,
+
+    /// The bind group referencing the uniform and all the textures.
+    bind_group: wgpu::BindGroup,
+    /// The layout of `bind_group`.
+    pub bind_group_layout: wgpu::BindGroupLayout,
+}
+
+/// The placement of an environment, as laid out on the GPU.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+struct EnvironmentUniform {
+    /// The rotation around the vertical axis, in radians.
+    rotation: f32,
+    intensity: f32,
+    /// Whether there is a map to render, rather than the sky color.
+    enabled: u32,
+    padding: u32,
+}
+
+impl EnvironmentMap {
+    /// The largest width or height of a map, the smallest maximum texture size a GPU can have.
+    const MAX_SIZE: u32 = 8192;
+
+    /// Loads an equirectangular `.hdr` or `.exr` image, and builds its sampling distributions.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+
+        let image = image::open(path)
+            .with_context(|| format!("failed to load environment map {}", path.display()))?
+            .into_rgb32f();
+
+        let (width, height) = image.dimensions();
+
+        if width > Self::MAX_SIZE || height > Self::MAX_SIZE {
+            return Err(anyhow!(
+                "environment map {} is {width}x{height}, larger than the maximum of {max}x{max}",
+                path.display(),
+                max = Self::MAX_SIZE,
+            ));
+        }
+
+        let pixels = image
+            .pixels()
+            .map(|pixel| Vec3::from(pixel.0).max(Vec3::ZERO))
+            .collect();
+
+        Ok(Self::from_pixels(path.to_owned(), width, height, pixels))
+    }
+
+    fn from_pixels(path: PathBuf, width: u32, height: u32, pixels: Vec<Vec3>) -> Self {
+        let luminances: Vec<f32> = pixels
+            .iter()
+            .map(|pixel| pixel.dot(Vec3::new(0.2126, 0.7152, 0.0722)))
+            .collect();
+
+        // a completely black map is sampled uniformly instead
+        let black = luminances.iter().all(|&luminance| luminance <= 0.0);
+
+        let mut conditional_cdf = Vec::with_capacity(pixels.len());
+        let mut row_weights = Vec::with_capacity(height as usize);
+
+        for (y, row) in luminances.chunks_exact(width as usize).enumerate() {
+            // rows near the poles cover less of the sphere than rows at the horizon
+            let sin_theta = ((y as f32 + 0.5) / height as f32 * std::f32::consts::PI).sin();
+            let weights = row.iter().map(|&luminance| {
+                if black {
+                    sin_theta
+                } else {
+                    luminance * sin_theta
+                }
+            });
+
+            let (cdf, total) = cumulative_distribution(weights);
+
+            conditional_cdf.extend(cdf);
+            row_weights.push(total);
+        }
+
+        let (marginal_cdf, _) = cumulative_distribution(row_weights);
+
+        Self {
+            path,
+            width,
+            height,
+            pixels,
+            conditional_cdf,
+            marginal_cdf,
+        }
+    }
+
+    /// The file the map was loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The radiance of the pixel in the given column and row.
+    pub(crate) fn pixel(&self, x: u32, y: u32) -> Vec3 {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// The cumulative distribution over the columns of a row, ending at one.
+    pub(crate) fn conditional_cdf(&self, y: u32) -> &[f32] {
+        let start = (y * self.width) as usize;
+
+        &self.conditional_cdf[start..start + self.width as usize]
+    }
+
+    /// The cumulative distribution over the rows, ending at one.
+    pub(crate) fn marginal_cdf(&self) -> &[f32] {
+        &self.marginal_cdf
+    }
+}
+
+impl fmt::Debug for EnvironmentMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnvironmentMap")
+            .field("path", &self.path)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Environment {
+    /// Places a map around the scene without any rotation, at its original intensity.
+    pub fn new(map: Arc<EnvironmentMap>) -> Self {
+        Self {
+            map,
+            rotation: 0.0,
+            intensity: 1.0,
+        }
+    }
+
+    /// The settings to store the environment in a scene file with.
+    pub fn settings(&self) -> EnvironmentSettings {
+        EnvironmentSettings {
+            path: self.map.path().to_owned(),
+            rotation: self.rotation,
+            intensity: self.intensity,
+        }
+    }
+}
+
+impl EnvironmentSettings {
+    /// Loads the map, and places it as described.
+    pub fn load(&self) -> Result<Environment> {
+        Ok(Environment {
+            map: Arc::new(EnvironmentMap::load(&self.path)?),
+            rotation: self.rotation,
+            intensity: self.intensity,
+        })
+    }
+}
+
+impl Default for EnvironmentSettings {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::new(),
+            rotation: 0.0,
+            intensity: 1.0,
+        }
+    }
+}
+
+impl EnvironmentBuffers {
+    /// Creates the buffers, initially without any map.
+    pub fn new(device: &Device) -> Self {
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Environment Uniform Buffer"),
+            contents: bytemuck::bytes_of(&EnvironmentUniform::new(None)),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let (radiance_texture, conditional_cdf_texture, marginal_cdf_texture) =
+            Self::create_textures(device, None);
+
+        let bind_group_layout = Self::create_bind_group_layout(device);
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &uniform_buffer,
+            [
+                &radiance_texture,
+                &conditional_cdf_texture,
+                &marginal_cdf_texture,
+            ],
+        );
+
+        Self {
+            uniform_buffer,
+            radiance_texture,
+            conditional_cdf_texture,
+            marginal_cdf_texture,
+            map: None,
+            bind_group,
+            bind_group_layout,
+        }
+    }
+
+    /// Uploads the placement of the environment, and its map if it changed.
+    pub fn update(&mut self, device: &Device, queue: &Queue, environment: Option<&Environment>) {
+        let map = environment.map(|environment| &environment.map);
+
+        let map_changed = match (&self.map, map) {
+            (Some(current), Some(map)) => !Arc::ptr_eq(current, map),
+            (current, map) => current.is_some() != map.is_some(),
+        };
+
+        if map_changed {
+            self.map = map.cloned();
+
+            let textures = Self::create_textures(device, self.map.as_deref());
+            (
+                self.radiance_texture,
+                self.conditional_cdf_texture,
+                self.marginal_cdf_texture,
+            ) = textures;
+
+            self.write_textures(queue);
+
+            self.bind_group = Self::create_bind_group(
+                device,
+                &self.bind_group_layout,
+                &self.uniform_buffer,
+                [
+                    &self.radiance_texture,
+                    &self.conditional_cdf_texture,
+                    &self.marginal_cdf_texture,
+                ],
+            );
+        }
+
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&EnvironmentUniform::new(environment)),
+        );
+    }
+
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+
+    /// Creates the radiance, conditional and marginal textures, sized for the map.
+    fn create_textures(
+        device: &Device,
+        map: Option<&EnvironmentMap>,
+    ) -> (Texture, Texture, Texture) {
+        let (width, height) = map.map_or((1, 1), |map| (map.width, map.height));
+
+        let create_texture = |label, width, height, format| {
+            device.create_texture(&TextureDescriptor {
+                label: Some(label),
+                size: Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            })
+        };
+
+        (
+            create_texture(
+                "Environment Radiance Texture",
+                width,
+                height,
+                TextureFormat::Rgba16Float,
+            ),
+            create_texture(
+                "Environment Conditional CDF Texture",
+                width,
+                height,
+                TextureFormat::R32Float,
+            ),
+            create_texture(
+                "Environment Marginal CDF Texture",
+                height,
+                1,
+                TextureFormat::R32Float,
+            ),
+        )
+    }
+
+    /// Uploads the current map into the textures, or clears them without one.
+    fn write_textures(&self, queue: &Queue) {
+        let Some(map) = &self.map else {
+            // textures start out zeroed, which is all a missing map needs
+            return;
+        };
+
+        let radiance: Vec<u16> = map
+            .pixels
+            .iter()
+            .flat_map(|pixel| pixel.extend(1.0).to_array())
+            .map(|channel| f16::from_f32(channel).to_bits())
+            .collect();
+
+        let write_texture = |texture: &Texture, bytes: &[u8], bytes_per_pixel: u32| {
+            queue.write_texture(
+                texture.as_image_copy(),
+                bytes,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(texture.width() * bytes_per_pixel),
+                    rows_per_image: None,
+                },
+                texture.size(),
+            );
+        };
+
+        write_texture(&self.radiance_texture, bytemuck::cast_slice(&radiance), 8);
+        write_texture(
+            &self.conditional_cdf_texture,
+            bytemuck::cast_slice(&map.conditional_cdf),
+            4,
+        );
+        write_texture(
+            &self.marginal_cdf_texture,
+            bytemuck::cast_slice(&map.marginal_cdf),
+            4,
+        );
+    }
+
+    fn create_bind_group_layout(device: &Device) -> BindGroupLayout {
+        let texture_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: false },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Environment Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                texture_entry(2),
+                texture_entry(3),
+            ],
+        })
+    }
+
+    /// Creates a bind group with the uniform buffer first, followed by the textures in order.
+    fn create_bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        uniform_buffer: &Buffer,
+        textures: [&Texture; 3],
+    ) -> BindGroup {
+        let views = textures.map(|texture| texture.create_view(&TextureViewDescriptor::default()));
+
+        let mut entries = vec![BindGroupEntry {
+            binding: 0,
+            resource: uniform_buffer.as_entire_binding(),
+        }];
+
+        entries.extend(views.iter().enumerate().map(|(i, view)| BindGroupEntry {
+            binding: i as u32 + 1,
+            resource: BindingResource::TextureView(view),
+        }));
+
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Environment Bind Group"),
+            entries: &entries,
+            layout,
+        })
+    }
+}
+
+impl EnvironmentUniform {
+    fn new(environment: Option<&Environment>) -> Self {
+        Self {
+            rotation: environment.map_or(0.0, |environment| environment.rotation.to_radians()),
+            intensity: environment.map_or(0.0, |environment| environment.intensity),
+            enabled: environment.is_some() as u32,
+            padding: 0,
+        }
+    }
+}
+
+/// Accumulates the weights into a distribution ending at one, returning it along with the sum of
+/// the weights. Weights summing to zero give a uniform distribution instead.
+fn cumulative_distribution(weights: impl IntoIterator<Item = f32>) -> (Vec<f32>, f32) {
+    let mut cdf: Vec<f32> = weights
+        .into_iter()
+        .scan(0.0, |sum, weight| {
+            *sum += weight;
+            Some(*sum)
+        })
+        .collect();
+
+    let count = cdf.len();
+    let total = cdf.last().copied().unwrap_or(0.0);
+
+    for (i, value) in cdf.iter_mut().enumerate() {
+        *value = if total > 0.0 {
+            *value / total
+        } else {
+            (i + 1) as f32 / count as f32
+        };
+    }
+
+    // rounding must not leave any chance of falling past the end
+    if let Some(last) = cdf.last_mut() {
+        *last = 1.0;
+    }
+
+    (cdf, total)
+}
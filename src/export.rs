@@ -0,0 +1,103 @@
+use std::path::Path;
+
+use anyhow::Result;
+use glam::Vec3;
+
+use crate::tone_mapping::ToneMapping;
+
+/// An image of linear radiance values, such as the averaged contents of the accumulation buffer.
+#[derive(Debug, Clone)]
+pub struct RadianceImage {
+    /// The width of the image in pixels.
+    pub width: u32,
+    /// The height of the image in pixels.
+    pub height: u32,
+    /// The radiance of every pixel, in rows from top to bottom.
+    pub pixels: Vec<Vec3>,
+}
+
+impl RadianceImage {
+    /// Maps every pixel to a linear display color, as it is shown in the viewer.
+    pub fn tone_mapped(&self, tone_mapping: &ToneMapping) -> Self {
+        Self {
+            width: self.width,
+            height: self.height,
+            pixels: self
+                .pixels
+                .iter()
+                .map(|&pixel| tone_mapping.apply(pixel))
+                .collect(),
+        }
+    }
+
+    /// Converts the image to 8-bit sRGB, clamping any radiance above one.
+    pub fn to_srgb8(&self) -> image::RgbImage {
+        let bytes = self
+            .pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_array())
+            .map(|channel| (linear_to_srgb(channel.clamp(0.0, 1.0)) * 255.0).round() as u8)
+            .collect();
+
+        image::RgbImage::from_raw(self.width, self.height, bytes)
+            .expect("pixel count should match the image dimensions")
+    }
+
+    /// Converts the image to linear 32-bit floats, without any clamping.
+    pub fn to_linear_f32(&self) -> image::Rgb32FImage {
+        let floats = self
+            .pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_array())
+            .collect();
+
+        image::Rgb32FImage::from_raw(self.width, self.height, floats)
+            .expect("pixel count should match the image dimensions")
+    }
+
+    /// Writes the image as an exr if the path has an `exr` extension, and as a png otherwise.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        match image::ImageFormat::from_path(&path) {
+            Ok(image::ImageFormat::OpenExr) => self.save_exr(path),
+            _ => self.save_png(path),
+        }
+    }
+
+    /// Writes the image like [`RadianceImage::save`], but tone mapped first when it is written as a
+    /// png. Exrs always keep the linear radiance.
+    pub fn save_tone_mapped(
+        &self,
+        path: impl AsRef<Path>,
+        tone_mapping: &ToneMapping,
+    ) -> Result<()> {
+        match image::ImageFormat::from_path(&path) {
+            Ok(image::ImageFormat::OpenExr) => self.save_exr(path),
+            _ => self.tone_mapped(tone_mapping).save_png(path),
+        }
+    }
+
+    /// Writes the image as an 8-bit sRGB png.
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.to_srgb8()
+            .save_with_format(path, image::ImageFormat::Png)?;
+
+        Ok(())
+    }
+
+    /// Writes the image as a linear floating point exr.
+    pub fn save_exr(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.to_linear_f32()
+            .save_with_format(path, image::ImageFormat::OpenExr)?;
+
+        Ok(())
+    }
+}
+
+/// Applies the sRGB transfer function to a linear value in the range [0, 1].
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
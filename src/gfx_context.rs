@@ -1,4 +1,7 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use egui_wgpu::ScreenDescriptor;
 
@@ -6,10 +9,15 @@ use glam::*;
 use wgpu::{util::*, *};
 use winit::{dpi::PhysicalSize, window::Window};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 use crate::camera::Camera;
+use crate::environment::{Environment, EnvironmentBuffers};
+use crate::export::RadianceImage;
+use crate::pixel_filter::PixelFilter;
 use crate::scene::*;
+use crate::sky::ProceduralSky;
+use crate::tone_mapping::ToneMapping;
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Zeroable, bytemuck::NoUninit)]
@@ -23,6 +31,48 @@ pub struct RenderUniform {
     pub accumulate: bool,
 
     pub _padding: [u8; 3],
+
+    /// The direction pointing towards the center of the sun of the procedural sky.
+    pub sun_direction: glam::Vec3,
+    pub turbidity: f32,
+    /// The angle between the center and the edge of the sun disk, in radians.
+    pub sun_angular_radius: f32,
+    pub sun_intensity: f32,
+    /// Whether rays that don't hit anything see the procedural sky rather than `sky_color`.
+    pub procedural_sky: bool,
+
+    pub _sky_padding: [u8; 7],
+
+    /// The position of the first traced pixel within the whole image, when it is traced in tiles.
+    pub tile_offset: glam::UVec2,
+    /// The size of the whole image in pixels, of which `dimensions` are traced.
+    pub image_dimensions: glam::UVec2,
+
+    /// The number of jittered samples traced for every pixel in each frame.
+    pub samples_per_frame: u32,
+    /// The [`PixelFilter`] weighing the samples, by its index.
+    pub pixel_filter: u32,
+
+    /// The radius of the camera's lens, or zero for a pinhole.
+    pub aperture_radius: f32,
+    /// The distance in front of the camera which is in perfect focus.
+    pub focus_distance: f32,
+    /// The number of blades shaping the aperture into a polygon, or zero for a circle.
+    pub aperture_blades: u32,
+
+    /// The camera's [`Projection`](crate::Projection), by its index.
+    pub projection: u32,
+    pub near: f32,
+    pub far: f32,
+    /// The full horizontal and vertical angles the image spans, in radians.
+    pub field_of_view: glam::Vec2,
+
+    /// The range of the triangles of the selected mesh, which is empty without one.
+    pub selected_triangles: glam::UVec2,
+    /// The index of the selected sphere, or -1 without one.
+    pub selected_sphere: i32,
+
+    pub _selection_padding: [u32; 3],
 }
 
 #[derive(Debug)]
@@ -30,6 +80,19 @@ pub struct AccumulationBuffer {
     pub bind_group: wgpu::BindGroup,
     bind_group_layout: wgpu::BindGroupLayout,
     buffer: wgpu::Buffer,
+    /// Whether the selected object is seen through each pixel, written by `cs_selection`.
+    selection_mask: wgpu::Buffer,
+}
+
+/// The tone mapping parameters of the blit pass, as laid out in `blit.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Zeroable, bytemuck::NoUninit)]
+struct DisplayUniform {
+    exposure_scale: f32,
+    white_point: f32,
+    tone_mapper: u32,
+
+    _padding: u32,
 }
 
 pub struct GfxContext {
@@ -37,15 +100,15 @@ pub struct GfxContext {
     pub device: wgpu::Device,
     /// The queue of commands being staged to be sent to the `device`.
     pub queue: wgpu::Queue,
-    /// The series of steps that data takes while moving through the rendering process.
-    pipeline: wgpu::RenderPipeline,
+    /// The compute pipeline tracing paths into the accumulation buffer.
+    trace_pipeline: wgpu::ComputePipeline,
+    /// The compute pipeline finding the pixels covered by the selected object.
+    selection_pipeline: wgpu::ComputePipeline,
+    /// The pipeline drawing the accumulated image onto the render target.
+    blit_pipeline: wgpu::RenderPipeline,
 
-    /// The actual window, being targeted by the `surface`
-    window: Arc<Window>,
-    /// A reference to the surface being rendered onto.
-    surface: wgpu::Surface<'static>,
-    /// The configuration of the `surface`.
-    surface_config: wgpu::SurfaceConfiguration,
+    /// Where the final image of each frame ends up.
+    target: RenderTarget,
 
     /// The main egui renderer.
     egui_renderer: egui_wgpu::Renderer,
@@ -56,9 +119,74 @@ pub struct GfxContext {
     render_uniform_buffer: wgpu::Buffer,
 
     accumulation_buffer: AccumulationBuffer,
+    /// The size of the image being traced, which the accumulation buffer holds.
+    render_size: PhysicalSize<u32>,
+    /// The size of the traced image relative to the render target.
+    render_scale: f32,
+    /// The number of samples per pixel after which tracing stops, or `None` to accumulate forever.
+    pub target_samples: Option<u32>,
+    /// The number of jittered samples traced for every pixel in each frame.
+    pub samples_per_frame: u32,
+    /// The filter weighing the samples of every pixel.
+    pub pixel_filter: PixelFilter,
+    /// When the accumulation was last reset.
+    accumulation_start: Instant,
+    /// When the accumulation reached `target_samples`, if it has.
+    accumulation_end: Option<Instant>,
+    /// Whether the frame prepared by the last [`GfxContext::update_buffers`] is traced, decided
+    /// before it is counted so that the frame reaching `target_samples` is still traced.
+    trace_frame: bool,
+
+    /// The scene data uploaded to the GPU.
+    scene_buffers: SceneBuffers,
+    /// The object outlined on top of the image, if any.
+    pub selection: Option<SceneObject>,
+
+    /// The environment map lighting the scene in place of the sky color, if any.
+    pub environment: Option<Environment>,
+    /// The procedural sky lighting the scene in place of the sky color, if any. An environment
+    /// map takes precedence over it.
+    pub procedural_sky: Option<ProceduralSky>,
+    /// The environment uploaded to the GPU.
+    environment_buffers: EnvironmentBuffers,
+
+    /// How the accumulated radiance is mapped to displayed colors.
+    pub tone_mapping: ToneMapping,
+    display_bind_group: wgpu::BindGroup,
+    display_uniform_buffer: wgpu::Buffer,
+}
+
+/// The destination that frames are rendered onto.
+enum RenderTarget {
+    /// A surface presented to a window every frame.
+    Window {
+        /// The actual window, being targeted by the `surface`
+        window: Arc<Window>,
+        /// A reference to the surface being rendered onto.
+        surface: wgpu::Surface<'static>,
+        /// The configuration of the `surface`.
+        config: wgpu::SurfaceConfiguration,
+    },
+    /// A texture which is never presented, used when there is no display to render to.
+    Offscreen {
+        /// The texture being rendered onto.
+        texture: wgpu::Texture,
+    },
 }
 
 impl GfxContext {
+    /// The format of the offscreen texture used by headless contexts.
+    const OFFSCREEN_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+    /// The width and height of the workgroups of `cs_main`.
+    const TRACE_WORKGROUP_SIZE: u32 = 8;
+    /// The color the window is cleared to before the image is drawn.
+    const CLEAR_COLOR: Color = Color {
+        r: 0.01,
+        g: 0.01,
+        b: 0.01,
+        a: 1.0,
+    };
+
     /// Creates a new renderer given a window as the surface.
     pub async fn new(window: Arc<Window>, camera: &Camera) -> Result<Self> {
         let instance = Instance::new(InstanceDescriptor {
@@ -78,11 +206,56 @@ impl GfxContext {
             .await
             .unwrap();
 
+        let (device, queue) = Self::request_device(&adapter).await?;
+
+        let config = Self::get_surface_config(&adapter, &surface, window.inner_size());
+        surface.configure(&device, &config);
+
+        let target = RenderTarget::Window {
+            window,
+            surface,
+            config,
+        };
+
+        Ok(Self::with_target(device, queue, target, camera))
+    }
+
+    /// Creates a new renderer which draws into an offscreen texture of the given size, without
+    /// requiring a window or display server.
+    ///
+    /// The backend and adapter can be picked with the `WGPU_BACKEND` and `WGPU_ADAPTER_NAME`
+    /// environment variables, which allows selecting a software adapter.
+    pub async fn new_headless(size: PhysicalSize<u32>, camera: &Camera) -> Result<Self> {
+        let instance = Instance::new(InstanceDescriptor {
+            backends: util::backend_bits_from_env().unwrap_or(Backends::all()),
+            flags: InstanceFlags::empty(),
+            ..Default::default()
+        });
+
+        let adapter = util::initialize_adapter_from_env_or_default(&instance, None)
+            .await
+            .ok_or_else(|| anyhow!("failed to find a suitable graphics adapter"))?;
+
+        let (device, queue) = Self::request_device(&adapter).await?;
+
+        let target = RenderTarget::Offscreen {
+            texture: Self::create_offscreen_texture(&device, size),
+        };
+
+        Ok(Self::with_target(device, queue, target, camera))
+    }
+
+    /// Requests a device and queue from the adapter, with the limits required by the renderer.
+    async fn request_device(adapter: &Adapter) -> Result<(Device, Queue)> {
         let (device, queue) = adapter
             .request_device(
                 &DeviceDescriptor {
                     required_limits: Limits {
-                        max_storage_buffer_binding_size: 256000000,
+                        // software adapters may not support large storage buffers
+                        max_storage_buffer_binding_size: adapter
+                            .limits()
+                            .max_storage_buffer_binding_size
+                            .min(256000000),
                         ..Default::default()
                     },
                     ..Default::default()
@@ -91,61 +264,156 @@ impl GfxContext {
             )
             .await?;
 
-        let surface_config = Self::get_surface_config(&adapter, &surface, window.inner_size());
-        surface.configure(&device, &surface_config);
+        Ok((device, queue))
+    }
 
-        let render_uniform = RenderUniform::new(window.inner_size(), camera);
+    /// Creates all the resources shared by every kind of target.
+    fn with_target(device: Device, queue: Queue, target: RenderTarget, camera: &Camera) -> Self {
+        let size = target.size();
+        let format = target.format();
+
+        let render_uniform = RenderUniform::new(size, camera);
         let render_uniform_buffer = render_uniform.create_buffer(&device);
 
         let (render_data_bind_group, render_data_bind_group_layout) =
             Self::create_render_data_bind_group(&device, &render_uniform_buffer);
 
-        let accumulation_buffer = AccumulationBuffer::new(&device, window.inner_size());
+        let display_uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Display Uniform Buffer"),
+            size: size_of::<DisplayUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let (display_bind_group, display_bind_group_layout) =
+            Self::create_display_bind_group(&device, &display_uniform_buffer);
+
+        let accumulation_buffer = AccumulationBuffer::new(&device, size);
+        let scene_buffers = SceneBuffers::new(&device);
+        let environment_buffers = EnvironmentBuffers::new(&device);
 
-        let pipeline = Self::create_pipeline(
+        let trace_shader = device.create_shader_module(include_wgsl!("shader.wgsl"));
+        let trace_bind_group_layouts = [
+            &render_data_bind_group_layout,
+            &accumulation_buffer.bind_group_layout,
+            &scene_buffers.bind_group_layout,
+            &environment_buffers.bind_group_layout,
+        ];
+
+        let trace_pipeline = Self::create_compute_pipeline(
+            &device,
+            &trace_shader,
+            &trace_bind_group_layouts,
+            "cs_main",
+        );
+        let selection_pipeline = Self::create_compute_pipeline(
             &device,
-            &surface_config,
-            device.create_shader_module(include_wgsl!("shader.wgsl")),
+            &trace_shader,
+            &trace_bind_group_layouts,
+            "cs_selection",
+        );
+
+        let blit_pipeline = Self::create_blit_pipeline(
+            &device,
+            format,
+            device.create_shader_module(include_wgsl!("blit.wgsl")),
             &[
                 &render_data_bind_group_layout,
                 &accumulation_buffer.bind_group_layout,
-                &Scene::create_bind_group_layout(&device),
+                &display_bind_group_layout,
             ],
         );
 
-        let egui_renderer =
-            egui_wgpu::Renderer::new(&device, surface_config.format, None, 1, false);
+        let egui_renderer = egui_wgpu::Renderer::new(&device, format, None, 1, false);
 
-        Ok(Self {
+        Self {
             device,
             queue,
-            pipeline,
-            window,
-            surface,
-            surface_config,
+            trace_pipeline,
+            selection_pipeline,
+            blit_pipeline,
+            target,
             egui_renderer,
             render_data_bind_group,
             render_uniform,
             render_uniform_buffer,
             accumulation_buffer,
+            render_size: size,
+            render_scale: 1.0,
+            target_samples: None,
+            samples_per_frame: 1,
+            pixel_filter: PixelFilter::default(),
+            accumulation_start: Instant::now(),
+            accumulation_end: None,
+            trace_frame: true,
+            scene_buffers,
+            selection: None,
+            environment: None,
+            procedural_sky: None,
+            environment_buffers,
+            tone_mapping: ToneMapping::default(),
+            display_bind_group,
+            display_uniform_buffer,
+        }
+    }
+
+    /// Creates the texture rendered onto by headless contexts.
+    fn create_offscreen_texture(device: &Device, size: PhysicalSize<u32>) -> Texture {
+        device.create_texture(&TextureDescriptor {
+            label: Some("Offscreen Render Texture"),
+            size: Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: Self::OFFSCREEN_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
         })
     }
 
-    /// Creates the rendering pipeline.
-    fn create_pipeline(
+    /// Creates a pipeline running one of the compute entry points of `shader.wgsl`, which all
+    /// share the same bind groups.
+    fn create_compute_pipeline(
         device: &Device,
-        surface_config: &SurfaceConfiguration,
+        shader: &ShaderModule,
+        bind_group_layouts: &[&BindGroupLayout],
+        entry_point: &str,
+    ) -> ComputePipeline {
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(&format!("{entry_point} Pipeline Layout")),
+            push_constant_ranges: &[],
+            bind_group_layouts,
+        });
+
+        device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some(&format!("{entry_point} Pipeline")),
+            layout: Some(&pipeline_layout),
+            module: shader,
+            entry_point,
+            compilation_options: PipelineCompilationOptions::default(),
+            cache: None,
+        })
+    }
+
+    /// Creates the pipeline drawing the accumulated image onto the render target.
+    fn create_blit_pipeline(
+        device: &Device,
+        format: TextureFormat,
         shader: ShaderModule,
         bind_group_layouts: &[&BindGroupLayout],
     ) -> RenderPipeline {
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some("Render Pipeline Layout"),
+            label: Some("Blit Pipeline Layout"),
             push_constant_ranges: &[],
             bind_group_layouts,
         });
 
         device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
+            label: Some("Blit Pipeline"),
             layout: Some(&pipeline_layout),
             vertex: VertexState {
                 module: &shader,
@@ -157,7 +425,7 @@ impl GfxContext {
                 module: &shader,
                 entry_point: "fs_main",
                 targets: &[Some(ColorTargetState {
-                    format: surface_config.format,
+                    format,
                     blend: Some(BlendState::REPLACE),
                     write_mask: ColorWrites::ALL,
                 })],
@@ -212,38 +480,86 @@ impl GfxContext {
         }
     }
 
-    /// Resizes the renderer's `config` to match the new given size.
+    /// Resizes the render target to match the new given size.
     pub fn resize(&mut self, size: PhysicalSize<u32>) {
         let PhysicalSize { width, height } = size;
 
         assert!(width > 0, "cannot resize to zero width");
         assert!(height > 0, "cannot resize to zero height");
 
-        self.surface_config.width = width;
-        self.surface_config.height = height;
+        match &mut self.target {
+            RenderTarget::Window {
+                surface, config, ..
+            } => {
+                config.width = width;
+                config.height = height;
+
+                surface.configure(&self.device, config);
+            }
+            RenderTarget::Offscreen { texture } => {
+                *texture = Self::create_offscreen_texture(&self.device, size);
+            }
+        }
+
+        self.set_render_size(self.scaled_render_size());
+    }
+
+    /// The size of the render target in pixels.
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.target.size()
+    }
+
+    /// The size of the traced image relative to the render target.
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// Traces the image at `scale` times the size of the render target, resetting the
+    /// accumulation.
+    pub fn set_render_scale(&mut self, scale: f32) {
+        self.render_scale = scale;
+
+        self.set_render_size(self.scaled_render_size());
+    }
 
-        self.surface.configure(&self.device, &self.surface_config);
+    /// Traces the whole image at the given size, independently of the size of the render target,
+    /// resetting the accumulation.
+    pub fn set_render_size(&mut self, size: PhysicalSize<u32>) {
+        assert!(size.width > 0, "cannot render at zero width");
+        assert!(size.height > 0, "cannot render at zero height");
 
-        self.render_uniform.dimensions = uvec2(width, height);
+        self.render_size = size;
+        self.render_uniform.dimensions = uvec2(size.width, size.height);
+
+        self.set_tile(UVec2::ZERO, size);
+    }
+
+    /// Traces only the tile of the render size at `offset` within an image of `image_size`, which
+    /// is drawn into the matching region of the render target, resetting the accumulation.
+    pub fn set_tile(&mut self, offset: UVec2, image_size: PhysicalSize<u32>) {
+        self.render_uniform.tile_offset = offset;
+        self.render_uniform.image_dimensions = uvec2(image_size.width, image_size.height);
 
         self.reset_accumulation();
     }
 
-    pub fn update_buffers(&mut self, camera: &mut Camera) {
-        let aspect_ratio = {
-            let PhysicalSize { width, height } = self.window.inner_size();
+    fn scaled_render_size(&self) -> PhysicalSize<u32> {
+        let PhysicalSize { width, height } = self.size();
+        let scale = |length: u32| ((length as f32 * self.render_scale).round() as u32).max(1);
 
-            width as f32 / height as f32
-        };
+        PhysicalSize::new(scale(width), scale(height))
+    }
 
-        let projection = camera.calculate_projection(aspect_ratio);
-        let view = camera.calculate_view();
+    /// Uploads the camera and scene for the next frame, resetting the accumulation if the camera
+    /// moved.
+    pub fn update_buffers(&mut self, camera: &mut Camera, scene: &Scene) {
+        let aspect_ratio = {
+            let image_dimensions = self.render_uniform.image_dimensions.as_vec2();
 
-        self.render_uniform.inverse_projection = projection.inverse();
-        self.render_uniform.inverse_view = view.inverse();
+            image_dimensions.x / image_dimensions.y
+        };
 
-        self.render_uniform.time += 0.01;
-        self.render_uniform.frames_accumulated += 1;
+        self.render_uniform.set_camera(camera, aspect_ratio);
 
         if camera.moved {
             camera.moved = false;
@@ -251,6 +567,29 @@ impl GfxContext {
             self.reset_accumulation();
         }
 
+        self.render_uniform
+            .set_procedural_sky(self.procedural_sky.as_ref());
+
+        self.render_uniform.samples_per_frame = self.samples_per_frame.max(1);
+        self.render_uniform.pixel_filter = self.pixel_filter as u32;
+
+        self.render_uniform.set_selection(self.selection, scene);
+
+        self.trace_frame = !self.converged();
+
+        if !self.trace_frame {
+            self.accumulation_end.get_or_insert_with(Instant::now);
+        } else {
+            self.accumulation_end = None;
+
+            self.render_uniform.time += 0.01;
+            self.render_uniform.frames_accumulated += 1;
+        }
+
+        self.scene_buffers.update(&self.device, &self.queue, scene);
+        self.environment_buffers
+            .update(&self.device, &self.queue, self.environment.as_ref());
+
         self.queue.write_buffer(
             &self.render_uniform_buffer,
             0,
@@ -258,14 +597,42 @@ impl GfxContext {
         );
     }
 
-    /// Renders the currently bound vertex buffer onto the `surface`.
+    /// Whether the accumulation has reached `target_samples`, after which no more frames are
+    /// traced and only the accumulated image is displayed.
+    pub fn converged(&self) -> bool {
+        let samples = self.samples_accumulated();
+
+        self.render_uniform.accumulate
+            && self.target_samples.is_some_and(|target| samples >= target)
+    }
+
+    /// The number of samples accumulated for every pixel.
+    pub fn samples_accumulated(&self) -> u32 {
+        self.render_uniform.frames_accumulated * self.render_uniform.samples_per_frame
+    }
+
+    /// The time spent accumulating the current image, which stops once it has converged.
+    pub fn accumulation_time(&self) -> Duration {
+        self.accumulation_end
+            .unwrap_or_else(Instant::now)
+            .duration_since(self.accumulation_start)
+    }
+
+    /// Renders the scene and the egui output onto the window's `surface`, tracing a new frame
+    /// unless the accumulation has converged.
+    ///
+    /// # Panics
+    /// Panics if the context was created with [`GfxContext::new_headless`].
     pub fn render(
         &mut self,
         egui_ctx: &egui::Context,
         egui_output: egui::FullOutput,
-        scene: &Scene,
     ) -> Result<(), SurfaceError> {
-        let output = self.surface.get_current_texture()?;
+        let RenderTarget::Window { surface, .. } = &self.target else {
+            panic!("cannot present a frame from a headless context");
+        };
+
+        let output = surface.get_current_texture()?;
         let view = output.texture.create_view(&TextureViewDescriptor {
             label: Some("Render View"),
             ..Default::default()
@@ -277,7 +644,15 @@ impl GfxContext {
                 label: Some("Render Encoder"),
             });
 
-        self.main_render_pass(&mut encoder, &view, scene);
+        if self.trace_frame {
+            self.trace_pass(&mut encoder);
+        }
+
+        if self.selection.is_some() {
+            self.selection_pass(&mut encoder);
+        }
+
+        self.blit_pass(&mut encoder, &view, LoadOp::Clear(Self::CLEAR_COLOR));
         self.egui_render_pass(&mut encoder, &view, egui_ctx, egui_output);
 
         self.queue.submit(std::iter::once(encoder.finish()));
@@ -286,19 +661,206 @@ impl GfxContext {
         Ok(())
     }
 
-    fn main_render_pass(&self, encoder: &mut CommandEncoder, view: &TextureView, scene: &Scene) {
+    /// Renders the scene onto the offscreen texture, leaving the parts outside the current tile
+    /// untouched, tracing a new frame unless the accumulation has converged.
+    ///
+    /// # Panics
+    /// Panics if the context was not created with [`GfxContext::new_headless`].
+    pub fn render_offscreen(&mut self) {
+        let RenderTarget::Offscreen { texture } = &self.target else {
+            panic!("cannot render offscreen with a window context");
+        };
+
+        let view = texture.create_view(&TextureViewDescriptor {
+            label: Some("Offscreen Render View"),
+            ..Default::default()
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Offscreen Render Encoder"),
+            });
+
+        if self.trace_frame {
+            self.trace_pass(&mut encoder);
+        }
+
+        self.blit_pass(&mut encoder, &view, LoadOp::Load);
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    /// Copies the contents of the offscreen texture back to the CPU, as tightly packed rows of
+    /// 8-bit sRGB RGBA pixels.
+    ///
+    /// # Panics
+    /// Panics if the context was not created with [`GfxContext::new_headless`].
+    pub fn read_offscreen_texture(&self) -> Result<Vec<u8>> {
+        let RenderTarget::Offscreen { texture } = &self.target else {
+            panic!("cannot read back the surface of a window context");
+        };
+
+        let PhysicalSize { width, height } = self.size();
+
+        let unpadded_bytes_per_row = width * 4;
+        let padded_bytes_per_row =
+            unpadded_bytes_per_row.next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let buffer = self.device.create_buffer(&BufferDescriptor {
+            label: Some("Offscreen Readback Buffer"),
+            size: padded_bytes_per_row as u64 * height as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Offscreen Readback Encoder"),
+            });
+
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            texture.size(),
+        );
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let pixels = self
+            .read_buffer(&buffer)?
+            .chunks_exact(padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+            .copied()
+            .collect();
+
+        Ok(pixels)
+    }
+
+    /// Copies the accumulated radiance back to the CPU, averaged over all the accumulated samples.
+    pub fn read_accumulation(&self) -> Result<RadianceImage> {
+        if !self.render_uniform.accumulate {
+            return Err(anyhow!(
+                "accumulation is disabled, there is no image to read"
+            ));
+        }
+
+        let PhysicalSize { width, height } = self.render_size;
+        let accumulated = self.read_accumulation_buffer()?;
+
+        // the samples are weighed by the pixel filter, with the sum of their weights in `w`
+        let pixels = accumulated
+            .iter()
+            .map(|pixel| {
+                if pixel.w > 0.0 {
+                    pixel.xyz() / pixel.w
+                } else {
+                    Vec3::ZERO
+                }
+            })
+            .collect();
+
+        Ok(RadianceImage {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Copies the raw contents of the accumulation buffer back to the CPU.
+    fn read_accumulation_buffer(&self) -> Result<Vec<Vec4>> {
+        let source = &self.accumulation_buffer.buffer;
+
+        let buffer = self.device.create_buffer(&BufferDescriptor {
+            label: Some("Accumulation Readback Buffer"),
+            size: source.size(),
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Accumulation Readback Encoder"),
+            });
+
+        encoder.copy_buffer_to_buffer(source, 0, &buffer, 0, source.size());
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let bytes = self.read_buffer(&buffer)?;
+
+        Ok(bytemuck::pod_collect_to_vec(&bytes))
+    }
+
+    /// Maps a buffer with `MAP_READ` usage, blocking until all submitted work is done, and copies
+    /// out its contents.
+    fn read_buffer(&self, buffer: &Buffer) -> Result<Vec<u8>> {
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+
+        self.device.poll(Maintain::Wait);
+        receiver.recv()??;
+
+        let bytes = slice.get_mapped_range().to_vec();
+        buffer.unmap();
+
+        Ok(bytes)
+    }
+
+    fn trace_pass(&self, encoder: &mut CommandEncoder) {
+        self.compute_pass(encoder, &self.trace_pipeline, "Trace Pass");
+    }
+
+    /// Finds the pixels covered by the selected object again, since it may have changed even
+    /// when the accumulation has converged.
+    fn selection_pass(&self, encoder: &mut CommandEncoder) {
+        self.compute_pass(encoder, &self.selection_pipeline, "Selection Pass");
+    }
+
+    /// Runs a compute pipeline over every pixel of the render size.
+    fn compute_pass(&self, encoder: &mut CommandEncoder, pipeline: &ComputePipeline, label: &str) {
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some(label),
+            timestamp_writes: None,
+        });
+
+        compute_pass.set_pipeline(pipeline);
+
+        compute_pass.set_bind_group(0, &self.render_data_bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.accumulation_buffer.bind_group, &[]);
+        compute_pass.set_bind_group(2, self.scene_buffers.bind_group(), &[]);
+        compute_pass.set_bind_group(3, self.environment_buffers.bind_group(), &[]);
+
+        let PhysicalSize { width, height } = self.render_size;
+
+        compute_pass.dispatch_workgroups(
+            width.div_ceil(Self::TRACE_WORKGROUP_SIZE),
+            height.div_ceil(Self::TRACE_WORKGROUP_SIZE),
+            1,
+        );
+    }
+
+    fn blit_pass(&self, encoder: &mut CommandEncoder, view: &TextureView, load: LoadOp<Color>) {
         let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-            label: Some("Render Pass"),
+            label: Some("Blit Pass"),
             color_attachments: &[Some(RenderPassColorAttachment {
-                view: &view,
+                view,
                 resolve_target: None,
                 ops: Operations {
-                    load: LoadOp::Clear(Color {
-                        r: 0.01,
-                        g: 0.01,
-                        b: 0.01,
-                        a: 1.0,
-                    }),
+                    load,
                     store: StoreOp::Store,
                 },
             })],
@@ -306,11 +868,33 @@ impl GfxContext {
             ..Default::default()
         });
 
-        render_pass.set_pipeline(&self.pipeline);
+        let display_uniform = DisplayUniform {
+            exposure_scale: self.tone_mapping.exposure_scale(),
+            white_point: self.tone_mapping.white_point,
+            tone_mapper: self.tone_mapping.tone_mapper as u32,
+            _padding: 0,
+        };
+
+        self.queue.write_buffer(
+            &self.display_uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[display_uniform]),
+        );
+
+        // the tile covers the same fraction of the render target as it does of the whole image
+        let target_size = self.size();
+        let scale = vec2(target_size.width as f32, target_size.height as f32)
+            / self.render_uniform.image_dimensions.as_vec2();
+
+        let offset = self.render_uniform.tile_offset.as_vec2() * scale;
+        let size = self.render_uniform.dimensions.as_vec2() * scale;
+
+        render_pass.set_viewport(offset.x, offset.y, size.x, size.y, 0.0, 1.0);
+        render_pass.set_pipeline(&self.blit_pipeline);
 
         render_pass.set_bind_group(0, &self.render_data_bind_group, &[]);
         render_pass.set_bind_group(1, &self.accumulation_buffer.bind_group, &[]);
-        render_pass.set_bind_group(2, &scene.bind_group(), &[]);
+        render_pass.set_bind_group(2, &self.display_bind_group, &[]);
 
         render_pass.draw(0..6, 0..1);
     }
@@ -322,15 +906,19 @@ impl GfxContext {
         ctx: &egui::Context,
         full_output: egui::FullOutput,
     ) {
+        let RenderTarget::Window { window, config, .. } = &self.target else {
+            return;
+        };
+
         let tris = ctx.tessellate(full_output.shapes, full_output.pixels_per_point);
         let screen_descriptor = ScreenDescriptor {
-            size_in_pixels: [self.surface_config.width, self.surface_config.height],
-            pixels_per_point: self.window.scale_factor() as f32,
+            size_in_pixels: [config.width, config.height],
+            pixels_per_point: window.scale_factor() as f32,
         };
 
         for (id, image_delta) in &full_output.textures_delta.set {
             self.egui_renderer
-                .update_texture(&self.device, &self.queue, *id, &image_delta);
+                .update_texture(&self.device, &self.queue, *id, image_delta);
         }
 
         self.egui_renderer.update_buffers(
@@ -376,7 +964,7 @@ impl GfxContext {
             label: Some("Render Information Bind Group Layout"),
             entries: &[BindGroupLayoutEntry {
                 binding: 0,
-                visibility: ShaderStages::FRAGMENT,
+                visibility: ShaderStages::COMPUTE | ShaderStages::FRAGMENT,
                 ty: BindingType::Buffer {
                     ty: BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -398,11 +986,60 @@ impl GfxContext {
         (bind_group, bind_group_layout)
     }
 
+    fn create_display_bind_group(
+        device: &Device,
+        uniform_buffer: &Buffer,
+    ) -> (BindGroup, BindGroupLayout) {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Display Bind Group Layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Display Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        (bind_group, bind_group_layout)
+    }
+
     pub fn reset_accumulation(&mut self) {
-        self.accumulation_buffer
-            .reset(&self.device, self.window.inner_size());
+        let size = self.render_size;
+
+        self.accumulation_buffer.reset(&self.device, size);
 
-        self.render_uniform.frames_accumulated = 1;
+        self.render_uniform.frames_accumulated = 0;
+        self.accumulation_start = Instant::now();
+        self.accumulation_end = None;
+    }
+}
+
+impl RenderTarget {
+    fn size(&self) -> PhysicalSize<u32> {
+        match self {
+            Self::Window { config, .. } => PhysicalSize::new(config.width, config.height),
+            Self::Offscreen { texture } => PhysicalSize::new(texture.width(), texture.height()),
+        }
+    }
+
+    fn format(&self) -> TextureFormat {
+        match self {
+            Self::Window { config, .. } => config.format,
+            Self::Offscreen { texture } => texture.format(),
+        }
     }
 }
 
@@ -410,17 +1047,86 @@ impl RenderUniform {
     fn new(size: PhysicalSize<u32>, camera: &Camera) -> Self {
         let PhysicalSize { width, height } = size;
 
-        Self {
-            inverse_projection: camera
-                .calculate_projection(width as f32 / height as f32)
-                .inverse(),
-            inverse_view: camera.calculate_view().inverse(),
+        let mut uniform = Self {
+            inverse_projection: Mat4::IDENTITY,
+            inverse_view: Mat4::IDENTITY,
             sky_color: vec3(0.01, 0.01, 0.01),
             time: 0.0,
             dimensions: uvec2(size.width, size.height),
             frames_accumulated: 0,
             accumulate: true,
             _padding: [0; 3],
+            sun_direction: Vec3::Y,
+            turbidity: 0.0,
+            sun_angular_radius: 0.0,
+            sun_intensity: 0.0,
+            procedural_sky: false,
+            _sky_padding: [0; 7],
+            tile_offset: UVec2::ZERO,
+            image_dimensions: uvec2(size.width, size.height),
+            samples_per_frame: 1,
+            pixel_filter: 0,
+            aperture_radius: 0.0,
+            focus_distance: 0.0,
+            aperture_blades: 0,
+            projection: 0,
+            near: 0.0,
+            far: 0.0,
+            field_of_view: Vec2::ZERO,
+            selected_triangles: UVec2::ZERO,
+            selected_sphere: -1,
+            _selection_padding: [0; 3],
+        };
+
+        uniform.set_camera(camera, width as f32 / height as f32);
+
+        uniform
+    }
+
+    /// Copies the placement, projection and thin lens of the camera.
+    fn set_camera(&mut self, camera: &Camera, aspect_ratio: f32) {
+        let (near, far) = camera.clip_planes();
+
+        self.inverse_projection = camera.calculate_projection(aspect_ratio).inverse();
+        self.inverse_view = camera.calculate_view().inverse();
+
+        self.projection = camera.projection as u32;
+        self.near = near;
+        self.far = far;
+        self.field_of_view = camera.field_of_view(aspect_ratio);
+
+        self.aperture_radius = camera.aperture_radius.max(0.0);
+        self.focus_distance = camera.focus_distance;
+        self.aperture_blades = camera.aperture_blades;
+    }
+
+    /// Outlines the given object, or nothing.
+    fn set_selection(&mut self, selection: Option<SceneObject>, scene: &Scene) {
+        self.selected_triangles = UVec2::ZERO;
+        self.selected_sphere = -1;
+
+        match selection {
+            Some(SceneObject::Sphere(index)) if index < scene.spheres().len() => {
+                self.selected_sphere = index as i32;
+            }
+            Some(SceneObject::Mesh(index)) => {
+                if let Some(triangles) = scene.mesh_triangles(index) {
+                    self.selected_triangles = uvec2(triangles.start, triangles.end);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Switches to the procedural sky with the given parameters, or back to the sky color.
+    fn set_procedural_sky(&mut self, sky: Option<&ProceduralSky>) {
+        self.procedural_sky = sky.is_some();
+
+        if let Some(sky) = sky {
+            self.sun_direction = sky.sun_direction();
+            self.turbidity = sky.model_turbidity();
+            self.sun_angular_radius = sky.sun_angular_radius();
+            self.sun_intensity = sky.sun_intensity;
         }
     }
 
@@ -435,30 +1141,33 @@ impl RenderUniform {
 
 impl AccumulationBuffer {
     fn new(device: &Device, size: PhysicalSize<u32>) -> Self {
-        let buffer_size = Self::calculate_bytes(size);
-
-        let buffer = Self::create_buffer(device, buffer_size);
+        let buffer = Self::create_buffer(device, Self::calculate_bytes(size));
+        let selection_mask = Self::create_selection_mask(device, size);
+
+        let storage_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE | ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
 
         let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: Some("Accumulation Buffer Bind Group Layout"),
-            entries: &[BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStages::FRAGMENT,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Storage { read_only: false },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
+            entries: &[storage_entry(0), storage_entry(1)],
         });
 
-        let bind_group = Self::create_bind_group(device, &bind_group_layout, &buffer);
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &buffer, &selection_mask);
 
         Self {
             bind_group,
             bind_group_layout,
             buffer,
+            selection_mask,
         }
     }
 
@@ -470,24 +1179,95 @@ impl AccumulationBuffer {
         device.create_buffer(&BufferDescriptor {
             label: Some("Accumulation Storage Buffer"),
             size: bytes,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Creates the mask holding one `u32` for every pixel.
+    fn create_selection_mask(device: &Device, size: PhysicalSize<u32>) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
+            label: Some("Selection Mask Storage Buffer"),
+            size: size.width as u64 * size.height as u64 * size_of::<u32>() as u64,
             usage: BufferUsages::STORAGE,
             mapped_at_creation: false,
         })
     }
 
-    fn create_bind_group(device: &Device, layout: &BindGroupLayout, buffer: &Buffer) -> BindGroup {
+    fn create_bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        buffer: &Buffer,
+        selection_mask: &Buffer,
+    ) -> BindGroup {
         device.create_bind_group(&BindGroupDescriptor {
             label: Some("Accumulation Buffer Bind Group"),
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: buffer.as_entire_binding(),
-            }],
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: selection_mask.as_entire_binding(),
+                },
+            ],
             layout,
         })
     }
 
     fn reset(&mut self, device: &Device, size: PhysicalSize<u32>) {
         self.buffer = Self::create_buffer(device, Self::calculate_bytes(size));
-        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.buffer);
+        self.selection_mask = Self::create_selection_mask(device, size);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.buffer,
+            &self.selection_mask,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::Material;
+
+    #[test]
+    fn converging_traces_exactly_the_target_samples() {
+        let size = PhysicalSize::new(8, 8);
+        let mut camera = Camera::default();
+
+        let Ok(mut gfx_context) = pollster::block_on(GfxContext::new_headless(size, &camera))
+        else {
+            eprintln!("skipping, as there is no graphics adapter");
+            return;
+        };
+
+        let scene = Scene::with_objects(Vec::new(), vec![Material::default()]);
+
+        for (target, samples_per_frame) in [(1, 1), (6, 1), (6, 2), (12, 4)] {
+            gfx_context.render_uniform.accumulate = true;
+            gfx_context.target_samples = Some(target);
+            gfx_context.samples_per_frame = samples_per_frame;
+            // every sample weighs one
+            gfx_context.pixel_filter = PixelFilter::Box;
+            gfx_context.reset_accumulation();
+
+            // keep rendering well past convergence
+            for _ in 0..target * 2 {
+                gfx_context.update_buffers(&mut camera, &scene);
+                gfx_context.render_offscreen();
+            }
+
+            assert!(gfx_context.converged());
+            assert_eq!(gfx_context.samples_accumulated(), target);
+
+            let accumulated = gfx_context.read_accumulation_buffer().unwrap();
+
+            for pixel in accumulated {
+                assert_eq!(pixel.w, target as f32, "{samples_per_frame} per frame");
+            }
+        }
     }
 }
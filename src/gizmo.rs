@@ -0,0 +1,572 @@
+use egui::{Color32, Context, Id, LayerId, Order, Painter, Pos2, Rect, Shape, Stroke};
+use glam::{vec2, BVec3, Vec2, Vec3};
+
+use crate::{
+    camera::{Camera, CameraRay},
+    history::Edit,
+    scene::{Scene, SceneObject},
+};
+
+/// What dragging the handles of a [`Gizmo`] does to the selected object.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GizmoMode {
+    #[default]
+    Translate,
+    Scale,
+}
+
+/// A part of a [`Gizmo`] that can be dragged, which constrains how the object is transformed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Handle {
+    /// Along one of the world axes, by its index.
+    Axis(usize),
+    /// Within the plane perpendicular to one of the world axes, by its index.
+    Plane(usize),
+    /// Within the plane facing the camera when translating, or along every axis at once when
+    /// scaling.
+    Center,
+}
+
+/// A handle being dragged.
+#[derive(Debug, Clone, Copy)]
+struct Drag {
+    handle: Handle,
+    /// The center of the object when the drag started, which it is scaled around.
+    origin: Vec3,
+    /// The point on the constraint of the handle which was under the cursor when the drag started.
+    start: Vec3,
+    /// Where the cursor was when the drag started, in points.
+    start_pointer: Pos2,
+    /// The translation or the scale applied to the object since the drag started.
+    applied: Vec3,
+}
+
+/// Where the handles of a [`Gizmo`] are drawn on the screen, in points.
+struct Layout {
+    center: Pos2,
+    /// The ends of the axis handles, which start at `center`.
+    axes: [Option<Pos2>; 3],
+    /// The corners of the plane handles.
+    planes: [Option<[Pos2; 4]>; 3],
+}
+
+/// Handles drawn over the image around the selected object, which move or scale it when dragged.
+#[derive(Debug, Clone)]
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    /// Whether the center of the object is kept on a grid while translating it, and scales are
+    /// rounded to multiples of a step. Holding ctrl while dragging inverts this.
+    pub snapping: bool,
+    /// The spacing of the grid the center of the object is kept on when snapping.
+    pub translate_step: f32,
+    /// The factor scales are rounded to multiples of when snapping.
+    pub scale_step: f32,
+
+    /// The handle under the cursor, if any.
+    hovered: Option<Handle>,
+    /// The handle being dragged, if any.
+    drag: Option<Drag>,
+}
+
+/// The directions of the world axes, in the order of their handles.
+const AXES: [Vec3; 3] = [Vec3::X, Vec3::Y, Vec3::Z];
+/// The colors of the handles along and perpendicular to each axis.
+const AXIS_COLORS: [Color32; 3] = [
+    Color32::from_rgb(230, 60, 60),
+    Color32::from_rgb(90, 200, 60),
+    Color32::from_rgb(60, 120, 240),
+];
+/// The color of the handle under the cursor or being dragged.
+const HIGHLIGHT_COLOR: Color32 = Color32::from_rgb(255, 200, 40);
+
+/// The length of the axis handles on the screen, in points.
+const AXIS_LENGTH: f32 = 90.0;
+/// How close the cursor has to be to a handle to grab it, and the radius of the center handle,
+/// in points.
+const GRAB_RADIUS: f32 = 7.0;
+/// The distance of the plane handles from the center, and their size, as fractions of the length
+/// of the axis handles.
+const PLANE_OFFSET: f32 = 0.25;
+const PLANE_SIZE: f32 = 0.2;
+/// The smallest factor an object can be scaled by in a single drag.
+const MIN_SCALE: f32 = 0.01;
+
+impl GizmoMode {
+    /// Every mode, in the order they are listed in the ui.
+    pub const ALL: [Self; 2] = [Self::Translate, Self::Scale];
+
+    /// The name shown in the ui.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Translate => "translate",
+            Self::Scale => "scale",
+        }
+    }
+}
+
+impl Gizmo {
+    /// Switches between translating and scaling, letting go of any handle.
+    pub fn toggle_mode(&mut self) {
+        self.drag = None;
+        self.mode = match self.mode {
+            GizmoMode::Translate => GizmoMode::Scale,
+            GizmoMode::Scale => GizmoMode::Translate,
+        };
+    }
+
+    /// Whether the cursor is over a handle or dragging one, in which case the mouse shouldn't
+    /// move the camera or pick objects.
+    pub fn is_active(&self) -> bool {
+        self.hovered.is_some() || self.drag.is_some()
+    }
+
+    /// Lets go of any handle, for when the gizmo isn't shown.
+    pub fn hide(&mut self) {
+        self.hovered = None;
+        self.drag = None;
+    }
+
+    /// Draws the handles around an object over the whole screen and transforms the object as they
+    /// are dragged, recording the edits in the history. Returns whether the object was changed.
+    pub fn show(
+        &mut self,
+        ctx: &Context,
+        camera: &Camera,
+        scene: &mut Scene,
+        object: SceneObject,
+    ) -> bool {
+        let screen = ctx.screen_rect();
+
+        let (pointer, any_down, primary_pressed, ctrl) = ctx.input(|i| {
+            (
+                i.pointer.hover_pos(),
+                i.pointer.any_down(),
+                i.pointer.primary_pressed(),
+                i.modifiers.ctrl,
+            )
+        });
+
+        if !any_down {
+            self.drag = None;
+        }
+
+        // the hovered handle only changes while no button is held, so that dragging the camera
+        // across a handle doesn't grab it
+        if self.drag.is_none() && (!any_down || primary_pressed) {
+            let over_ui = ctx.is_pointer_over_area();
+
+            self.hovered = pointer
+                .filter(|_| !over_ui)
+                .and_then(|pointer| self.hit_test(camera, scene, object, screen, pointer));
+        }
+
+        let ray = pointer
+            .and_then(|pointer| camera.world_ray(to_ndc(screen, pointer), screen.aspect_ratio()));
+        let mut edit = None;
+
+        if let (Some(pointer), Some(ray)) = (pointer, ray) {
+            if primary_pressed && self.drag.is_none() {
+                self.start_drag(camera, scene, object, pointer, &ray);
+            }
+
+            let snapping = self.snapping != ctrl;
+            edit = self.drag_to(camera, object, pointer, &ray, snapping);
+        }
+
+        let edited = edit.is_some_and(|edit| match scene.apply(edit) {
+            Ok(()) => true,
+            Err(e) => {
+                log::error!("failed to {} the object: {e:#}", self.mode.name());
+                false
+            }
+        });
+
+        if let Some(layout) = self.layout(camera, scene, object, screen) {
+            let painter = ctx.layer_painter(LayerId::new(Order::Background, Id::new("gizmo")));
+
+            self.draw(&painter, &layout, object);
+        }
+
+        edited
+    }
+
+    /// Grabs the hovered handle, if there is one.
+    fn start_drag(
+        &mut self,
+        camera: &Camera,
+        scene: &Scene,
+        object: SceneObject,
+        pointer: Pos2,
+        ray: &CameraRay,
+    ) {
+        let Some(handle) = self.hovered else {
+            return;
+        };
+
+        let Some((origin, _)) = scene.bounding_sphere(object) else {
+            return;
+        };
+
+        let Some(start) = constraint_point(handle, origin, camera, ray) else {
+            return;
+        };
+
+        let applied = match self.mode {
+            GizmoMode::Translate => Vec3::ZERO,
+            GizmoMode::Scale => Vec3::ONE,
+        };
+
+        self.drag = Some(Drag {
+            handle,
+            origin,
+            start,
+            start_pointer: pointer,
+            applied,
+        });
+    }
+
+    /// The edit transforming the object to follow the cursor along the constraint of the dragged
+    /// handle, if it moved.
+    fn drag_to(
+        &mut self,
+        camera: &Camera,
+        object: SceneObject,
+        pointer: Pos2,
+        ray: &CameraRay,
+        snapping: bool,
+    ) -> Option<Edit> {
+        let drag = self.drag.as_mut()?;
+        let point = constraint_point(drag.handle, drag.origin, camera, ray)?;
+
+        let edit = match self.mode {
+            GizmoMode::Translate => {
+                let mut offset = point - drag.start;
+
+                if snapping {
+                    // only the coordinates the handle moves along are snapped, so that dragging
+                    // along an axis doesn't also move the object across it
+                    let moved = match drag.handle {
+                        Handle::Axis(axis) => AXES[axis].cmpne(Vec3::ZERO),
+                        Handle::Plane(axis) => AXES[axis].cmpeq(Vec3::ZERO),
+                        Handle::Center => BVec3::TRUE,
+                    };
+
+                    let snapped = snap(drag.origin + offset, self.translate_step) - drag.origin;
+
+                    offset = Vec3::select(moved, snapped, offset);
+                }
+
+                if offset == drag.applied {
+                    return None;
+                }
+
+                let step = offset - drag.applied;
+                drag.applied = offset;
+
+                Edit::TranslateObject {
+                    object,
+                    offset: step,
+                }
+            }
+            GizmoMode::Scale => {
+                let (from, to) = (drag.start - drag.origin, point - drag.origin);
+
+                let factor = match drag.handle {
+                    Handle::Axis(axis) => to[axis] / from[axis],
+                    Handle::Plane(_) => to.length() / from.length(),
+                    // the center is too small to scale by the distance from it, so dragging up
+                    // or to the right grows the object instead
+                    Handle::Center => {
+                        let moved = pointer - drag.start_pointer;
+
+                        1.0 + (moved.x - moved.y) / AXIS_LENGTH
+                    }
+                };
+
+                if !factor.is_finite() {
+                    return None;
+                }
+
+                let mut factor = factor.max(MIN_SCALE);
+
+                if snapping {
+                    let step = self.scale_step.max(MIN_SCALE);
+
+                    factor = ((factor / step).round() * step).max(step);
+                }
+
+                let scale = match drag.handle {
+                    Handle::Axis(axis) => Vec3::ONE + AXES[axis] * (factor - 1.0),
+                    Handle::Plane(axis) => Vec3::splat(factor) - AXES[axis] * (factor - 1.0),
+                    Handle::Center => Vec3::splat(factor),
+                };
+
+                if scale == drag.applied {
+                    return None;
+                }
+
+                let step = scale / drag.applied;
+                drag.applied = scale;
+
+                Edit::ScaleObject {
+                    object,
+                    pivot: drag.origin,
+                    scale: step,
+                }
+            }
+        };
+
+        Some(edit)
+    }
+
+    /// Whether the handles along and perpendicular to the axes are shown. Spheres stay round, so
+    /// they can only be scaled with the center handle.
+    fn shows_axes(&self, object: SceneObject) -> bool {
+        !(self.mode == GizmoMode::Scale && matches!(object, SceneObject::Sphere(_)))
+    }
+
+    /// The handle under a point on the screen, if there is one.
+    fn hit_test(
+        &self,
+        camera: &Camera,
+        scene: &Scene,
+        object: SceneObject,
+        screen: Rect,
+        pointer: Pos2,
+    ) -> Option<Handle> {
+        let layout = self.layout(camera, scene, object, screen)?;
+
+        if pointer.distance(layout.center) <= GRAB_RADIUS {
+            return Some(Handle::Center);
+        }
+
+        if !self.shows_axes(object) {
+            return None;
+        }
+
+        let axis = (0..3).find(|&axis| {
+            layout.axes[axis]
+                .is_some_and(|end| segment_distance(pointer, layout.center, end) <= GRAB_RADIUS)
+        });
+
+        let plane = || {
+            (0..3).find(|&axis| {
+                layout.planes[axis].is_some_and(|corners| quad_contains(&corners, pointer))
+            })
+        };
+
+        axis.map(Handle::Axis)
+            .or_else(|| plane().map(Handle::Plane))
+    }
+
+    /// Where the handles around the object are on the screen, or `None` if the object isn't in
+    /// front of the camera.
+    fn layout(
+        &self,
+        camera: &Camera,
+        scene: &Scene,
+        object: SceneObject,
+        screen: Rect,
+    ) -> Option<Layout> {
+        let (center, _) = scene.bounding_sphere(object)?;
+
+        let to_screen = |point: Vec3| {
+            camera
+                .project_point(point, screen.aspect_ratio())
+                .map(|ndc| to_screen(screen, ndc))
+        };
+
+        let screen_center = to_screen(center)?;
+
+        // the handles keep the same size on the screen at any distance, so find how far a small
+        // step up from the center moves on the screen
+        let forward = camera.forward();
+        let up = forward.cross(Vec3::Y).normalize().cross(forward);
+        let step = 0.01 * center.distance(camera.eye).max(1e-3);
+
+        let step_pixels = screen_center.distance(to_screen(center + up * step)?);
+
+        if step_pixels < 1e-6 {
+            return None;
+        }
+
+        let length = AXIS_LENGTH * step / step_pixels;
+
+        let axes = AXES.map(|axis| to_screen(center + axis * length));
+
+        let planes = [0, 1, 2].map(|axis| {
+            let u = AXES[(axis + 1) % 3] * length;
+            let v = AXES[(axis + 2) % 3] * length;
+
+            let (near, far) = (PLANE_OFFSET, PLANE_OFFSET + PLANE_SIZE);
+
+            Some([
+                to_screen(center + u * near + v * near)?,
+                to_screen(center + u * far + v * near)?,
+                to_screen(center + u * far + v * far)?,
+                to_screen(center + u * near + v * far)?,
+            ])
+        });
+
+        Some(Layout {
+            center: screen_center,
+            axes,
+            planes,
+        })
+    }
+
+    fn draw(&self, painter: &Painter, layout: &Layout, object: SceneObject) {
+        let active = self.drag.map(|drag| drag.handle).or(self.hovered);
+
+        let handle_color = |handle: Handle, color: Color32| {
+            if active == Some(handle) {
+                HIGHLIGHT_COLOR
+            } else {
+                color
+            }
+        };
+
+        if self.shows_axes(object) {
+            for (axis, (corners, color)) in layout.planes.iter().zip(AXIS_COLORS).enumerate() {
+                let Some(corners) = corners else {
+                    continue;
+                };
+
+                let color = handle_color(Handle::Plane(axis), color);
+
+                painter.add(Shape::convex_polygon(
+                    corners.to_vec(),
+                    color.gamma_multiply(0.4),
+                    Stroke::new(1.0, color),
+                ));
+            }
+
+            for (axis, (&end, color)) in layout.axes.iter().zip(AXIS_COLORS).enumerate() {
+                let Some(end) = end else {
+                    continue;
+                };
+
+                let color = handle_color(Handle::Axis(axis), color);
+
+                painter.line_segment([layout.center, end], Stroke::new(2.5, color));
+
+                let tip = match self.mode {
+                    GizmoMode::Translate => Shape::circle_filled(end, 5.0, color),
+                    GizmoMode::Scale => Shape::rect_filled(
+                        Rect::from_center_size(end, egui::Vec2::splat(9.0)),
+                        0.0,
+                        color,
+                    ),
+                };
+
+                painter.add(tip);
+            }
+        }
+
+        let stroke = Stroke::new(2.0, handle_color(Handle::Center, Color32::WHITE));
+
+        let center = match self.mode {
+            GizmoMode::Translate => Shape::circle_stroke(layout.center, GRAB_RADIUS, stroke),
+            GizmoMode::Scale => Shape::rect_stroke(
+                Rect::from_center_size(layout.center, egui::Vec2::splat(GRAB_RADIUS * 2.0)),
+                0.0,
+                stroke,
+            ),
+        };
+
+        painter.add(center);
+    }
+}
+
+impl Default for Gizmo {
+    fn default() -> Self {
+        Self {
+            mode: GizmoMode::Translate,
+            snapping: false,
+            translate_step: 0.25,
+            scale_step: 0.1,
+            hovered: None,
+            drag: None,
+        }
+    }
+}
+
+/// The point on the line or plane a handle of an object centered at `origin` is constrained to,
+/// which is under the cursor, or `None` if the ray is parallel to it.
+fn constraint_point(
+    handle: Handle,
+    origin: Vec3,
+    camera: &Camera,
+    ray: &CameraRay,
+) -> Option<Vec3> {
+    match handle {
+        Handle::Axis(axis) => {
+            // the closest point on the axis to the ray
+            let axis = AXES[axis];
+            let to_origin = origin - ray.origin;
+            let cos = axis.dot(ray.direction);
+            let denominator = 1.0 - cos * cos;
+
+            if denominator < 1e-6 {
+                return None;
+            }
+
+            let t = (cos * ray.direction.dot(to_origin) - axis.dot(to_origin)) / denominator;
+
+            Some(origin + axis * t)
+        }
+        Handle::Plane(axis) => intersect_plane(origin, AXES[axis], ray),
+        Handle::Center => intersect_plane(origin, camera.forward(), ray),
+    }
+}
+
+/// Where a ray hits the plane through a point, if it does.
+fn intersect_plane(point: Vec3, normal: Vec3, ray: &CameraRay) -> Option<Vec3> {
+    let cos = normal.dot(ray.direction);
+
+    if cos.abs() < 1e-6 {
+        return None;
+    }
+
+    let distance = normal.dot(point - ray.origin) / cos;
+
+    (distance >= 0.0).then(|| ray.origin + ray.direction * distance)
+}
+
+/// Rounds every component of a vector to the nearest multiple of a step.
+fn snap(value: Vec3, step: f32) -> Vec3 {
+    let step = step.max(1e-4);
+
+    (value / step).round() * step
+}
+
+/// The normalized device coordinate of a point on the screen.
+fn to_ndc(screen: Rect, point: Pos2) -> Vec2 {
+    let uv = (point - screen.min) / screen.size();
+
+    vec2(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0)
+}
+
+/// The point on the screen at a normalized device coordinate.
+fn to_screen(screen: Rect, ndc: Vec2) -> Pos2 {
+    screen.min + egui::vec2(ndc.x + 1.0, 1.0 - ndc.y) * 0.5 * screen.size()
+}
+
+/// The distance from a point to the line segment between two others.
+fn segment_distance(point: Pos2, start: Pos2, end: Pos2) -> f32 {
+    let along = end - start;
+    let t = ((point - start).dot(along) / along.length_sq().max(1e-6)).clamp(0.0, 1.0);
+
+    point.distance(start + along * t)
+}
+
+/// Whether a point is inside a convex quad, with its corners in either winding order.
+fn quad_contains(corners: &[Pos2; 4], point: Pos2) -> bool {
+    let mut sides = (0..4).map(|i| {
+        let (start, end) = (corners[i], corners[(i + 1) % 4]);
+
+        (end - start).x * (point - start).y - (end - start).y * (point - start).x
+    });
+
+    sides.clone().all(|side| side >= 0.0) || sides.all(|side| side <= 0.0)
+}
@@ -0,0 +1,216 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use glam::{Mat3, Mat4, Vec3};
+
+use crate::{
+    camera::{Camera, FovAxis, Projection},
+    obj_import::generate_normals,
+    scene::{Material, Mesh, Vertex},
+};
+
+/// The contents of a glTF file, flattened into what a [`Scene`](crate::Scene) can hold.
+#[derive(Debug, Clone)]
+pub struct GltfScene {
+    /// Every triangle primitive of every mesh node, transformed into world space.
+    pub meshes: Vec<Mesh>,
+    /// The materials of the file, which the material indices of `meshes` index into.
+    pub materials: Vec<Material>,
+    /// The first camera found in the scene, if there is one.
+    pub camera: Option<Camera>,
+}
+
+/// Loads the default scene of a glTF file, or its first scene if there is no default.
+///
+/// Node transforms are baked into the vertices of the meshes, and the material indices of the
+/// meshes index into the returned materials, as expected by
+/// [`Edit::AddMeshes`](crate::Edit::AddMeshes). Primitives without a material share a default
+/// one, and vertex normals are generated for primitives without any. Textures are ignored, and
+/// only the factors of the metallic-roughness materials are used.
+pub fn load_gltf(path: impl AsRef<Path>) -> Result<GltfScene> {
+    let path = path.as_ref();
+
+    let gltf::Gltf { document, blob } = gltf::Gltf::open(path)
+        .with_context(|| format!("failed to load gltf file {}", path.display()))?;
+
+    let buffers = gltf::import_buffers(&document, path.parent(), blob)
+        .with_context(|| format!("failed to load the buffers of {}", path.display()))?;
+
+    let mut importer = Importer {
+        buffers: &buffers,
+        scene: GltfScene {
+            meshes: Vec::new(),
+            materials: document.materials().map(convert_material).collect(),
+            camera: None,
+        },
+        uses_default_material: false,
+    };
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .with_context(|| format!("{} contains no scenes", path.display()))?;
+
+    for node in scene.nodes() {
+        importer.visit_node(&node, Mat4::IDENTITY);
+    }
+
+    let mut scene = importer.scene;
+
+    if importer.uses_default_material {
+        scene.materials.push(Material::default());
+    }
+
+    Ok(scene)
+}
+
+/// The state accumulated while walking the node hierarchy.
+struct Importer<'a> {
+    buffers: &'a [gltf::buffer::Data],
+    scene: GltfScene,
+    /// Whether any primitive uses the default material, appended after the other materials.
+    uses_default_material: bool,
+}
+
+impl Importer<'_> {
+    /// Adds the mesh and camera of a node and all its descendants.
+    fn visit_node(&mut self, node: &gltf::Node, parent_transform: Mat4) {
+        let transform = parent_transform * Mat4::from_cols_array_2d(&node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            self.add_mesh(node, &mesh, transform);
+        }
+
+        if let Some(camera) = node.camera().filter(|_| self.scene.camera.is_none()) {
+            self.scene.camera = Some(Self::camera(&camera, transform));
+        }
+
+        for child in node.children() {
+            self.visit_node(&child, transform);
+        }
+    }
+
+    /// Converts a camera placed with the given transform, keeping its projection and clip planes.
+    fn camera(camera: &gltf::Camera, transform: Mat4) -> Camera {
+        // cameras look down their local negative Z axis
+        let position = transform.transform_point3(Vec3::ZERO);
+        let forward = transform.transform_vector3(Vec3::NEG_Z);
+
+        let mut result = Camera::new_facing(position, forward);
+
+        match camera.projection() {
+            gltf::camera::Projection::Perspective(perspective) => {
+                result.projection = Projection::Perspective;
+                result.fov = perspective.yfov().to_degrees();
+                result.fov_axis = FovAxis::Vertical;
+                result.near = perspective.znear();
+                // an infinite projection keeps the default far plane
+                if let Some(far) = perspective.zfar() {
+                    result.far = far;
+                }
+            }
+            gltf::camera::Projection::Orthographic(orthographic) => {
+                result.projection = Projection::Orthographic;
+                // the magnification is half the height of the view
+                result.orthographic_height = orthographic.ymag() * 2.0;
+                result.near = orthographic.znear();
+                result.far = orthographic.zfar();
+            }
+        }
+
+        result
+    }
+
+    /// Adds every triangle primitive of a mesh, transformed into world space.
+    fn add_mesh(&mut self, node: &gltf::Node, mesh: &gltf::Mesh, transform: Mat4) {
+        let name = mesh
+            .name()
+            .or(node.name())
+            .map_or_else(|| format!("mesh {}", mesh.index()), str::to_owned);
+
+        let normal_transform = Mat3::from_mat4(transform).inverse().transpose();
+
+        for (i, primitive) in mesh.primitives().enumerate() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                log::warn!(
+                    "skipping primitive {i} of {name}, as it is made of {:?} rather than triangles",
+                    primitive.mode()
+                );
+                continue;
+            }
+
+            let reader = primitive.reader(|buffer| {
+                self.buffers
+                    .get(buffer.index())
+                    .map(|data| data.0.as_slice())
+            });
+
+            let Some(positions) = reader.read_positions() else {
+                log::warn!("skipping primitive {i} of {name}, as it has no positions");
+                continue;
+            };
+
+            let positions: Vec<Vec3> = positions
+                .map(|position| transform.transform_point3(Vec3::from(position)))
+                .collect();
+
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+
+            let normals: Vec<Vec3> = match reader.read_normals() {
+                Some(normals) => normals
+                    .map(|normal| (normal_transform * Vec3::from(normal)).normalize_or(Vec3::Y))
+                    .collect(),
+                None => generate_normals(&positions, &indices),
+            };
+
+            let material_index = match primitive.material().index() {
+                Some(index) => index as u32,
+                None => {
+                    self.uses_default_material = true;
+                    self.scene.materials.len() as u32
+                }
+            };
+
+            self.scene.meshes.push(Mesh {
+                name: if mesh.primitives().len() > 1 {
+                    format!("{name} {i}")
+                } else {
+                    name.clone()
+                },
+                vertices: positions
+                    .into_iter()
+                    .zip(normals)
+                    .map(|(position, normal)| Vertex::new(position, normal))
+                    .collect(),
+                indices,
+                material_index,
+            });
+        }
+    }
+}
+
+/// Maps the factors of a metallic-roughness material onto a [`Material`].
+fn convert_material(material: gltf::Material) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+
+    let emission =
+        Vec3::from(material.emissive_factor()) * material.emissive_strength().unwrap_or(1.0);
+    let emission_strength = emission.max_element();
+
+    Material::new(
+        Vec3::from_slice(&pbr.base_color_factor()[..3]),
+        pbr.roughness_factor(),
+        pbr.metallic_factor(),
+    )
+    .with_emission(
+        if emission_strength > 0.0 {
+            emission / emission_strength
+        } else {
+            Vec3::ZERO
+        },
+        emission_strength,
+    )
+}
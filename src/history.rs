@@ -0,0 +1,892 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use glam::Vec3;
+
+use crate::{
+    camera::Camera,
+    scene::{Material, Mesh, Scene, SceneObject, Sphere},
+};
+
+/// A single change made to a [`Scene`] or the camera looking at it, holding enough to undo and
+/// redo it.
+#[derive(Debug, Clone)]
+pub enum Edit {
+    AddSphere {
+        index: usize,
+        sphere: Sphere,
+    },
+    RemoveSphere {
+        index: usize,
+        sphere: Sphere,
+    },
+    ModifySphere {
+        index: usize,
+        before: Sphere,
+        after: Sphere,
+    },
+    /// Meshes imported along with the materials they use, added after every other mesh and
+    /// material. The material indices of the meshes index into `materials`.
+    AddMeshes {
+        meshes: Vec<Mesh>,
+        materials: Vec<Material>,
+    },
+    RemoveMesh {
+        index: usize,
+        mesh: Mesh,
+    },
+    SetMeshMaterial {
+        index: usize,
+        before: u32,
+        after: u32,
+    },
+    TranslateObject {
+        object: SceneObject,
+        offset: Vec3,
+    },
+    ScaleObject {
+        object: SceneObject,
+        pivot: Vec3,
+        scale: Vec3,
+    },
+    AddMaterial {
+        index: usize,
+        material: Material,
+    },
+    /// The removal of a material which no object used.
+    RemoveMaterial {
+        index: usize,
+        material: Material,
+    },
+    ModifyMaterial {
+        index: usize,
+        before: Material,
+        after: Material,
+    },
+    MoveCamera {
+        before: Camera,
+        after: Camera,
+    },
+}
+
+/// The edits made to a [`Scene`], which can be undone and redone in order.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    /// Every recorded edit from oldest to newest, including the undone ones after `position`.
+    edits: Vec<Edit>,
+    /// The number of edits currently applied, from the start of `edits`.
+    position: usize,
+    /// When the last edit was recorded, which it is merged with if it continues it.
+    last_recorded: Option<Instant>,
+    /// Why the edits before the first one were cleared, such as the file loaded in their place.
+    cleared_by: Option<String>,
+}
+
+impl Edit {
+    /// A short description shown in the history panel.
+    pub fn description(&self) -> String {
+        let object_name = |object: &SceneObject| match object {
+            SceneObject::Sphere(index) => format!("sphere {index}"),
+            SceneObject::Mesh(index) => format!("mesh {index}"),
+        };
+
+        match self {
+            Self::AddSphere { index, .. } => format!("add sphere {index}"),
+            Self::RemoveSphere { index, .. } => format!("remove sphere {index}"),
+            Self::ModifySphere { index, .. } => format!("edit sphere {index}"),
+            Self::AddMeshes { meshes, .. } if meshes.len() == 1 => {
+                format!("import {}", meshes[0].name)
+            }
+            Self::AddMeshes { meshes, .. } => format!("import {} meshes", meshes.len()),
+            Self::RemoveMesh { mesh, .. } => format!("remove {}", mesh.name),
+            Self::SetMeshMaterial { index, .. } => format!("set material of mesh {index}"),
+            Self::TranslateObject { object, .. } => format!("move {}", object_name(object)),
+            Self::ScaleObject { object, .. } => format!("scale {}", object_name(object)),
+            Self::AddMaterial { index, .. } => format!("add material {index}"),
+            Self::RemoveMaterial { index, .. } => format!("remove material {index}"),
+            Self::ModifyMaterial { index, .. } => format!("edit material {index}"),
+            Self::MoveCamera { .. } => String::from("move camera"),
+        }
+    }
+
+    /// Checks that the edit fits the scene, so that it can be made without panicking.
+    pub(crate) fn validate(&self, scene: &Scene) -> Result<()> {
+        let spheres_len = scene.spheres().len();
+        let meshes_len = scene.meshes().len();
+        let materials_len = scene.materials().len();
+
+        let exists = |kind: &str, index: usize, len: usize| {
+            if index < len {
+                Ok(())
+            } else {
+                Err(anyhow!(
+                    "there is no {kind} {index}, as there are only {len}"
+                ))
+            }
+        };
+        let insertable = |kind: &str, index: usize, len: usize| {
+            if index <= len {
+                Ok(())
+            } else {
+                Err(anyhow!(
+                    "cannot insert {kind} {index}, as there are only {len}"
+                ))
+            }
+        };
+        let object_exists = |object: &SceneObject| match *object {
+            SceneObject::Sphere(index) => exists("sphere", index, spheres_len),
+            SceneObject::Mesh(index) => exists("mesh", index, meshes_len),
+        };
+
+        match self {
+            Self::AddSphere { index, sphere } => {
+                insertable("sphere", *index, spheres_len)?;
+                exists("material", sphere.material_index as usize, materials_len)
+            }
+            Self::RemoveSphere { index, .. } => exists("sphere", *index, spheres_len),
+            Self::ModifySphere { index, after, .. } => {
+                exists("sphere", *index, spheres_len)?;
+                exists("material", after.material_index as usize, materials_len)
+            }
+            Self::AddMeshes { meshes, materials } => meshes.iter().try_for_each(|mesh| {
+                exists("material", mesh.material_index as usize, materials.len())
+            }),
+            Self::RemoveMesh { index, .. } => exists("mesh", *index, meshes_len),
+            Self::SetMeshMaterial { index, after, .. } => {
+                exists("mesh", *index, meshes_len)?;
+                exists("material", *after as usize, materials_len)
+            }
+            Self::TranslateObject { object, offset } => {
+                if !offset.is_finite() {
+                    return Err(anyhow!("cannot move an object by {offset}"));
+                }
+
+                object_exists(object)
+            }
+            Self::ScaleObject {
+                object,
+                pivot,
+                scale,
+            } => {
+                if !invertible_scale(*scale) || !pivot.is_finite() {
+                    return Err(anyhow!("cannot scale an object by {scale} around {pivot}"));
+                }
+
+                object_exists(object)
+            }
+            Self::AddMaterial { index, .. } => insertable("material", *index, materials_len),
+            Self::RemoveMaterial { index, .. } => {
+                exists("material", *index, materials_len)?;
+
+                if scene.material_in_use(*index) {
+                    return Err(anyhow!(
+                        "cannot remove material {index}, which is still in use"
+                    ));
+                }
+
+                Ok(())
+            }
+            Self::ModifyMaterial { index, .. } => exists("material", *index, materials_len),
+            Self::MoveCamera { .. } => Err(anyhow!(
+                "camera moves don't change the scene, and are recorded with Scene::record_camera_move"
+            )),
+        }
+    }
+
+    /// Makes the edit to the scene, for the first time or again after it was reverted.
+    pub(crate) fn apply(&self, scene: &mut Scene) {
+        match self {
+            Self::AddSphere { index, sphere } => scene.insert_sphere(*index, *sphere),
+            Self::RemoveSphere { index, .. } => {
+                scene.remove_sphere(*index);
+            }
+            Self::ModifySphere { index, after, .. } => scene.spheres_mut()[*index] = *after,
+            Self::AddMeshes { meshes, materials } => {
+                scene.add_meshes(meshes.clone(), materials.clone());
+            }
+            Self::RemoveMesh { index, .. } => {
+                scene.remove_mesh(*index);
+            }
+            Self::SetMeshMaterial { index, after, .. } => scene.set_mesh_material(*index, *after),
+            Self::TranslateObject { object, offset } => scene.translate_object(*object, *offset),
+            Self::ScaleObject {
+                object,
+                pivot,
+                scale,
+            } => scene.scale_object(*object, *pivot, *scale),
+            Self::AddMaterial { index, material } => scene.insert_material(*index, *material),
+            Self::RemoveMaterial { index, .. } => {
+                scene
+                    .remove_material(*index)
+                    .expect("removed materials should be unused");
+            }
+            Self::ModifyMaterial { index, after, .. } => scene.materials_mut()[*index] = *after,
+            // the history moves the camera, as the scene doesn't hold it
+            Self::MoveCamera { .. } => {}
+        }
+    }
+
+    /// Undoes the edit to the scene, which must be the last one applied to it.
+    fn revert(&self, scene: &mut Scene) {
+        match self {
+            Self::AddSphere { index, .. } => {
+                scene.remove_sphere(*index);
+            }
+            Self::RemoveSphere { index, sphere } => scene.insert_sphere(*index, *sphere),
+            Self::ModifySphere { index, before, .. } => scene.spheres_mut()[*index] = *before,
+            Self::AddMeshes { meshes, materials } => {
+                for _ in meshes {
+                    scene.remove_mesh(scene.meshes().len() - 1);
+                }
+
+                for _ in materials {
+                    scene
+                        .remove_material(scene.materials().len() - 1)
+                        .expect("imported materials should be unused once the meshes are removed");
+                }
+            }
+            Self::RemoveMesh { index, mesh } => scene.insert_mesh(*index, mesh.clone()),
+            Self::SetMeshMaterial { index, before, .. } => scene.set_mesh_material(*index, *before),
+            Self::TranslateObject { object, offset } => scene.translate_object(*object, -*offset),
+            Self::ScaleObject {
+                object,
+                pivot,
+                scale,
+            } => scene.scale_object(*object, *pivot, scale.recip()),
+            Self::AddMaterial { index, .. } => {
+                scene
+                    .remove_material(*index)
+                    .expect("added materials should be unused once later edits are undone");
+            }
+            Self::RemoveMaterial { index, material } => scene.insert_material(*index, *material),
+            Self::ModifyMaterial { index, before, .. } => scene.materials_mut()[*index] = *before,
+            Self::MoveCamera { .. } => {}
+        }
+    }
+
+    /// Folds an edit continuing this one into it, such as the next step of a drag, returning
+    /// whether it could.
+    fn merge(&mut self, next: &Edit) -> bool {
+        match (self, next) {
+            (
+                Self::ModifySphere { index, after, .. },
+                Self::ModifySphere {
+                    index: next_index,
+                    after: next_after,
+                    ..
+                },
+            ) if index == next_index => *after = *next_after,
+            (
+                Self::SetMeshMaterial { index, after, .. },
+                Self::SetMeshMaterial {
+                    index: next_index,
+                    after: next_after,
+                    ..
+                },
+            ) if index == next_index => *after = *next_after,
+            (
+                Self::TranslateObject { object, offset },
+                Self::TranslateObject {
+                    object: next_object,
+                    offset: next_offset,
+                },
+            ) if object == next_object => *offset += *next_offset,
+            (
+                Self::ScaleObject {
+                    object,
+                    pivot,
+                    scale,
+                },
+                Self::ScaleObject {
+                    object: next_object,
+                    pivot: next_pivot,
+                    scale: next_scale,
+                },
+            ) if object == next_object
+                && pivot == next_pivot
+                && invertible_scale(*scale * *next_scale) =>
+            {
+                *scale *= *next_scale
+            }
+            (
+                Self::ModifyMaterial { index, after, .. },
+                Self::ModifyMaterial {
+                    index: next_index,
+                    after: next_after,
+                    ..
+                },
+            ) if index == next_index => *after = *next_after,
+            (
+                Self::MoveCamera { after, .. },
+                Self::MoveCamera {
+                    after: next_after, ..
+                },
+            ) => *after = *next_after,
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+impl History {
+    /// Edits recorded within this long of each other are merged when they continue each other,
+    /// so that dragging a value or flying the camera around is undone all at once.
+    const MERGE_INTERVAL: Duration = Duration::from_millis(500);
+    /// The number of edits kept, after which the oldest ones are forgotten.
+    const MAX_EDITS: usize = 256;
+
+    /// Adds an edit which was just made, forgetting any edits that were undone.
+    pub fn record(&mut self, edit: Edit) {
+        let now = Instant::now();
+        let recent = self
+            .last_recorded
+            .is_some_and(|last| now.duration_since(last) < Self::MERGE_INTERVAL);
+
+        self.edits.truncate(self.position);
+        self.last_recorded = Some(now);
+
+        if let Some(last) = self.edits.last_mut().filter(|_| recent) {
+            if last.merge(&edit) {
+                return;
+            }
+        }
+
+        self.edits.push(edit);
+
+        if self.edits.len() > Self::MAX_EDITS {
+            self.edits.remove(0);
+        }
+
+        self.position = self.edits.len();
+    }
+
+    /// Forgets every edit, for when the scene they were made to is replaced.
+    pub(crate) fn clear(&mut self, reason: String) {
+        *self = Self {
+            cleared_by: Some(reason),
+            ..Self::default()
+        };
+    }
+
+    /// Why the history was last cleared, if it ever was.
+    pub fn cleared_by(&self) -> Option<&str> {
+        self.cleared_by.as_deref()
+    }
+
+    /// Every recorded edit from oldest to newest, including the undone ones.
+    pub fn edits(&self) -> &[Edit] {
+        &self.edits
+    }
+
+    /// The number of edits currently applied, from the start of [`History::edits`].
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.position > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.position < self.edits.len()
+    }
+
+    /// Reverts the last applied edit, returning whether there was one.
+    pub(crate) fn undo(&mut self, scene: &mut Scene, camera: &mut Camera) -> bool {
+        if !self.can_undo() {
+            return false;
+        }
+
+        self.position -= 1;
+
+        let edit = &self.edits[self.position];
+        edit.revert(scene);
+
+        if let Edit::MoveCamera { before, .. } = edit {
+            set_camera(camera, before);
+        }
+
+        // an undone edit is never continued
+        self.last_recorded = None;
+
+        true
+    }
+
+    /// Applies the first undone edit again, returning whether there was one.
+    pub(crate) fn redo(&mut self, scene: &mut Scene, camera: &mut Camera) -> bool {
+        if !self.can_redo() {
+            return false;
+        }
+
+        let edit = &self.edits[self.position];
+        edit.apply(scene);
+
+        if let Edit::MoveCamera { after, .. } = edit {
+            set_camera(camera, after);
+        }
+
+        self.position += 1;
+        self.last_recorded = None;
+
+        true
+    }
+}
+
+/// Whether a scale can be undone by dividing by it, so neither zero nor infinite along any axis.
+fn invertible_scale(scale: Vec3) -> bool {
+    scale.is_finite() && !scale.cmpeq(Vec3::ZERO).any()
+}
+
+/// Moves the camera to where an edit left it.
+fn set_camera(camera: &mut Camera, state: &Camera) {
+    *camera = Camera {
+        moved: true,
+        ..*state
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3;
+
+    use super::*;
+    use crate::scene::Vertex;
+
+    /// The contents of a mesh, which can be compared.
+    type MeshContents = (String, Vec<Vec3>, Vec<Vec3>, Vec<u32>, u32);
+
+    fn contents(scene: &Scene) -> (Vec<Sphere>, Vec<Material>, Vec<MeshContents>) {
+        let meshes = scene
+            .meshes()
+            .iter()
+            .map(|mesh| {
+                (
+                    mesh.name.clone(),
+                    mesh.vertices.iter().map(|vertex| vertex.position).collect(),
+                    mesh.vertices.iter().map(|vertex| vertex.normal).collect(),
+                    mesh.indices.clone(),
+                    mesh.material_index,
+                )
+            })
+            .collect();
+
+        (scene.spheres().to_vec(), scene.materials().to_vec(), meshes)
+    }
+
+    fn triangle(name: &str, material_index: u32) -> Mesh {
+        Mesh {
+            name: name.to_owned(),
+            vertices: vec![
+                Vertex::new(vec3(0.0, 0.0, 0.0), Vec3::Z),
+                Vertex::new(vec3(1.0, 0.0, 0.0), Vec3::Z),
+                Vertex::new(vec3(0.0, 1.0, 0.0), Vec3::Z),
+            ],
+            indices: vec![0, 1, 2],
+            material_index,
+        }
+    }
+
+    /// Three materials, with the middle one unused, two spheres and two meshes.
+    fn scene() -> Scene {
+        let mut scene = Scene::with_objects(
+            vec![
+                Sphere::new(vec3(0.0, 1.0, 0.0), 1.0, 0),
+                Sphere::new(vec3(2.0, 1.0, 0.0), 0.5, 2),
+            ],
+            vec![
+                Material::new(vec3(0.8, 0.2, 0.2), 0.5, 0.0),
+                Material::new(vec3(0.2, 0.8, 0.2), 0.5, 0.0),
+                Material::new(vec3(0.2, 0.2, 0.8), 0.5, 0.0),
+            ],
+        );
+
+        scene.add_mesh(triangle("first", 0));
+        scene.add_mesh(triangle("second", 2));
+
+        scene
+    }
+
+    fn add_sphere(scene: &mut Scene, sphere: Sphere) {
+        let index = scene.spheres().len();
+
+        scene.apply(Edit::AddSphere { index, sphere }).unwrap();
+    }
+
+    fn edits() -> Vec<Edit> {
+        let sphere = Sphere::new(vec3(-2.0, 1.0, 0.0), 0.75, 2);
+        let material = Material::new(Vec3::ONE, 0.0, 1.0);
+        let moved_camera = Camera::new_facing(vec3(5.0, 2.0, 5.0), Vec3::NEG_ONE);
+
+        vec![
+            Edit::AddSphere { index: 1, sphere },
+            Edit::RemoveSphere {
+                index: 0,
+                sphere: scene().spheres()[0],
+            },
+            Edit::ModifySphere {
+                index: 1,
+                before: scene().spheres()[1],
+                after: sphere,
+            },
+            Edit::AddMeshes {
+                meshes: vec![triangle("imported", 1), triangle("other", 0)],
+                materials: vec![material, material],
+            },
+            Edit::RemoveMesh {
+                index: 0,
+                mesh: triangle("first", 0),
+            },
+            Edit::SetMeshMaterial {
+                index: 1,
+                before: 2,
+                after: 1,
+            },
+            Edit::TranslateObject {
+                object: SceneObject::Sphere(0),
+                offset: vec3(0.5, -1.0, 2.0),
+            },
+            Edit::TranslateObject {
+                object: SceneObject::Mesh(1),
+                offset: vec3(0.5, -1.0, 2.0),
+            },
+            Edit::ScaleObject {
+                object: SceneObject::Sphere(1),
+                pivot: vec3(1.0, 1.0, 0.0),
+                scale: Vec3::splat(2.0),
+            },
+            Edit::ScaleObject {
+                object: SceneObject::Mesh(0),
+                pivot: vec3(1.0, 1.0, 0.0),
+                scale: vec3(2.0, 0.5, 4.0),
+            },
+            Edit::AddMaterial { index: 1, material },
+            Edit::RemoveMaterial {
+                index: 1,
+                material: scene().materials()[1],
+            },
+            Edit::ModifyMaterial {
+                index: 2,
+                before: scene().materials()[2],
+                after: material,
+            },
+            Edit::MoveCamera {
+                before: Camera::default(),
+                after: moved_camera,
+            },
+        ]
+    }
+
+    #[test]
+    fn every_edit_is_undone_and_redone() {
+        for edit in edits() {
+            let mut scene = scene();
+            let mut camera = Camera::default();
+            let description = edit.description();
+
+            let before = (contents(&scene), camera);
+
+            // the camera is moved first, and the move only recorded
+            if let Edit::MoveCamera { before, after } = edit {
+                camera = Camera {
+                    moved: true,
+                    ..after
+                };
+                scene.record_camera_move(before, after);
+            } else {
+                scene.apply(edit).unwrap();
+            }
+
+            let after = (contents(&scene), camera);
+
+            assert_ne!(before, after, "{description} changed nothing");
+            scene.validate().unwrap();
+
+            assert!(scene.undo(&mut camera));
+            assert_eq!(
+                (
+                    contents(&scene),
+                    Camera {
+                        moved: false,
+                        ..camera
+                    }
+                ),
+                before
+            );
+            assert!(!scene.history().can_undo(), "{description}");
+
+            assert!(scene.redo(&mut camera));
+            assert_eq!(contents(&scene), after.0, "{description}");
+            assert_eq!(camera, after.1, "{description}");
+            assert!(!scene.history().can_redo(), "{description}");
+        }
+    }
+
+    #[test]
+    fn continuing_edits_are_merged() {
+        let mut scene = scene();
+        let mut camera = Camera::default();
+        let original = contents(&scene);
+
+        let sphere = |radius| Sphere::new(vec3(2.0, 1.0, 0.0), radius, 2);
+
+        scene
+            .apply(Edit::ModifySphere {
+                index: 1,
+                before: sphere(0.5),
+                after: sphere(0.6),
+            })
+            .unwrap();
+        scene
+            .apply(Edit::ModifySphere {
+                index: 1,
+                before: sphere(0.6),
+                after: sphere(0.7),
+            })
+            .unwrap();
+
+        for _ in 0..3 {
+            scene
+                .apply(Edit::TranslateObject {
+                    object: SceneObject::Mesh(0),
+                    offset: Vec3::X,
+                })
+                .unwrap();
+        }
+
+        for _ in 0..2 {
+            scene
+                .apply(Edit::ScaleObject {
+                    object: SceneObject::Mesh(0),
+                    pivot: Vec3::ZERO,
+                    scale: Vec3::splat(3.0),
+                })
+                .unwrap();
+        }
+
+        let edits = scene.history().edits();
+
+        assert_eq!(edits.len(), 3);
+        assert!(matches!(
+            edits[0],
+            Edit::ModifySphere { before, after, .. } if before == sphere(0.5) && after == sphere(0.7)
+        ));
+        assert!(
+            matches!(edits[1], Edit::TranslateObject { offset, .. } if offset == Vec3::X * 3.0)
+        );
+        assert!(matches!(edits[2], Edit::ScaleObject { scale, .. } if scale == Vec3::splat(9.0)));
+
+        assert!(scene.undo(&mut camera));
+        assert!(scene.undo(&mut camera));
+        assert!(scene.undo(&mut camera));
+        assert_eq!(contents(&scene), original);
+    }
+
+    #[test]
+    fn unrelated_edits_are_not_merged() {
+        let mut scene = scene();
+        let mut camera = Camera::default();
+
+        let translate = |object| Edit::TranslateObject {
+            object,
+            offset: Vec3::Y,
+        };
+
+        scene.apply(translate(SceneObject::Sphere(0))).unwrap();
+        scene.apply(translate(SceneObject::Sphere(1))).unwrap();
+        scene.apply(translate(SceneObject::Mesh(0))).unwrap();
+        scene
+            .apply(Edit::ScaleObject {
+                object: SceneObject::Mesh(0),
+                pivot: Vec3::ZERO,
+                scale: Vec3::splat(2.0),
+            })
+            .unwrap();
+        // a different pivot
+        scene
+            .apply(Edit::ScaleObject {
+                object: SceneObject::Mesh(0),
+                pivot: Vec3::ONE,
+                scale: Vec3::splat(2.0),
+            })
+            .unwrap();
+
+        assert_eq!(scene.history().edits().len(), 5);
+
+        // an undone edit is never continued, even right after it was undone
+        assert!(scene.undo(&mut camera));
+        scene
+            .apply(Edit::ScaleObject {
+                object: SceneObject::Mesh(0),
+                pivot: Vec3::ZERO,
+                scale: Vec3::splat(2.0),
+            })
+            .unwrap();
+
+        assert_eq!(scene.history().edits().len(), 5);
+        assert_eq!(scene.history().position(), 5);
+    }
+
+    #[test]
+    fn new_edits_drop_the_undone_ones() {
+        let mut scene = scene();
+        let mut camera = Camera::default();
+
+        add_sphere(&mut scene, Sphere::new(Vec3::ZERO, 1.0, 0));
+        scene
+            .apply(Edit::AddMaterial {
+                index: 3,
+                material: Material::default(),
+            })
+            .unwrap();
+        add_sphere(&mut scene, Sphere::new(Vec3::ONE, 1.0, 0));
+
+        assert!(scene.undo(&mut camera));
+        assert!(scene.undo(&mut camera));
+        assert!(scene.history().can_redo());
+
+        add_sphere(&mut scene, Sphere::new(Vec3::NEG_ONE, 1.0, 0));
+
+        let history = scene.history();
+
+        assert_eq!(history.edits().len(), 2);
+        assert_eq!(history.position(), 2);
+        assert!(!history.can_redo());
+        assert!(!scene.redo(&mut camera));
+        assert_eq!(scene.spheres().len(), 4);
+        assert_eq!(scene.materials().len(), 3);
+    }
+
+    #[test]
+    fn old_edits_are_forgotten() {
+        let mut scene = scene();
+        let mut camera = Camera::default();
+        let extra = 10;
+
+        for i in 0..History::MAX_EDITS + extra {
+            add_sphere(&mut scene, Sphere::new(Vec3::X * i as f32, 1.0, 0));
+        }
+
+        assert_eq!(scene.history().edits().len(), History::MAX_EDITS);
+        assert_eq!(scene.history().position(), History::MAX_EDITS);
+
+        scene.go_to_edit(0, &mut camera);
+
+        // only the forgotten spheres are left
+        assert_eq!(scene.history().position(), 0);
+        assert!(!scene.history().can_undo());
+        assert_eq!(scene.spheres().len(), 2 + extra);
+        assert_eq!(
+            scene.spheres()[2 + extra - 1].position.x,
+            (extra - 1) as f32
+        );
+
+        scene.go_to_edit(History::MAX_EDITS, &mut camera);
+
+        assert_eq!(scene.spheres().len(), 2 + History::MAX_EDITS + extra);
+        assert!(!scene.history().can_redo());
+    }
+
+    #[test]
+    fn undoing_imports_restores_material_indices() {
+        let mut scene = scene();
+        let mut camera = Camera::default();
+        let original = contents(&scene);
+
+        scene
+            .apply(Edit::AddMeshes {
+                meshes: vec![triangle("imported", 1), triangle("other", 0)],
+                materials: vec![Material::default(), Material::new(Vec3::ONE, 0.0, 1.0)],
+            })
+            .unwrap();
+
+        // the imported meshes use the imported materials
+        let material_indices = |scene: &Scene| -> Vec<u32> {
+            scene
+                .meshes()
+                .iter()
+                .map(|mesh| mesh.material_index)
+                .collect()
+        };
+
+        assert_eq!(material_indices(&scene), [0, 2, 4, 3]);
+        assert_eq!(scene.history().edits()[0].description(), "import 2 meshes");
+
+        assert!(scene.undo(&mut camera));
+        assert_eq!(contents(&scene), original);
+
+        // removing an unused material moves the indices after it down, and undoing it back up
+        scene
+            .apply(Edit::RemoveMaterial {
+                index: 1,
+                material: scene.materials()[1],
+            })
+            .unwrap();
+
+        assert_eq!(material_indices(&scene), [0, 1]);
+        assert_eq!(scene.spheres()[1].material_index, 1);
+
+        assert!(scene.undo(&mut camera));
+        assert_eq!(contents(&scene), original);
+
+        assert!(scene.redo(&mut camera));
+        assert_eq!(material_indices(&scene), [0, 1]);
+    }
+
+    #[test]
+    fn invalid_edits_change_nothing() {
+        let mut scene = scene();
+        let original = contents(&scene);
+
+        let invalid = [
+            Edit::RemoveSphere {
+                index: 2,
+                sphere: scene.spheres()[0],
+            },
+            Edit::AddSphere {
+                index: 0,
+                sphere: Sphere::new(Vec3::ZERO, 1.0, 3),
+            },
+            Edit::TranslateObject {
+                object: SceneObject::Mesh(2),
+                offset: Vec3::X,
+            },
+            Edit::TranslateObject {
+                object: SceneObject::Mesh(0),
+                offset: Vec3::INFINITY,
+            },
+            Edit::ScaleObject {
+                object: SceneObject::Sphere(0),
+                pivot: Vec3::ZERO,
+                scale: vec3(2.0, 0.0, 2.0),
+            },
+            Edit::ScaleObject {
+                object: SceneObject::Mesh(0),
+                pivot: Vec3::ZERO,
+                scale: Vec3::NAN,
+            },
+            Edit::ScaleObject {
+                object: SceneObject::Mesh(0),
+                pivot: Vec3::NAN,
+                scale: Vec3::ONE,
+            },
+            // still used by the first sphere and mesh
+            Edit::RemoveMaterial {
+                index: 0,
+                material: scene.materials()[0],
+            },
+            Edit::MoveCamera {
+                before: Camera::default(),
+                after: Camera::default(),
+            },
+        ];
+
+        for edit in invalid {
+            let description = edit.description();
+
+            assert!(scene.apply(edit).is_err(), "{description} was applied");
+            assert_eq!(contents(&scene), original, "{description}");
+            assert!(scene.history().edits().is_empty(), "{description}");
+        }
+    }
+}
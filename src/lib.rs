@@ -1,24 +1,74 @@
+//! A gpu path tracer, usable both as an interactive viewer and as a library.
+//!
+//! Scenes are built out of [`Sphere`]s, triangle [`Mesh`]es and [`Material`]s, and rendered from a [`Camera`] into a
+//! [`RadianceImage`] by a [`Renderer`], or with the one-off [`render_to_image`]. The
+//! [`CpuRenderer`] traces the same scenes without a graphics adapter.
+
 mod app;
+mod bvh;
 mod camera;
+mod camera_path;
+mod cli;
+mod cpu_tracer;
+mod environment;
+mod export;
 mod gfx_context;
+mod gizmo;
+mod gltf_import;
+mod history;
+mod obj_import;
+mod pixel_filter;
+mod renderer;
 mod scene;
+mod scene_file;
+mod sky;
+mod tone_mapping;
+
+pub use camera::{Camera, CameraMode, CameraRay, FovAxis, Projection};
+pub use camera_path::{CameraAnimation, CameraKeyframe, CameraPath, Turntable};
+pub use cpu_tracer::CpuRenderer;
+pub use environment::{Environment, EnvironmentMap, EnvironmentSettings};
+pub use export::RadianceImage;
+pub use gltf_import::{load_gltf, GltfScene};
+pub use history::{Edit, History};
+pub use obj_import::load_obj;
+pub use pixel_filter::PixelFilter;
+pub use renderer::{render_to_image, RenderSettings, Renderer};
+pub use scene::{Material, Mesh, Scene, SceneObject, Sphere, Vertex};
+pub use scene_file::SceneFile;
+pub use sky::ProceduralSky;
+pub use tone_mapping::{ToneMapper, ToneMapping};
+
+use std::path::PathBuf;
 
 use anyhow::Result;
 use app::AppHandler;
 
+use clap::Parser;
+use cli::{Cli, Command, ViewArgs};
 use log::LevelFilter;
 use winit::event_loop::{ControlFlow, EventLoop};
 
+/// Parses the command line arguments, and either opens the viewer or renders headlessly.
 pub fn run() -> Result<()> {
     env_logger::builder()
         .filter_level(LevelFilter::Warn)
         .filter_module("raytracer", LevelFilter::Debug)
         .init();
 
+    match Cli::parse().command {
+        Some(Command::Render(args)) => args.run(),
+        Some(Command::View(ViewArgs { scene })) => view(scene),
+        None => view(None),
+    }
+}
+
+/// Opens the interactive viewer, optionally loading a scene file at startup.
+fn view(scene_path: Option<PathBuf>) -> Result<()> {
     let event_loop = EventLoop::new().unwrap();
 
     event_loop.set_control_flow(ControlFlow::Poll);
-    event_loop.run_app(&mut AppHandler::new())?;
+    event_loop.run_app(&mut AppHandler::new(scene_path))?;
 
     Ok(())
 }
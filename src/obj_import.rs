@@ -0,0 +1,143 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use glam::Vec3;
+
+use crate::scene::{Material, Mesh, Vertex};
+
+/// Loads every group of an obj file as a mesh, along with the materials from its mtl files.
+///
+/// The material indices of the meshes index into the returned materials, as expected by
+/// [`Edit::AddMeshes`](crate::Edit::AddMeshes). Groups without a material share a default one,
+/// and vertex normals are generated for groups without any.
+pub fn load_obj(path: impl AsRef<Path>) -> Result<(Vec<Mesh>, Vec<Material>)> {
+    let path = path.as_ref();
+
+    let (models, mtl_materials) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)
+        .with_context(|| format!("failed to load obj file {}", path.display()))?;
+
+    let mut materials: Vec<Material> = match mtl_materials {
+        Ok(materials) => materials.iter().map(convert_material).collect(),
+        Err(e) => {
+            log::warn!("failed to load the materials of {}: {e}", path.display());
+            Vec::new()
+        }
+    };
+
+    let default_material = materials.len() as u32;
+    let mut uses_default_material = false;
+
+    let meshes = models
+        .into_iter()
+        .map(|model| {
+            let material_index = match model.mesh.material_id {
+                Some(id) if (id as u32) < default_material => id as u32,
+                _ => {
+                    uses_default_material = true;
+                    default_material
+                }
+            };
+
+            Mesh {
+                name: model.name,
+                vertices: convert_vertices(&model.mesh),
+                indices: model.mesh.indices,
+                material_index,
+            }
+        })
+        .collect();
+
+    if uses_default_material {
+        materials.push(Material::default());
+    }
+
+    Ok((meshes, materials))
+}
+
+/// Maps the parameters of an mtl material onto a [`Material`].
+fn convert_material(material: &tobj::Material) -> Material {
+    let default = Material::default();
+
+    // the emission color and the pbr extension aren't part of the original spec, so tobj doesn't
+    // parse them
+    let emission = unknown_channels(material, "Ke")
+        .and_then(|channels| Some(Vec3::from_slice(channels.get(..3)?)))
+        .unwrap_or(Vec3::ZERO);
+
+    let emission_strength = emission.max_element();
+
+    let metallic = unknown_channels(material, "Pm")
+        .and_then(|channels| channels.first().copied())
+        .unwrap_or(default.metallic);
+
+    Material::new(
+        material.diffuse.map_or(default.albedo, Vec3::from),
+        // converts the blinn-phong specular exponent to an approximately matching roughness
+        material.shininess.map_or(default.roughness, |shininess| {
+            (2.0 / (shininess.max(0.0) + 2.0)).sqrt()
+        }),
+        metallic,
+    )
+    .with_emission(
+        if emission_strength > 0.0 {
+            emission / emission_strength
+        } else {
+            Vec3::ZERO
+        },
+        emission_strength,
+    )
+}
+
+/// Parses a parameter tobj doesn't know about as a list of numbers.
+fn unknown_channels(material: &tobj::Material, name: &str) -> Option<Vec<f32>> {
+    material
+        .unknown_param
+        .get(name)?
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()
+}
+
+/// Collects the vertices of a mesh loaded with a single index, generating smooth normals if the
+/// mesh has none.
+fn convert_vertices(mesh: &tobj::Mesh) -> Vec<Vertex> {
+    let positions: Vec<Vec3> = mesh
+        .positions
+        .chunks_exact(3)
+        .map(Vec3::from_slice)
+        .collect();
+
+    let normals: Vec<Vec3> = if mesh.normals.len() == mesh.positions.len() {
+        mesh.normals.chunks_exact(3).map(Vec3::from_slice).collect()
+    } else {
+        generate_normals(&positions, &mesh.indices)
+    };
+
+    positions
+        .into_iter()
+        .zip(normals)
+        .map(|(position, normal)| Vertex::new(position, normal))
+        .collect()
+}
+
+/// Averages the normals of the faces around every vertex, weighted by their area.
+pub(crate) fn generate_normals(positions: &[Vec3], indices: &[u32]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::ZERO; positions.len()];
+
+    for corners in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| corners[i] as usize);
+
+        // the cross product's length is twice the area of the triangle
+        let face_normal = (positions[b] - positions[a]).cross(positions[c] - positions[a]);
+
+        normals[a] += face_normal;
+        normals[b] += face_normal;
+        normals[c] += face_normal;
+    }
+
+    normals
+        .into_iter()
+        .map(|normal| normal.normalize_or(Vec3::Y))
+        .collect()
+}
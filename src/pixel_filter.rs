@@ -0,0 +1,67 @@
+use std::f32::consts::PI;
+
+use glam::Vec2;
+
+/// The filter weighing the jittered samples of a pixel by their distance from its center, which
+/// trades between sharpness and aliasing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PixelFilter {
+    /// Weighs every sample inside the pixel equally.
+    #[default]
+    Box,
+    /// Falls off linearly over one pixel from the center.
+    Tent,
+    /// A gaussian with a standard deviation of half a pixel, cut off after one and a half pixels.
+    Gaussian,
+    /// The four term Blackman-Harris window, two pixels wide on either side.
+    BlackmanHarris,
+}
+
+impl PixelFilter {
+    /// Every filter, in the order they are listed in the ui.
+    pub const ALL: [Self; 4] = [Self::Box, Self::Tent, Self::Gaussian, Self::BlackmanHarris];
+
+    /// The name shown in the ui.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Box => "box",
+            Self::Tent => "tent",
+            Self::Gaussian => "gaussian",
+            Self::BlackmanHarris => "blackman-harris",
+        }
+    }
+
+    /// How far from the pixel center samples are taken, in pixels along each axis.
+    pub fn radius(self) -> f32 {
+        match self {
+            Self::Box => 0.5,
+            Self::Tent => 1.0,
+            Self::Gaussian => 1.5,
+            Self::BlackmanHarris => 2.0,
+        }
+    }
+
+    /// The weight of a sample at the given offset from the pixel center, as in the shader.
+    pub fn weight(self, offset: Vec2) -> f32 {
+        self.weight_1d(offset.x) * self.weight_1d(offset.y)
+    }
+
+    fn weight_1d(self, x: f32) -> f32 {
+        let radius = self.radius();
+
+        match self {
+            Self::Box => 1.0,
+            Self::Tent => (1.0 - x.abs() / radius).max(0.0),
+            Self::Gaussian => {
+                let gaussian = |x: f32| (-2.0 * x * x).exp();
+
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            Self::BlackmanHarris => {
+                let t = 2.0 * PI * (x / radius * 0.5 + 0.5);
+
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+}
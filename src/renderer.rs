@@ -0,0 +1,206 @@
+use anyhow::{anyhow, Result};
+use glam::{uvec2, Vec3};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    camera::Camera, environment::Environment, export::RadianceImage, gfx_context::GfxContext,
+    pixel_filter::PixelFilter, scene::Scene, sky::ProceduralSky, tone_mapping::ToneMapping,
+};
+
+/// How images are rendered by a [`Renderer`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderSettings {
+    /// The width of the image in pixels.
+    pub width: u32,
+    /// The height of the image in pixels.
+    pub height: u32,
+    /// The number of samples accumulated for every pixel, rounded up to a whole number of frames.
+    pub samples_per_pixel: u32,
+    /// The number of jittered samples traced for every pixel in each frame.
+    pub samples_per_frame: u32,
+    /// The filter weighing the samples of every pixel.
+    pub pixel_filter: PixelFilter,
+    /// The color of light coming from rays that don't hit anything.
+    pub sky_color: Vec3,
+    /// The environment map lighting the scene in place of `sky_color`, if any.
+    pub environment: Option<Environment>,
+    /// The procedural sky lighting the scene in place of `sky_color`, if any. An environment map
+    /// takes precedence over it.
+    pub procedural_sky: Option<ProceduralSky>,
+    /// The largest width and height traced at once, splitting bigger images into tiles which are
+    /// rendered one after another, or `None` to trace the whole image at once. Smaller tiles keep
+    /// every dispatch short, so the GPU doesn't time out on heavy scenes.
+    pub tile_size: Option<u32>,
+    /// How the radiance is mapped to displayed colors, by [`Renderer::read_display`] and when
+    /// saving pngs. The rendered radiance itself is never tone mapped.
+    pub tone_mapping: ToneMapping,
+}
+
+/// A headless renderer, turning scenes into images without a window.
+///
+/// The renderer keeps its GPU resources alive between renders, so rendering several images with
+/// one renderer is much cheaper than calling [`render_to_image`] repeatedly.
+pub struct Renderer {
+    /// The offscreen context doing the actual rendering.
+    gfx_context: GfxContext,
+    /// The settings used for every render.
+    settings: RenderSettings,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            samples_per_pixel: 256,
+            samples_per_frame: 1,
+            pixel_filter: PixelFilter::default(),
+            sky_color: Vec3::splat(0.01),
+            environment: None,
+            procedural_sky: None,
+            tile_size: None,
+            tone_mapping: ToneMapping::default(),
+        }
+    }
+}
+
+impl RenderSettings {
+    /// The number of frames traced to accumulate at least `samples_per_pixel` samples.
+    pub fn frame_count(&self) -> u32 {
+        self.samples_per_pixel
+            .max(1)
+            .div_ceil(self.samples_per_frame.max(1))
+    }
+
+    /// Checks that the settings describe an image which can be rendered.
+    pub fn validate(&self) -> Result<()> {
+        if self.width == 0 || self.height == 0 {
+            return Err(anyhow!(
+                "cannot render a {}x{} image, both dimensions must be at least one pixel",
+                self.width,
+                self.height,
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Renderer {
+    /// Creates a new [`Renderer`], picking a graphics adapter as described in
+    /// [`GfxContext::new_headless`].
+    pub async fn new(settings: RenderSettings) -> Result<Self> {
+        settings.validate()?;
+
+        let size = PhysicalSize::new(settings.width, settings.height);
+        let gfx_context = GfxContext::new_headless(size, &Camera::default()).await?;
+
+        Ok(Self {
+            gfx_context,
+            settings,
+        })
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    /// Replaces the settings used for the following renders, keeping the current ones if the new
+    /// settings are invalid.
+    pub fn set_settings(&mut self, settings: RenderSettings) -> Result<()> {
+        settings.validate()?;
+
+        if (settings.width, settings.height) != (self.settings.width, self.settings.height) {
+            self.gfx_context
+                .resize(PhysicalSize::new(settings.width, settings.height));
+        }
+
+        self.settings = settings;
+
+        Ok(())
+    }
+
+    /// Renders the scene as seen from the camera, blocking until all the samples are accumulated.
+    pub fn render(&mut self, scene: &Scene, camera: &Camera) -> Result<RadianceImage> {
+        scene.validate()?;
+
+        let mut camera = *camera;
+        let frames = self.settings.frame_count();
+
+        self.gfx_context.render_uniform.sky_color = self.settings.sky_color;
+        self.gfx_context.environment = self.settings.environment.clone();
+        self.gfx_context.procedural_sky = self.settings.procedural_sky;
+        self.gfx_context.tone_mapping = self.settings.tone_mapping;
+        self.gfx_context.samples_per_frame = self.settings.samples_per_frame;
+        self.gfx_context.pixel_filter = self.settings.pixel_filter;
+        self.gfx_context.render_uniform.accumulate = true;
+
+        let RenderSettings { width, height, .. } = self.settings;
+        let image_size = PhysicalSize::new(width, height);
+        let tile_size = self.settings.tile_size.unwrap_or(u32::MAX).max(1);
+
+        let mut image = RadianceImage {
+            width,
+            height,
+            pixels: vec![Vec3::ZERO; width as usize * height as usize],
+        };
+
+        for tile_y in (0..height).step_by(tile_size as usize) {
+            for tile_x in (0..width).step_by(tile_size as usize) {
+                let tile_width = tile_size.min(width - tile_x);
+                let tile_height = tile_size.min(height - tile_y);
+
+                self.gfx_context
+                    .set_render_size(PhysicalSize::new(tile_width, tile_height));
+                self.gfx_context.set_tile(uvec2(tile_x, tile_y), image_size);
+
+                self.accumulate_frames(&mut camera, scene, frames);
+
+                let tile = self.gfx_context.read_accumulation()?;
+
+                for (row, pixels) in tile.pixels.chunks_exact(tile_width as usize).enumerate() {
+                    let start = (tile_y as usize + row) * width as usize + tile_x as usize;
+
+                    image.pixels[start..start + pixels.len()].copy_from_slice(pixels);
+                }
+            }
+        }
+
+        Ok(image)
+    }
+
+    /// Accumulates the frames of the current tile.
+    fn accumulate_frames(&mut self, camera: &mut Camera, scene: &Scene, frames: u32) {
+        for frame in 1..=frames {
+            self.gfx_context.update_buffers(camera, scene);
+            self.gfx_context.render_offscreen();
+
+            // wait for every frame, so that thousands of submissions don't queue up at once
+            self.gfx_context.device.poll(wgpu::Maintain::Wait);
+
+            if frame % (frames / 10).max(1) == 0 {
+                log::debug!("rendered {frame}/{frames} frames");
+            }
+        }
+    }
+
+    /// Reads back the last rendered frame, as it was displayed, in 8-bit sRGB.
+    pub fn read_display(&self) -> Result<image::RgbaImage> {
+        let PhysicalSize { width, height } = self.gfx_context.size();
+        let pixels = self.gfx_context.read_offscreen_texture()?;
+
+        Ok(image::RgbaImage::from_raw(width, height, pixels)
+            .expect("pixel count should match the texture dimensions"))
+    }
+}
+
+/// Renders a single image of the scene, creating and discarding a [`Renderer`].
+pub fn render_to_image(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+) -> Result<RadianceImage> {
+    let mut renderer = pollster::block_on(Renderer::new(settings.clone()))?;
+
+    renderer.render(scene, camera)
+}
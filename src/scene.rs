@@ -1,33 +1,87 @@
-use std::sync::OnceLock;
-
+use std::{
+    borrow::Cow,
+    ops::Range,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
 use wgpu::{util::*, *};
 
-use crate::gfx_context::GfxContext;
+use crate::{
+    bvh::Bvh,
+    camera::Camera,
+    history::{Edit, History},
+};
 
 /// A description of all the primitives and materials currently being rendered.
-#[derive(Debug)]
+///
+/// The scene can be changed directly, which isn't recorded anywhere, or with [`Scene::apply`],
+/// which records the edit in its history so that it can be undone. Edits recorded before the
+/// scene was changed directly may no longer fit it, so the two are best not mixed.
+#[derive(Debug, Clone)]
 pub struct Scene {
     /// The spheres currently in the scene.
     spheres: Vec<Sphere>,
     /// The materials loaded in the scene.
     materials: Vec<Material>,
+    /// The triangle meshes currently in the scene.
+    meshes: Vec<Mesh>,
+
+    /// A unique identifier of the current contents of `meshes`, changed on every modification so
+    /// that they are only uploaded again when needed.
+    meshes_generation: u64,
+    /// A unique identifier of the current vertices of `meshes`, changed when they are transformed
+    /// without changing the triangles, so that the hierarchy is only refit rather than rebuilt.
+    vertices_generation: u64,
+
+    /// The edits made to the scene and the camera looking at it, which can be undone.
+    history: History,
+}
+
+/// A single sphere or mesh of a [`Scene`], by its index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SceneObject {
+    Sphere(usize),
+    Mesh(usize),
+}
 
+/// The GPU side copy of a [`Scene`], bound as a single bind group.
+#[derive(Debug)]
+pub(crate) struct SceneBuffers {
     /// A handle to the uploaded sphere data in the GPU.
     spheres_buffer: wgpu::Buffer,
     /// A handle to the uploaded material data in the GPU.
     materials_buffer: wgpu::Buffer,
-
-    /// The bind group referencing both the buffers.
+    /// A handle to the vertices of every mesh, concatenated together.
+    vertices_buffer: wgpu::Buffer,
+    /// A handle to the triangles of every mesh, indexing into `vertices_buffer`.
+    triangles_buffer: wgpu::Buffer,
+
+    /// A handle to the flattened nodes of `bvh`.
+    bvh_nodes_buffer: wgpu::Buffer,
+    /// A handle to the primitive references of the leaves of `bvh`.
+    bvh_primitives_buffer: wgpu::Buffer,
+    /// A handle to the number of emissive spheres, followed by their indices.
+    lights_buffer: wgpu::Buffer,
+
+    /// The generation of the meshes currently in `vertices_buffer` and `triangles_buffer`.
+    meshes_generation: Option<u64>,
+    /// The generation of the vertices currently in `vertices_buffer`.
+    vertices_generation: Option<u64>,
+    /// The hierarchy over every sphere and triangle, kept around to refit as spheres move.
+    bvh: Bvh,
+
+    /// The bind group referencing all the buffers.
     bind_group: wgpu::BindGroup,
-
-    /// If the size of `self.spheres` changed in the last frame (need to allocate a new buffer).
-    spheres_size_changed: bool,
-    /// If the size of `self.materials` changed in the last frame (need to allocate a new buffer).
-    materials_size_changed: bool,
+    /// The layout of `bind_group`.
+    pub bind_group_layout: wgpu::BindGroupLayout,
 }
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, bytemuck::Zeroable, bytemuck::Pod, Serialize, Deserialize,
+)]
 pub struct Sphere {
     /// The position of the sphere in 3d space.
     pub position: glam::Vec4,
@@ -37,28 +91,89 @@ pub struct Sphere {
     /// The index of the material of the sphere.
     pub material_index: u32,
 
+    #[serde(skip)]
     padding: [u32; 2],
 }
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, bytemuck::Zeroable, bytemuck::Pod, Serialize, Deserialize,
+)]
+#[serde(default)]
 pub struct Material {
-    /// The unlit, diffuse component of the material.
+    /// The diffuse color of dielectrics, or the specular color of metals.
     pub albedo: glam::Vec3,
-    /// How much light gets scattered when hitting this material.
-    /// A value of zero means no light is scattered (perfectly smooth), while one means
-    /// lightly is fully randomly scattered.
+    /// The perceptual roughness of the microfacets of the surface.
+    /// A value of zero means a perfectly smooth mirror-like surface, while one means light is
+    /// scattered almost evenly.
     pub roughness: f32,
 
     /// The color that this material emits.
     pub emission_color: glam::Vec3,
     /// The strength at which this material emits emission.
     pub emission_strength: f32,
+
+    /// The fraction of every color of light that is left after traveling a unit of distance
+    /// inside a transmissive material.
+    pub absorption_color: glam::Vec3,
+    /// The index of refraction of a transmissive material, such as 1.33 for water or 1.5 for glass.
+    pub ior: f32,
+
+    /// How metallic the surface is, blending between a dielectric at zero, with a diffuse base
+    /// and a faint white reflection, and a metal at one, with only a reflection tinted by `albedo`.
+    pub metallic: f32,
+    /// How much light passes through the surface rather than being reflected by it, where one
+    /// means a fully transparent material such as glass.
+    pub transmission: f32,
+
+    #[serde(skip)]
+    padding: [u32; 2],
 }
 
+/// A triangle mesh, made entirely out of one material.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mesh {
+    /// A name for the mesh, such as the group it was imported from.
+    pub name: String,
+    /// The corners of the triangles.
+    pub vertices: Vec<Vertex>,
+    /// Indices into `vertices`, with every three consecutive indices making up a triangle.
+    pub indices: Vec<u32>,
+
+    /// The index of the material of the mesh.
+    pub material_index: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Zeroable, bytemuck::Pod, Serialize, Deserialize)]
+pub struct Vertex {
+    /// The position of the vertex in 3d space.
+    pub position: glam::Vec3,
+    #[serde(skip)]
+    padding0: u32,
+
+    /// The shading normal of the vertex, interpolated across triangles.
+    pub normal: glam::Vec3,
+    #[serde(skip)]
+    padding1: u32,
+}
+
+/// A single triangle of a mesh, as laid out on the GPU.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+pub(crate) struct Triangle {
+    /// The indices of the corners, into all the vertices of the scene.
+    pub indices: glam::UVec3,
+    /// The index of the material of the triangle.
+    pub material_index: u32,
+}
+
+/// The source of unique mesh generations, shared between all scenes.
+static NEXT_MESHES_GENERATION: AtomicU64 = AtomicU64::new(0);
+
 impl Scene {
-    /// Creates a new [`Scene`].
-    pub fn new(gfx_context: &GfxContext) -> Self {
+    /// Creates a new [`Scene`], containing a single sphere.
+    pub fn new() -> Self {
         use glam::{vec3, vec4};
 
         let spheres = vec![Sphere {
@@ -67,37 +182,265 @@ impl Scene {
             material_index: 0,
             padding: [0; 2],
         }];
-        let materials = vec![Material {
-            albedo: vec3(0.6, 0.2, 0.7),
-            roughness: 0.2,
-            emission_color: vec3(0.0, 0.0, 0.0),
-            emission_strength: 0.0,
-        }];
-
-        let spheres_buffer = Self::create_spheres_buffer(gfx_context, &spheres);
-        let materials_buffer = Self::create_materials_buffer(gfx_context, &materials);
+        let materials = vec![Material::new(vec3(0.6, 0.2, 0.7), 0.2, 0.0)];
 
-        let bind_group = Self::create_bind_group(&gfx_context, &spheres_buffer, &materials_buffer);
+        Self::with_objects(spheres, materials)
+    }
 
+    /// Creates a new [`Scene`] from the given spheres and materials.
+    pub fn with_objects(spheres: Vec<Sphere>, materials: Vec<Material>) -> Self {
         Self {
             spheres,
             materials,
-            bind_group,
-            spheres_buffer,
-            materials_buffer,
-            spheres_size_changed: false,
-            materials_size_changed: false,
+            meshes: Vec::new(),
+            meshes_generation: Self::next_meshes_generation(),
+            vertices_generation: Self::next_meshes_generation(),
+            history: History::default(),
         }
     }
 
     pub fn add_sphere(&mut self, sphere: Sphere) {
         self.spheres.push(sphere);
-        self.spheres_size_changed = true;
     }
 
     pub fn add_material(&mut self, material: Material) {
         self.materials.push(material);
-        self.materials_size_changed = true;
+    }
+
+    pub fn add_mesh(&mut self, mesh: Mesh) {
+        self.meshes.push(mesh);
+        self.meshes_generation = Self::next_meshes_generation();
+    }
+
+    /// Adds meshes along with the materials they use, where the material indices of the meshes
+    /// index into `materials` rather than the materials of the scene.
+    pub fn add_meshes(&mut self, meshes: Vec<Mesh>, materials: Vec<Material>) {
+        let material_offset = self.materials.len() as u32;
+
+        self.materials.extend(materials);
+        self.meshes.extend(meshes.into_iter().map(|mesh| Mesh {
+            material_index: mesh.material_index + material_offset,
+            ..mesh
+        }));
+
+        self.meshes_generation = Self::next_meshes_generation();
+    }
+
+    /// Removes the sphere at the given index, moving the ones after it down by one.
+    pub fn remove_sphere(&mut self, index: usize) -> Sphere {
+        self.spheres.remove(index)
+    }
+
+    /// Removes the mesh at the given index, moving the ones after it down by one.
+    pub fn remove_mesh(&mut self, index: usize) -> Mesh {
+        self.meshes_generation = Self::next_meshes_generation();
+
+        self.meshes.remove(index)
+    }
+
+    /// Removes the material at the given index, and moves the material indices of the objects
+    /// using the materials after it down by one. Materials which any object uses can't be removed,
+    /// as the object would be left without one.
+    pub fn remove_material(&mut self, index: usize) -> Result<Material> {
+        if index >= self.materials.len() {
+            return Err(anyhow!(
+                "cannot remove material {index}, as there are only {} materials",
+                self.materials.len()
+            ));
+        }
+
+        if self.material_in_use(index) {
+            return Err(anyhow!(
+                "cannot remove material {index}, which is still in use"
+            ));
+        }
+
+        self.shift_material_indices(index as u32 + 1, -1);
+
+        Ok(self.materials.remove(index))
+    }
+
+    /// Inserts a sphere at the given index, moving the ones after it up by one.
+    pub(crate) fn insert_sphere(&mut self, index: usize, sphere: Sphere) {
+        self.spheres.insert(index, sphere);
+    }
+
+    /// Inserts a mesh at the given index, moving the ones after it up by one.
+    pub(crate) fn insert_mesh(&mut self, index: usize, mesh: Mesh) {
+        self.meshes.insert(index, mesh);
+        self.meshes_generation = Self::next_meshes_generation();
+    }
+
+    /// Inserts a material at the given index, and moves the material indices of the objects using
+    /// the materials after it up by one, so that they keep their materials.
+    pub(crate) fn insert_material(&mut self, index: usize, material: Material) {
+        self.shift_material_indices(index as u32, 1);
+        self.materials.insert(index, material);
+    }
+
+    /// Adds an offset to every material index of at least `start`.
+    fn shift_material_indices(&mut self, start: u32, offset: i32) {
+        let shift = |material_index: &mut u32| {
+            if *material_index >= start {
+                *material_index = material_index.saturating_add_signed(offset);
+            }
+        };
+
+        self.spheres
+            .iter_mut()
+            .for_each(|sphere| shift(&mut sphere.material_index));
+        self.meshes
+            .iter_mut()
+            .for_each(|mesh| shift(&mut mesh.material_index));
+
+        self.meshes_generation = Self::next_meshes_generation();
+    }
+
+    /// Whether any sphere or mesh uses the material at the given index.
+    pub fn material_in_use(&self, index: usize) -> bool {
+        let index = index as u32;
+
+        self.spheres
+            .iter()
+            .any(|sphere| sphere.material_index == index)
+            || self.meshes.iter().any(|mesh| mesh.material_index == index)
+    }
+
+    /// Whether the object is in the scene.
+    pub fn contains(&self, object: SceneObject) -> bool {
+        match object {
+            SceneObject::Sphere(index) => index < self.spheres.len(),
+            SceneObject::Mesh(index) => index < self.meshes.len(),
+        }
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Replaces the contents of the scene with another one, such as a file which was loaded. The
+    /// history is cleared, as its edits don't apply to the new contents, noting the reason.
+    pub fn replace(&mut self, other: Scene, reason: impl Into<String>) {
+        let mut history = std::mem::take(&mut self.history);
+        history.clear(reason.into());
+
+        *self = Self { history, ..other };
+    }
+
+    /// Makes an edit to the scene and records it in the history, so that it can be undone.
+    ///
+    /// Edits which don't fit the scene, such as ones referring to objects which don't exist or
+    /// removing a material which is still in use, are rejected without changing anything. Camera
+    /// moves are recorded with [`Scene::record_camera_move`] instead, as the scene doesn't hold the
+    /// camera.
+    pub fn apply(&mut self, edit: Edit) -> Result<()> {
+        edit.validate(self)?;
+        edit.apply(self);
+
+        self.history.record(edit);
+
+        Ok(())
+    }
+
+    /// Records that the camera looking at the scene moved, so that undoing goes back through the
+    /// camera moves along with the edits to the scene.
+    pub fn record_camera_move(&mut self, before: Camera, after: Camera) {
+        self.history.record(Edit::MoveCamera { before, after });
+    }
+
+    /// Reverts the last edit in the history, returning whether there was one.
+    pub fn undo(&mut self, camera: &mut Camera) -> bool {
+        let mut history = std::mem::take(&mut self.history);
+        let undone = history.undo(self, camera);
+
+        self.history = history;
+
+        undone
+    }
+
+    /// Makes the last undone edit in the history again, returning whether there was one.
+    pub fn redo(&mut self, camera: &mut Camera) -> bool {
+        let mut history = std::mem::take(&mut self.history);
+        let redone = history.redo(self, camera);
+
+        self.history = history;
+
+        redone
+    }
+
+    /// Undoes or redoes edits until the given number of edits in the history are applied.
+    pub fn go_to_edit(&mut self, position: usize, camera: &mut Camera) {
+        while self.history.position() > position && self.undo(camera) {}
+        while self.history.position() < position && self.redo(camera) {}
+    }
+
+    /// Changes the material of the mesh at the given index.
+    pub fn set_mesh_material(&mut self, mesh_index: usize, material_index: u32) {
+        self.meshes[mesh_index].material_index = material_index;
+        self.meshes_generation = Self::next_meshes_generation();
+    }
+
+    /// Moves an object by an offset.
+    pub fn translate_object(&mut self, object: SceneObject, offset: glam::Vec3) {
+        match object {
+            SceneObject::Sphere(index) => {
+                if let Some(sphere) = self.spheres.get_mut(index) {
+                    sphere.position += offset.extend(0.0);
+                }
+            }
+            SceneObject::Mesh(index) => {
+                let Some(mesh) = self.meshes.get_mut(index) else {
+                    return;
+                };
+
+                for vertex in &mut mesh.vertices {
+                    vertex.position += offset;
+                }
+
+                self.vertices_generation = Self::next_meshes_generation();
+            }
+        }
+    }
+
+    /// Scales an object away from a pivot point by a positive factor along each axis. Spheres stay
+    /// round, and have their radius scaled by the geometric mean of the factors.
+    pub fn scale_object(&mut self, object: SceneObject, pivot: glam::Vec3, scale: glam::Vec3) {
+        match object {
+            SceneObject::Sphere(index) => {
+                if let Some(sphere) = self.spheres.get_mut(index) {
+                    let position = sphere.position.truncate();
+
+                    sphere.position = (pivot + (position - pivot) * scale).extend(0.0);
+                    sphere.radius *= scale.element_product().cbrt();
+                }
+            }
+            SceneObject::Mesh(index) => {
+                let Some(mesh) = self.meshes.get_mut(index) else {
+                    return;
+                };
+
+                for vertex in &mut mesh.vertices {
+                    vertex.position = pivot + (vertex.position - pivot) * scale;
+                    // normals are transformed by the inverse transpose, which is just the
+                    // inverse of a scale
+                    vertex.normal = (vertex.normal / scale).normalize_or_zero();
+                }
+
+                self.vertices_generation = Self::next_meshes_generation();
+            }
+        }
+    }
+
+    pub fn spheres(&self) -> &[Sphere] {
+        &self.spheres
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    pub fn meshes(&self) -> &[Mesh] {
+        &self.meshes
     }
 
     pub fn spheres_mut(&mut self) -> &mut [Sphere] {
@@ -108,86 +451,315 @@ impl Scene {
         &mut self.materials
     }
 
-    pub fn update_buffers(&mut self, gfx_context: &GfxContext) {
-        let recreate_bind_group = self.spheres_size_changed || self.materials_size_changed;
+    /// The center and radius of a sphere enclosing the object, or `None` if the object doesn't
+    /// exist or is an empty mesh.
+    pub fn bounding_sphere(&self, object: SceneObject) -> Option<(glam::Vec3, f32)> {
+        match object {
+            SceneObject::Sphere(index) => {
+                let sphere = self.spheres.get(index)?;
 
-        let spheres_bytes = bytemuck::cast_slice(&self.spheres);
-        let materials_bytes = bytemuck::cast_slice(&self.materials);
+                Some((sphere.position.truncate(), sphere.radius))
+            }
+            SceneObject::Mesh(index) => {
+                let (minimum, maximum) = self.meshes.get(index)?.bounds()?;
 
-        if self.spheres_size_changed {
-            self.spheres_size_changed = false;
-            self.spheres_buffer = Self::create_spheres_buffer(gfx_context, &self.spheres);
+                Some(((minimum + maximum) * 0.5, minimum.distance(maximum) * 0.5))
+            }
         }
+    }
 
-        if self.materials_size_changed {
-            self.materials_size_changed = false;
-            self.materials_buffer = Self::create_materials_buffer(gfx_context, &self.materials);
+    /// Checks that the scene can actually be uploaded and rendered.
+    pub fn validate(&self) -> Result<()> {
+        let materials_len = self.materials.len();
+
+        for (i, sphere) in self.spheres.iter().enumerate() {
+            if sphere.material_index as usize >= materials_len {
+                return Err(anyhow!(
+                    "sphere {i} references material {}, but there are only {materials_len} materials",
+                    sphere.material_index,
+                ));
+            }
         }
 
-        if recreate_bind_group {
-            self.bind_group =
-                Self::create_bind_group(gfx_context, &self.spheres_buffer, &self.materials_buffer);
+        for (i, mesh) in self.meshes.iter().enumerate() {
+            if mesh.material_index as usize >= materials_len {
+                return Err(anyhow!(
+                    "mesh {i} references material {}, but there are only {materials_len} materials",
+                    mesh.material_index,
+                ));
+            }
+
+            if mesh.indices.len() % 3 != 0 {
+                return Err(anyhow!(
+                    "mesh {i} has a number of indices not divisible by three"
+                ));
+            }
+
+            if let Some(index) = mesh
+                .indices
+                .iter()
+                .find(|&&index| index as usize >= mesh.vertices.len())
+            {
+                return Err(anyhow!(
+                    "mesh {i} references vertex {index}, but there are only {} vertices",
+                    mesh.vertices.len()
+                ));
+            }
         }
 
-        gfx_context
-            .queue
-            .write_buffer(&self.spheres_buffer, 0, spheres_bytes);
-
-        gfx_context
-            .queue
-            .write_buffer(&self.materials_buffer, 0, materials_bytes);
-    }
-
-    pub fn create_bind_group_layout(device: &Device) -> &'static BindGroupLayout {
-        static LAYOUT: OnceLock<BindGroupLayout> = OnceLock::new();
-
-        LAYOUT.get_or_init(|| {
-            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("Scene Bind Group Layout"),
-                entries: &[
-                    BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::FRAGMENT,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: ShaderStages::FRAGMENT,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                ],
+        Ok(())
+    }
+
+    /// The indices of the spheres with an emissive material, which are sampled directly as lights.
+    pub(crate) fn emissive_spheres(&self) -> Vec<u32> {
+        (0..self.spheres.len() as u32)
+            .filter(|&i| {
+                let material_index = self.spheres[i as usize].material_index as usize;
+
+                self.materials.get(material_index).is_some_and(|material| {
+                    material.emission_strength > 0.0 && material.emission_color != glam::Vec3::ZERO
+                })
             })
+            .collect()
+    }
+
+    /// The range of the triangles of a mesh among the triangles of [`Scene::flatten_meshes`].
+    pub(crate) fn mesh_triangles(&self, mesh_index: usize) -> Option<Range<u32>> {
+        let start = self
+            .meshes
+            .get(..mesh_index)?
+            .iter()
+            .map(Mesh::triangle_count)
+            .sum();
+        let count = self.meshes.get(mesh_index)?.triangle_count();
+
+        Some(start..start + count)
+    }
+
+    /// The mesh a triangle of [`Scene::flatten_meshes`] belongs to.
+    pub(crate) fn triangle_mesh(&self, triangle_index: u32) -> Option<usize> {
+        let mut end = 0;
+
+        self.meshes.iter().position(|mesh| {
+            end += mesh.triangle_count();
+            triangle_index < end
         })
     }
 
+    /// Concatenates the vertices and triangles of every mesh, as they are laid out on the GPU.
+    pub(crate) fn flatten_meshes(&self) -> (Vec<Vertex>, Vec<Triangle>) {
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+
+        for mesh in &self.meshes {
+            let base_vertex = vertices.len() as u32;
+
+            triangles.extend(mesh.indices.chunks_exact(3).map(|corners| Triangle {
+                indices: glam::UVec3::from_slice(corners) + base_vertex,
+                material_index: mesh.material_index,
+            }));
+            vertices.extend_from_slice(&mesh.vertices);
+        }
+
+        (vertices, triangles)
+    }
+
+    fn next_meshes_generation() -> u64 {
+        NEXT_MESHES_GENERATION.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SceneBuffers {
+    /// Creates the buffers, initially holding the contents of [`Scene::new`].
+    pub fn new(device: &Device) -> Self {
+        let scene = Scene::new();
+        let (vertices, triangles) = scene.flatten_meshes();
+
+        let spheres_buffer = Self::create_spheres_buffer(device, &scene.spheres);
+        let materials_buffer = Self::create_materials_buffer(device, &scene.materials);
+        let vertices_buffer = Self::create_vertices_buffer(device, &vertices);
+        let triangles_buffer = Self::create_triangles_buffer(device, &triangles);
+
+        let bvh = Bvh::new(&scene.spheres, &vertices, &triangles);
+        let bvh_nodes_buffer = Self::create_bvh_nodes_buffer(device, &bvh);
+        let bvh_primitives_buffer = Self::create_bvh_primitives_buffer(device, &bvh);
+
+        let lights_buffer = Self::create_lights_buffer(device, &lights_bytes(&scene));
+
+        let bind_group_layout = Self::create_bind_group_layout(device);
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &[
+                &spheres_buffer,
+                &materials_buffer,
+                &vertices_buffer,
+                &triangles_buffer,
+                &bvh_nodes_buffer,
+                &bvh_primitives_buffer,
+                &lights_buffer,
+            ],
+        );
+
+        Self {
+            spheres_buffer,
+            materials_buffer,
+            vertices_buffer,
+            triangles_buffer,
+            bvh_nodes_buffer,
+            bvh_primitives_buffer,
+            lights_buffer,
+            meshes_generation: Some(scene.meshes_generation),
+            vertices_generation: Some(scene.vertices_generation),
+            bvh,
+            bind_group,
+            bind_group_layout,
+        }
+    }
+
+    /// Uploads the contents of the scene, allocating new buffers if its size changed.
+    ///
+    /// The hierarchy is only rebuilt when the meshes or the number of spheres change, and refit
+    /// when spheres or meshes move, such as every frame of dragging them with the gizmo.
+    pub fn update(&mut self, device: &Device, queue: &Queue, scene: &Scene) {
+        let spheres_bytes = storage_bytes(&scene.spheres);
+        let materials_bytes = storage_bytes(&scene.materials);
+        let lights_bytes = lights_bytes(scene);
+
+        let spheres_size_changed = self.spheres_buffer.size() != spheres_bytes.len() as u64;
+        let materials_size_changed = self.materials_buffer.size() != materials_bytes.len() as u64;
+        let lights_size_changed = self.lights_buffer.size() != lights_bytes.len() as u64;
+        let meshes_changed = self.meshes_generation != Some(scene.meshes_generation);
+        let vertices_changed = self.vertices_generation != Some(scene.vertices_generation);
+
+        if spheres_size_changed {
+            self.spheres_buffer = Self::create_spheres_buffer(device, &scene.spheres);
+        }
+
+        if materials_size_changed {
+            self.materials_buffer = Self::create_materials_buffer(device, &scene.materials);
+        }
+
+        if lights_size_changed {
+            self.lights_buffer = Self::create_lights_buffer(device, &lights_bytes);
+        }
+
+        if meshes_changed {
+            let (vertices, triangles) = scene.flatten_meshes();
+
+            self.vertices_buffer = Self::create_vertices_buffer(device, &vertices);
+            self.triangles_buffer = Self::create_triangles_buffer(device, &triangles);
+            self.meshes_generation = Some(scene.meshes_generation);
+            self.vertices_generation = Some(scene.vertices_generation);
+
+            self.bvh = Bvh::new(&scene.spheres, &vertices, &triangles);
+        } else if vertices_changed {
+            // the triangles are the same, so the vertices fit in the same buffer
+            let (vertices, triangles) = scene.flatten_meshes();
+
+            queue.write_buffer(&self.vertices_buffer, 0, &storage_bytes(&vertices));
+            self.vertices_generation = Some(scene.vertices_generation);
+
+            self.bvh.update_vertices(&vertices, &triangles);
+        }
+
+        let bvh_changed =
+            self.bvh.update_spheres(&scene.spheres) || meshes_changed || vertices_changed;
+        let mut bvh_size_changed = false;
+
+        if bvh_changed {
+            let nodes_bytes = storage_bytes(self.bvh.nodes());
+            let primitives_bytes = storage_bytes(self.bvh.primitives());
+
+            bvh_size_changed = self.bvh_nodes_buffer.size() != nodes_bytes.len() as u64
+                || self.bvh_primitives_buffer.size() != primitives_bytes.len() as u64;
+
+            if bvh_size_changed {
+                self.bvh_nodes_buffer = Self::create_bvh_nodes_buffer(device, &self.bvh);
+                self.bvh_primitives_buffer = Self::create_bvh_primitives_buffer(device, &self.bvh);
+            } else {
+                queue.write_buffer(&self.bvh_nodes_buffer, 0, &nodes_bytes);
+                queue.write_buffer(&self.bvh_primitives_buffer, 0, &primitives_bytes);
+            }
+        }
+
+        if spheres_size_changed
+            || materials_size_changed
+            || lights_size_changed
+            || meshes_changed
+            || bvh_size_changed
+        {
+            self.bind_group = Self::create_bind_group(
+                device,
+                &self.bind_group_layout,
+                &[
+                    &self.spheres_buffer,
+                    &self.materials_buffer,
+                    &self.vertices_buffer,
+                    &self.triangles_buffer,
+                    &self.bvh_nodes_buffer,
+                    &self.bvh_primitives_buffer,
+                    &self.lights_buffer,
+                ],
+            );
+        }
+
+        queue.write_buffer(&self.spheres_buffer, 0, &spheres_bytes);
+        queue.write_buffer(&self.materials_buffer, 0, &materials_bytes);
+        queue.write_buffer(&self.lights_buffer, 0, &lights_bytes);
+    }
+
+    fn create_bind_group_layout(device: &Device) -> BindGroupLayout {
+        let storage_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Scene Bind Group Layout"),
+            entries: &[
+                storage_entry(0),
+                storage_entry(1),
+                storage_entry(2),
+                storage_entry(3),
+                storage_entry(4),
+                storage_entry(5),
+                storage_entry(6),
+            ],
+        })
+    }
+
+    /// Creates a bind group with the buffers bound in order, starting at binding zero.
     fn create_bind_group(
-        gfx_context: &GfxContext,
-        sphere_buffer: &Buffer,
-        material_buffer: &Buffer,
+        device: &Device,
+        layout: &BindGroupLayout,
+        buffers: &[&Buffer],
     ) -> BindGroup {
-        gfx_context.device.create_bind_group(&BindGroupDescriptor {
+        let entries: Vec<_> = buffers
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+
+        device.create_bind_group(&BindGroupDescriptor {
             label: Some("Scene Bind Group"),
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: sphere_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: material_buffer.as_entire_binding(),
-                },
-            ],
-            layout: Self::create_bind_group_layout(&gfx_context.device),
+            entries: &entries,
+            layout,
         })
     }
 
@@ -196,34 +768,105 @@ impl Scene {
     }
 
     /// Utility function to create a new buffer, and upload all the given data to the GPU.
-    fn create_buffer(gfx_context: &GfxContext, label: &str, data: &[u8]) -> Buffer {
-        gfx_context
-            .device
-            .create_buffer_init(&BufferInitDescriptor {
-                label: Some(label),
-                contents: bytemuck::cast_slice(data),
-                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
-            })
+    fn create_buffer(device: &Device, label: &str, data: &[u8]) -> Buffer {
+        device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(label),
+            contents: data,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        })
     }
 
-    fn create_spheres_buffer(gfx_context: &GfxContext, spheres: &[Sphere]) -> Buffer {
+    fn create_spheres_buffer(device: &Device, spheres: &[Sphere]) -> Buffer {
         Self::create_buffer(
-            gfx_context,
+            device,
             "Scene Spheres Storage Buffer",
-            bytemuck::cast_slice(spheres),
+            &storage_bytes(spheres),
         )
     }
 
-    fn create_materials_buffer(gfx_context: &GfxContext, materials: &[Material]) -> Buffer {
+    fn create_materials_buffer(device: &Device, materials: &[Material]) -> Buffer {
         Self::create_buffer(
-            gfx_context,
+            device,
             "Scene Materials Storage Buffer",
-            bytemuck::cast_slice(materials),
+            &storage_bytes(materials),
+        )
+    }
+
+    fn create_vertices_buffer(device: &Device, vertices: &[Vertex]) -> Buffer {
+        Self::create_buffer(
+            device,
+            "Scene Vertices Storage Buffer",
+            &storage_bytes(vertices),
         )
     }
+
+    fn create_triangles_buffer(device: &Device, triangles: &[Triangle]) -> Buffer {
+        Self::create_buffer(
+            device,
+            "Scene Triangles Storage Buffer",
+            &storage_bytes(triangles),
+        )
+    }
+
+    fn create_bvh_nodes_buffer(device: &Device, bvh: &Bvh) -> Buffer {
+        Self::create_buffer(
+            device,
+            "Scene BVH Nodes Storage Buffer",
+            &storage_bytes(bvh.nodes()),
+        )
+    }
+
+    fn create_bvh_primitives_buffer(device: &Device, bvh: &Bvh) -> Buffer {
+        Self::create_buffer(
+            device,
+            "Scene BVH Primitives Storage Buffer",
+            &storage_bytes(bvh.primitives()),
+        )
+    }
+
+    fn create_lights_buffer(device: &Device, lights_bytes: &[u8]) -> Buffer {
+        Self::create_buffer(device, "Scene Lights Storage Buffer", lights_bytes)
+    }
+}
+
+/// The light list as laid out on the GPU, with the number of lights followed by their indices.
+///
+/// Like [`storage_bytes`], an empty list still has room for a single index.
+fn lights_bytes(scene: &Scene) -> Vec<u8> {
+    let lights = scene.emissive_spheres();
+
+    let mut words = Vec::with_capacity(lights.len() + 1);
+    words.push(lights.len() as u32);
+    words.extend(lights);
+
+    if words.len() == 1 {
+        words.push(0);
+    }
+
+    bytemuck::cast_slice(&words).to_vec()
+}
+
+/// Storage buffers cannot be empty, so an empty list is uploaded as a single zeroed element
+/// instead, which the hierarchy never references.
+fn storage_bytes<T: bytemuck::Pod>(items: &[T]) -> Cow<'_, [u8]> {
+    if items.is_empty() {
+        Cow::Owned(vec![0; size_of::<T>()])
+    } else {
+        Cow::Borrowed(bytemuck::cast_slice(items))
+    }
 }
 
 impl Sphere {
+    /// Creates a new [`Sphere`] at the given position.
+    pub fn new(position: glam::Vec3, radius: f32, material_index: u32) -> Self {
+        Self {
+            position: position.extend(0.0),
+            radius,
+            material_index,
+            padding: [0; 2],
+        }
+    }
+
     /// Creates a new [`Sphere`], with a random position and radius and a material referencing the
     /// first material in the [`Scene`].
     pub fn random() -> Self {
@@ -250,18 +893,89 @@ impl Sphere {
 }
 
 impl Material {
-    /// Creates a new [`Sphere`], with a random attributes.
+    /// Creates a new [`Material`] that doesn't emit any light.
+    pub fn new(albedo: glam::Vec3, roughness: f32, metallic: f32) -> Self {
+        Self {
+            albedo,
+            roughness,
+            emission_color: glam::Vec3::ZERO,
+            emission_strength: 0.0,
+            absorption_color: glam::Vec3::ONE,
+            ior: 1.5,
+            metallic,
+            transmission: 0.0,
+            padding: [0; 2],
+        }
+    }
+
+    /// Makes the material emit light of the given color and strength.
+    pub fn with_emission(self, emission_color: glam::Vec3, emission_strength: f32) -> Self {
+        Self {
+            emission_color,
+            emission_strength,
+            ..self
+        }
+    }
+
+    /// Makes light pass through the material, refracting with the given index of refraction and
+    /// absorbed along the way as described by `absorption_color`.
+    pub fn with_transmission(
+        self,
+        transmission: f32,
+        ior: f32,
+        absorption_color: glam::Vec3,
+    ) -> Self {
+        Self {
+            transmission,
+            ior,
+            absorption_color,
+            ..self
+        }
+    }
+
+    /// Creates a new [`Material`], with a random attributes.
     pub fn random() -> Self {
         use glam::vec3;
         use rand::Rng;
 
         let mut rng = rand::thread_rng();
 
+        Self::new(vec3(rng.gen(), rng.gen(), rng.gen()), rng.gen(), rng.gen())
+            .with_emission(vec3(rng.gen(), rng.gen(), rng.gen()), rng.gen())
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::new(glam::Vec3::splat(0.8), 1.0, 0.0)
+    }
+}
+
+impl Mesh {
+    pub fn triangle_count(&self) -> u32 {
+        (self.indices.len() / 3) as u32
+    }
+
+    /// The smallest and largest corners of the box around every vertex, or `None` if the mesh
+    /// has no vertices.
+    pub fn bounds(&self) -> Option<(glam::Vec3, glam::Vec3)> {
+        let positions = self.vertices.iter().map(|vertex| vertex.position);
+
+        positions
+            .clone()
+            .reduce(glam::Vec3::min)
+            .zip(positions.reduce(glam::Vec3::max))
+    }
+}
+
+impl Vertex {
+    /// Creates a new [`Vertex`] with the given position and shading normal.
+    pub fn new(position: glam::Vec3, normal: glam::Vec3) -> Self {
         Self {
-            albedo: vec3(rng.gen(), rng.gen(), rng.gen()),
-            roughness: rng.gen(),
-            emission_color: vec3(rng.gen(), rng.gen(), rng.gen()),
-            emission_strength: rng.gen(),
+            position,
+            padding0: 0,
+            normal,
+            padding1: 0,
         }
     }
 }
@@ -0,0 +1,117 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::Camera,
+    camera_path::CameraAnimation,
+    environment::{Environment, EnvironmentSettings},
+    scene::{Material, Mesh, Scene, Sphere},
+    sky::ProceduralSky,
+    tone_mapping::ToneMapping,
+};
+
+/// The on-disk description of a scene, along with the camera and sky it is viewed with.
+///
+/// Scene files are stored as human editable [RON](https://github.com/ron-rs/ron).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneFile {
+    /// The camera the scene is viewed from.
+    pub camera: Camera,
+    /// The color of light coming from rays that don't hit anything.
+    pub sky_color: glam::Vec3,
+    /// The environment map lighting the scene in place of `sky_color`, if any.
+    #[serde(default)]
+    pub environment: Option<EnvironmentSettings>,
+    /// The procedural sky lighting the scene in place of `sky_color`, if any. An environment map
+    /// takes precedence over it.
+    #[serde(default)]
+    pub procedural_sky: Option<ProceduralSky>,
+    /// How the rendered radiance is mapped to displayed colors.
+    #[serde(default)]
+    pub tone_mapping: ToneMapping,
+    /// How the camera moves when the scene is rendered as an image sequence, if it does.
+    #[serde(default)]
+    pub animation: Option<CameraAnimation>,
+
+    /// The materials used by the spheres and meshes.
+    pub materials: Vec<Material>,
+    /// The spheres in the scene.
+    pub spheres: Vec<Sphere>,
+    /// The triangle meshes in the scene.
+    #[serde(default)]
+    pub meshes: Vec<Mesh>,
+}
+
+impl SceneFile {
+    /// Creates a scene file describing the given scene, camera, sky, environment, tone mapping and
+    /// camera animation.
+    pub fn new(
+        scene: &Scene,
+        camera: Camera,
+        sky_color: glam::Vec3,
+        environment: Option<&Environment>,
+        procedural_sky: Option<ProceduralSky>,
+        tone_mapping: ToneMapping,
+        animation: Option<CameraAnimation>,
+    ) -> Self {
+        Self {
+            camera,
+            sky_color,
+            environment: environment.map(Environment::settings),
+            procedural_sky,
+            tone_mapping,
+            animation,
+            materials: scene.materials().to_vec(),
+            spheres: scene.spheres().to_vec(),
+            meshes: scene.meshes().to_vec(),
+        }
+    }
+
+    /// Creates the [`Scene`] described by the file.
+    pub fn scene(&self) -> Scene {
+        let mut scene = Scene::with_objects(self.spheres.clone(), self.materials.clone());
+
+        for mesh in &self.meshes {
+            scene.add_mesh(mesh.clone());
+        }
+
+        scene
+    }
+
+    /// Loads the environment map referenced by the file, if any.
+    pub fn environment(&self) -> Result<Option<Environment>> {
+        self.environment
+            .as_ref()
+            .map(EnvironmentSettings::load)
+            .transpose()
+    }
+
+    /// Reads and validates a scene file from the given path.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("failed to read scene file {}", path.display()))?;
+
+        let file: Self = ron::from_str(&source)
+            .with_context(|| format!("failed to parse scene file {}", path.display()))?;
+
+        file.scene()
+            .validate()
+            .with_context(|| format!("invalid scene file {}", path.display()))?;
+
+        Ok(file)
+    }
+
+    /// Writes the scene file to the given path.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+
+        let source = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+
+        fs::write(path, source)
+            .with_context(|| format!("failed to write scene file {}", path.display()))
+    }
+}
@@ -7,8 +7,50 @@ struct RenderUniform {
 	screen_dimensions: vec2<u32>,
 	frames_accumulated: u32,
 	accumulate: u32,
+
+	// the procedural sky, used in place of the sky color when enabled
+	sun_direction: vec3<f32>,
+	turbidity: f32,
+	// the angle between the center and the edge of the sun disk, in radians
+	sun_angular_radius: f32,
+	sun_intensity: f32,
+	procedural_sky: u32,
+
+	// the position of the first traced pixel within the whole image, when it is traced in tiles
+	tile_offset: vec2<u32>,
+	image_dimensions: vec2<u32>,
+
+	samples_per_frame: u32,
+	pixel_filter: u32,
+
+	// the thin lens of the camera, which is a pinhole when the aperture radius is zero
+	aperture_radius: f32,
+	focus_distance: f32,
+	// the number of blades shaping the aperture into a polygon, or zero for a circle
+	aperture_blades: u32,
+
+	projection: u32,
+	near: f32,
+	far: f32,
+	// the full horizontal and vertical angles the image spans, in radians
+	field_of_view: vec2<f32>,
+
+	// the object outlined in the viewer, as the range of its triangles or the index of its sphere,
+	// with an empty range and -1 when nothing is selected
+	selected_triangles: vec2<u32>,
+	selected_sphere: i32,
 }
 
+const PROJECTION_PERSPECTIVE = 0u;
+const PROJECTION_ORTHOGRAPHIC = 1u;
+const PROJECTION_FISHEYE = 2u;
+const PROJECTION_EQUIRECTANGULAR = 3u;
+
+const PIXEL_FILTER_BOX = 0u;
+const PIXEL_FILTER_TENT = 1u;
+const PIXEL_FILTER_GAUSSIAN = 2u;
+const PIXEL_FILTER_BLACKMAN_HARRIS = 3u;
+
 struct Sphere {
 	position: vec4<f32>,
 	radius: f32,
@@ -20,68 +62,239 @@ struct Material {
 	roughness: f32,
 	emission_color: vec3<f32>,
 	emission_strength: f32,
+	absorption_color: vec3<f32>,
+	ior: f32,
+	metallic: f32,
+	transmission: f32,
+}
+
+struct Vertex {
+	position: vec3<f32>,
+	normal: vec3<f32>,
+}
+
+struct Triangle {
+	indices: vec3<u32>,
+	material_index: u32,
+}
+
+// interior nodes have no primitives, and their two children start at `first_index`
+struct BvhNode {
+	minimum: vec3<f32>,
+	first_index: u32,
+	maximum: vec3<f32>,
+	primitive_count: u32,
+}
+
+const PI = 3.14159265358979;
+// converts the luminance of the sky model, in kilocandela per square meter, into scene radiance
+const SKY_LUMINANCE_SCALE = 0.04;
+// the illuminance of the sun above the atmosphere, in kilolux
+const SUN_ILLUMINANCE = 128.0;
+// the squared roughness below which surfaces are too smooth to sample reliably
+const MIN_ALPHA = 0.001;
+
+// the spheres with an emissive material, which are sampled directly
+struct LightList {
+	count: u32,
+	spheres: array<u32>,
 }
 
+// the placement of the environment map, which replaces the sky color when enabled
+struct EnvironmentUniform {
+	// around the vertical axis, in radians
+	rotation: f32,
+	intensity: f32,
+	enabled: u32,
+}
+
+// marks a primitive reference as a triangle rather than a sphere
+const TRIANGLE_BIT = 0x80000000u;
+// one more than the deepest node of the hierarchy
+const BVH_STACK_SIZE = 32u;
+
 @group(0) @binding(0)
 var<uniform> render_info: RenderUniform;
 
 @group(1) @binding(0)
 var<storage, read_write> accumulation: array<vec4<f32>>;
 
+// whether the selected object is seen through the center of each pixel
+@group(1) @binding(1)
+var<storage, read_write> selection_mask: array<u32>;
+
 @group(2) @binding(0)
 var<storage> spheres: array<Sphere>;
 
 @group(2) @binding(1)
 var<storage> materials: array<Material>;
 
+@group(2) @binding(2)
+var<storage> vertices: array<Vertex>;
 
-struct VertexOutput {
-  @builtin(position) clip_position: vec4<f32>,
-	@location(0) position: vec2<f32>,
-}
+@group(2) @binding(3)
+var<storage> triangles: array<Triangle>;
 
-@vertex
-fn vs_main(
-    @builtin(vertex_index) in_vertex_index: u32,
-) -> VertexOutput {
-    var positions = array<vec2<f32>, 6>(
-        vec2<f32>(-1.0, -1.0),
-        vec2<f32>(-1.0, 1.0),
-        vec2<f32>(1.0, 1.0),
-        vec2<f32>(1.0, 1.0),
-        vec2<f32>(1.0, -1.0),
-        vec2<f32>(-1.0, -1.0),
-    );
+@group(2) @binding(4)
+var<storage> bvh_nodes: array<BvhNode>;
 
-    var out: VertexOutput;
+@group(2) @binding(5)
+var<storage> bvh_primitives: array<u32>;
 
-    out.clip_position = vec4<f32>(positions[in_vertex_index], 0.0, 1.0);
-    out.position = vec2<f32>(positions[in_vertex_index]);
+@group(2) @binding(6)
+var<storage> lights: LightList;
 
-    return out;
-}
+@group(3) @binding(0)
+var<uniform> environment: EnvironmentUniform;
+
+@group(3) @binding(1)
+var environment_map: texture_2d<f32>;
+
+// the cumulative distribution over the columns of every row of the map
+@group(3) @binding(2)
+var environment_conditional_cdf: texture_2d<f32>;
+
+// the cumulative distribution over the rows of the map
+@group(3) @binding(3)
+var environment_marginal_cdf: texture_2d<f32>;
+
+
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let screen_dimensions = render_info.screen_dimensions;
+
+    if any(id.xy >= screen_dimensions) {
+        return;
+    }
+
+    let pixel = id.xy + render_info.tile_offset;
+    let image_dimensions = vec2<f32>(render_info.image_dimensions);
+    let pixel_index = pixel.y * render_info.image_dimensions.x + pixel.x;
+
+    let samples = max(render_info.samples_per_frame, 1u);
+    let first_sample = (max(render_info.frames_accumulated, 1u) - 1u) * samples;
+    let radius = pixel_filter_radius();
+
+		// the sum of the filtered samples, with the sum of their weights in the last component
+    var color = vec4<f32>(0.0);
+
+    for (var i = 0u; i < samples; i++) {
+        var rng = initial_seed(pixel_index, first_sample + i);
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let color = per_pixel(in.position.xy);
+				// a uniformly jittered position around the pixel center
+        let offset_x = (next_random(&rng) * 2.0 - 1.0) * radius;
+        let offset_y = (next_random(&rng) * 2.0 - 1.0) * radius;
+        let position = vec2<f32>(pixel) + 0.5 + vec2<f32>(offset_x, offset_y);
+
+				// in normalized device coordinates, with y pointing up
+        let coord = vec2<f32>(
+            position.x / image_dimensions.x * 2.0 - 1.0,
+            1.0 - position.y / image_dimensions.y * 2.0,
+        );
+
+        let sample = per_pixel(coord, rng);
+
+				// a rare degenerate path can come out as nan or infinity, which would spoil the pixel for
+				// the rest of the accumulation
+        if is_finite(sample.rgb) {
+            color += sample * pixel_filter_weight(vec2<f32>(offset_x, offset_y));
+        }
+    }
+
+    let index = id.y * screen_dimensions.x + id.x;
 
     if render_info.accumulate != 0 {
-        let index = calculate_index(in.position.xy);
         accumulation[index] += color;
+    } else {
+        accumulation[index] = color;
+    }
+}
+
+// marks the pixels through which the selected object is seen, for the viewer to outline it
+@compute @workgroup_size(8, 8)
+fn cs_selection(@builtin(global_invocation_id) id: vec3<u32>) {
+    let screen_dimensions = render_info.screen_dimensions;
 
-        return accumulation[index] / f32(render_info.frames_accumulated);
+    if any(id.xy >= screen_dimensions) {
+        return;
     }
 
-    return color;
+    let pixel = id.xy + render_info.tile_offset;
+    let image_dimensions = vec2<f32>(render_info.image_dimensions);
+    let position = vec2<f32>(pixel) + 0.5;
+
+    let coord = vec2<f32>(
+        position.x / image_dimensions.x * 2.0 - 1.0,
+        1.0 - position.y / image_dimensions.y * 2.0,
+    );
+
+    var selected = false;
+    let camera_ray = camera_ray(coord);
+
+    if camera_ray.valid {
+        let inverse_view = render_info.inverse_view;
+
+        let origin = (inverse_view * vec4<f32>(camera_ray.origin, 1.0)).xyz;
+        let direction = (inverse_view * vec4<f32>(camera_ray.direction, 0.0)).xyz;
+
+        let hit = trace_ray(Ray(origin, direction));
+
+        if hit.hit_distance >= 0.0 && hit.hit_distance <= camera_ray.max_distance {
+            let triangles = render_info.selected_triangles;
+            let triangle = u32(hit.triangle_index);
+
+            let sphere_selected = hit.sphere_index >= 0 && hit.sphere_index == render_info.selected_sphere;
+            let triangle_selected = hit.triangle_index >= 0 && triangle >= triangles.x && triangle < triangles.y;
+
+            selected = sphere_selected || triangle_selected;
+        }
+    }
+
+    selection_mask[id.y * screen_dimensions.x + id.x] = select(0u, 1u, selected);
 }
 
-fn calculate_index(coord: vec2<f32>) -> u32 {
-    let screen_dimensions = render_info.screen_dimensions;
+// how far from the pixel center samples are taken, in pixels along each axis
+fn pixel_filter_radius() -> f32 {
+    switch render_info.pixel_filter {
+        case PIXEL_FILTER_TENT: {
+            return 1.0;
+        }
+        case PIXEL_FILTER_GAUSSIAN: {
+            return 1.5;
+        }
+        case PIXEL_FILTER_BLACKMAN_HARRIS: {
+            return 2.0;
+        }
+        default: {
+            return 0.5;
+        }
+    }
+}
+
+fn pixel_filter_weight(offset: vec2<f32>) -> f32 {
+    return pixel_filter_weight_1d(offset.x) * pixel_filter_weight_1d(offset.y);
+}
 
-		// convert to [0, 1)
-    let normalized_coord = coord * 0.5 + 0.5;
-    let pixel_coord = vec2<u32>(normalized_coord * vec2<f32>(screen_dimensions));
-    return pixel_coord.y * screen_dimensions.x + pixel_coord.x;
+fn pixel_filter_weight_1d(x: f32) -> f32 {
+    let radius = pixel_filter_radius();
+
+    switch render_info.pixel_filter {
+        case PIXEL_FILTER_TENT: {
+            return max(1.0 - abs(x) / radius, 0.0);
+        }
+        case PIXEL_FILTER_GAUSSIAN: {
+						// a standard deviation of half a pixel
+            return max(exp(-2.0 * x * x) - exp(-2.0 * radius * radius), 0.0);
+        }
+        case PIXEL_FILTER_BLACKMAN_HARRIS: {
+            let t = 2.0 * PI * (x / radius * 0.5 + 0.5);
+
+            return 0.35875 - 0.48829 * cos(t) + 0.14128 * cos(2.0 * t) - 0.01168 * cos(3.0 * t);
+        }
+        default: {
+            return 1.0;
+        }
+    }
 }
 
 struct Ray {
@@ -89,20 +302,51 @@ struct Ray {
 	direction: vec3<f32>,
 }
 
+// a ray leaving the camera in view space
+struct CameraRay {
+	origin: vec3<f32>,
+	direction: vec3<f32>,
+	// how far the ray travels before reaching the far clip plane
+	max_distance: f32,
+	// false outside of the image circle of a fisheye
+	valid: bool,
+}
+
 struct HitPayload {
 	hit_distance: f32,
 	position: vec3<f32>,
+	// always facing against the ray
 	normal: vec3<f32>,
-	object_index: u32
+	material_index: u32,
+	// whether the ray hit the outside of the surface, rather than leaving it from the inside
+	front_face: bool,
+	// the index of the sphere that was hit, or -1 for triangles
+	sphere_index: i32,
+	// the index of the triangle that was hit, or -1 for spheres
+	triangle_index: i32,
 }
 
-fn per_pixel(coord: vec2<f32>) -> vec4<f32> {
+fn per_pixel(coord: vec2<f32>, seed: u32) -> vec4<f32> {
     let inverse_view = render_info.inverse_view;
 
-    let origin = inverse_view[3].xyz;
-    let target_ = render_info.inverse_projection * vec4<f32>(coord, 1.0, 1.0);
+    var rng = seed;
+
+    var camera_ray = camera_ray(coord);
+
+    if !camera_ray.valid {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+
+		// rays through a thin lens converge on the plane at the focus distance in front of the camera
+    let has_lens = render_info.projection == PROJECTION_PERSPECTIVE || render_info.projection == PROJECTION_ORTHOGRAPHIC;
+
+    if has_lens && render_info.aperture_radius > 0.0 {
+        camera_ray = thin_lens_ray(camera_ray, &rng);
+    }
+
 		// cast into world space
-    let direction = (inverse_view * vec4<f32>(normalize(target_.xyz / target_.w), 0.0)).xyz;
+    let origin = (inverse_view * vec4<f32>(camera_ray.origin, 1.0)).xyz;
+    let direction = (inverse_view * vec4<f32>(camera_ray.direction, 0.0)).xyz;
 
     var ray = Ray(origin, direction);
     let bounces = 5;
@@ -110,59 +354,205 @@ fn per_pixel(coord: vec2<f32>) -> vec4<f32> {
     var light = vec3<f32>(0.0);
     var contribution = vec3<f32>(1.0);
 
-    var rng = initial_seed(coord);
+		// the probability of the last bounce direction, for weighing hitting a light against sampling
+		// it directly, or zero if lights weren't sampled at the last bounce
+    var previous_pdf = 0.0;
 
     for (var i = 0; i < bounces; i++) {
         let hit = trace_ray(ray);
 
-        if hit.hit_distance < 0.0 {
-            light += render_info.sky_color * contribution;
+				// anything beyond the far clip plane is cut away from the camera's view
+        let clipped = i == 0 && hit.hit_distance > camera_ray.max_distance;
+
+        if hit.hit_distance < 0.0 || clipped {
+            light += sky_radiance(ray.direction, previous_pdf) * contribution;
 						break;
         }
 
-        let sphere = spheres[hit.object_index];
-        let material = materials[sphere.material_index];
+        let material = materials[hit.material_index];
+
+				// light traveling through the inside of a transmissive material is absorbed along the way
+        if !hit.front_face {
+            contribution *= pow(max(material.absorption_color, vec3<f32>(1e-4)), vec3<f32>(hit.hit_distance));
+        }
+
+        var emission_weight = 1.0;
+
+        if previous_pdf > 0.0 && hit.sphere_index >= 0 {
+            let light_pdf = sphere_light_pdf(spheres[hit.sphere_index], ray.origin);
+            emission_weight = power_heuristic(previous_pdf, light_pdf);
+        }
+
+        light += material.emission_color * material.emission_strength * contribution * emission_weight;
+
+				// only the reflective part of the material is lit directly
+        let brdf_probability = 1.0 - material.transmission;
+
+        if brdf_probability > 0.0 && light_count() > 0u {
+            light += sample_direct_light(material, hit, -ray.direction, brdf_probability, &rng) * contribution;
+        }
+
+        let scatter = sample_bsdf(material, hit, -ray.direction, &rng);
+        previous_pdf = scatter.pdf;
 
-        contribution *= material.albedo;
-        light += material.emission_color * material.emission_strength;
+        if all(scatter.weight == vec3<f32>(0.0)) {
+            break;
+        }
 
-        let scatter_direction = next_random_vec3(&rng) * material.roughness;
+        contribution *= scatter.weight;
 
-        ray.origin = hit.position + hit.normal * 0.0001;
-        ray.direction = normalize(hit.normal + scatter_direction);
+				// offset to the side of the surface the ray continues on, so that it doesn't hit itself
+        let side = select(-1.0, 1.0, dot(scatter.direction, hit.normal) > 0.0);
+
+        ray.origin = hit.position + hit.normal * side * 0.0001;
+        ray.direction = scatter.direction;
     }
 
     return vec4<f32>(light, 1.0);
 }
 
+// the ray through the center of the lens at the given normalized device coordinate, in view space
+fn camera_ray(coord: vec2<f32>) -> CameraRay {
+    var direction: vec3<f32>;
+
+    switch render_info.projection {
+        case PROJECTION_FISHEYE: {
+            let angles = coord * render_info.field_of_view * 0.5;
+            let theta = length(angles);
+
+            if theta > PI {
+                return CameraRay(vec3<f32>(0.0), vec3<f32>(0.0, 0.0, -1.0), 0.0, false);
+            }
+
+            let phi = atan2(angles.y, angles.x);
+
+            direction = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), -cos(theta));
+        }
+        case PROJECTION_EQUIRECTANGULAR: {
+            let longitude = coord.x * PI;
+            let latitude = coord.y * PI * 0.5;
+
+            direction = vec3<f32>(sin(longitude) * cos(latitude), sin(latitude), -cos(longitude) * cos(latitude));
+        }
+        default: {
+						// the points on the near and far clip planes
+            let near_point = render_info.inverse_projection * vec4<f32>(coord, 0.0, 1.0);
+            let far_point = render_info.inverse_projection * vec4<f32>(coord, 1.0, 1.0);
+
+            let origin = near_point.xyz / near_point.w;
+            let to_far = far_point.xyz / far_point.w - origin;
+
+            return CameraRay(origin, normalize(to_far), length(to_far), true);
+        }
+    }
+
+    return CameraRay(direction * render_info.near, direction, render_info.far - render_info.near, true);
+}
+
+// moves a ray through the center of the lens to a random point on the lens, aimed at the same point
+// on the focus plane and clipped by the same planes
+fn thin_lens_ray(ray: CameraRay, rng: ptr<function, u32>) -> CameraRay {
+		// where the ray crosses the lens and the focus plane
+    let lens_center = ray.origin - ray.direction * (ray.origin.z / ray.direction.z);
+    let focus_point = ray.origin + ray.direction * ((-render_info.focus_distance - ray.origin.z) / ray.direction.z);
+
+    let lens_position = lens_center + vec3<f32>(sample_aperture(rng) * render_info.aperture_radius, 0.0);
+    let direction = normalize(focus_point - lens_position);
+
+		// the depths of the clip planes
+    let near = ray.origin.z;
+    let far = ray.origin.z + ray.direction.z * ray.max_distance;
+
+    let near_distance = (near - lens_position.z) / direction.z;
+    let far_distance = (far - lens_position.z) / direction.z;
+
+    return CameraRay(lens_position + direction * near_distance, direction, far_distance - near_distance, true);
+}
 
 fn trace_ray(ray: Ray) -> HitPayload {
     var closest_sphere = -1;
+    var closest_triangle = -1;
     var hit_distance = bitcast<f32>(0x7f800000);
+    var barycentrics = vec2<f32>(0.0);
+
+    let inverse_direction = 1.0 / ray.direction;
+
+    var stack: array<u32, BVH_STACK_SIZE>;
+    var stack_size = 0u;
+
+    if intersect_aabb(ray, inverse_direction, bvh_nodes[0]) < hit_distance {
+        stack[0] = 0u;
+        stack_size = 1u;
+    }
+
+    while stack_size > 0u {
+        stack_size -= 1u;
+        let node = bvh_nodes[stack[stack_size]];
 
-    for (var i = 0; i < i32(arrayLength(&spheres)); i++) {
-        let sphere = spheres[i];
+        if node.primitive_count > 0u {
+            for (var i = 0u; i < node.primitive_count; i++) {
+                let primitive = bvh_primitives[node.first_index + i];
 
-        let origin = ray.origin - sphere.position.xyz;
+                if (primitive & TRIANGLE_BIT) != 0u {
+                    let triangle_index = primitive & ~TRIANGLE_BIT;
+                    let hit = intersect_triangle(ray, triangles[triangle_index]);
 
-        let a = dot(ray.direction, ray.direction);
-        let b = 2.0 * dot(origin, ray.direction);
-        let c = dot(origin, origin) - sphere.radius * sphere.radius;
+                    if hit.x >= 0 && hit.x < hit_distance {
+                        hit_distance = hit.x;
+                        barycentrics = hit.yz;
+                        closest_triangle = i32(triangle_index);
+                        closest_sphere = -1;
+                    }
+                } else {
+                    let t = intersect_sphere(ray, spheres[primitive]);
 
-        let discriminant = (b * b) - (4.0 * a * c);
+                    if t >= 0 && t < hit_distance {
+                        hit_distance = t;
+                        closest_sphere = i32(primitive);
+                        closest_triangle = -1;
+                    }
+                }
+            }
 
-        if discriminant <= 0 {
-					continue;
+            continue;
         }
 
-        let t = (-b - sqrt(discriminant)) / (2.0 * a);
+				// the root of an empty hierarchy
+        if node.first_index == 0u {
+            continue;
+        }
+
+        var near = node.first_index;
+        var far = node.first_index + 1u;
+        var near_distance = intersect_aabb(ray, inverse_direction, bvh_nodes[near]);
+        var far_distance = intersect_aabb(ray, inverse_direction, bvh_nodes[far]);
+
+        if far_distance < near_distance {
+            let index = near;
+            near = far;
+            far = index;
+
+            let distance = near_distance;
+            near_distance = far_distance;
+            far_distance = distance;
+        }
+
+				// push the nearer child last, so that it is visited first and can cull the other one
+        if far_distance < hit_distance {
+            stack[stack_size] = far;
+            stack_size += 1u;
+        }
 
-        if t >= 0 && t < hit_distance {
-            hit_distance = t;
-            closest_sphere = i;
+        if near_distance < hit_distance {
+            stack[stack_size] = near;
+            stack_size += 1u;
         }
     }
 
+    if closest_triangle != -1 {
+        return closest_triangle_hit(ray, hit_distance, u32(closest_triangle), barycentrics);
+    }
+
     if closest_sphere == -1 {
         return miss(ray);
     }
@@ -170,36 +560,769 @@ fn trace_ray(ray: Ray) -> HitPayload {
     return closest_hit(ray, hit_distance, u32(closest_sphere));
 }
 
+// The distance along the ray to where it first hits the sphere, from either the outside or the
+// inside, or a negative distance on a miss.
+fn intersect_sphere(ray: Ray, sphere: Sphere) -> f32 {
+    let origin = ray.origin - sphere.position.xyz;
+
+    let a = dot(ray.direction, ray.direction);
+    let b = 2.0 * dot(origin, ray.direction);
+    let c = dot(origin, origin) - sphere.radius * sphere.radius;
+
+    let discriminant = (b * b) - (4.0 * a * c);
+
+    if discriminant <= 0 {
+        return -1.0;
+    }
+
+    let near = (-b - sqrt(discriminant)) / (2.0 * a);
+
+    if near >= 0 {
+        return near;
+    }
+
+    return (-b + sqrt(discriminant)) / (2.0 * a);
+}
+
+// Slab test against the bounds of a node, returning the distance along the ray to where it enters
+// them, or infinity on a miss.
+fn intersect_aabb(ray: Ray, inverse_direction: vec3<f32>, node: BvhNode) -> f32 {
+    let t0 = (node.minimum - ray.origin) * inverse_direction;
+    let t1 = (node.maximum - ray.origin) * inverse_direction;
+
+    let t_near = min(t0, t1);
+    let t_far = max(t0, t1);
+
+    let entry = max(max(t_near.x, t_near.y), t_near.z);
+    let exit = min(min(t_far.x, t_far.y), t_far.z);
+
+    if entry > exit || exit < 0.0 {
+        return bitcast<f32>(0x7f800000);
+    }
+
+    return max(entry, 0.0);
+}
+
+// Möller–Trumbore intersection, returning the distance along the ray followed by the barycentric
+// coordinates of the second and third corners, or a negative distance on a miss.
+fn intersect_triangle(ray: Ray, triangle: Triangle) -> vec3<f32> {
+    let v0 = vertices[triangle.indices.x].position;
+    let v1 = vertices[triangle.indices.y].position;
+    let v2 = vertices[triangle.indices.z].position;
+
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+
+    let p = cross(ray.direction, edge2);
+    let determinant = dot(edge1, p);
+
+		// parallel to the triangle, or a degenerate triangle
+    if abs(determinant) < 1e-8 {
+        return vec3<f32>(-1.0);
+    }
+
+    let inverse_determinant = 1.0 / determinant;
+    let s = ray.origin - v0;
+
+    let u = dot(s, p) * inverse_determinant;
+
+    if u < 0 || u > 1 {
+        return vec3<f32>(-1.0);
+    }
+
+    let q = cross(s, edge1);
+    let v = dot(ray.direction, q) * inverse_determinant;
+
+    if v < 0 || u + v > 1 {
+        return vec3<f32>(-1.0);
+    }
+
+    return vec3<f32>(dot(edge2, q) * inverse_determinant, u, v);
+}
+
 fn closest_hit(ray: Ray, hit_distance: f32, object_index: u32) -> HitPayload {
     var payload: HitPayload;
 
     payload.hit_distance = hit_distance;
-    payload.object_index = object_index;
 
     let sphere = spheres[object_index];
     let origin = ray.origin - sphere.position.xyz;
 
+    payload.material_index = sphere.material_index;
+    payload.sphere_index = i32(object_index);
+    payload.triangle_index = -1;
     payload.position = origin + ray.direction * hit_distance;
-    payload.normal = normalize(payload.position);
+
+    let normal = normalize(payload.position);
+    payload.front_face = dot(normal, ray.direction) < 0.0;
+    payload.normal = select(-normal, normal, payload.front_face);
+
     payload.position += sphere.position.xyz;
 
     return payload;
 }
 
+fn closest_triangle_hit(ray: Ray, hit_distance: f32, triangle_index: u32, barycentrics: vec2<f32>) -> HitPayload {
+    var payload: HitPayload;
+
+    let triangle = triangles[triangle_index];
+
+    let n0 = vertices[triangle.indices.x].normal;
+    let n1 = vertices[triangle.indices.y].normal;
+    let n2 = vertices[triangle.indices.z].normal;
+
+    let normal = normalize(
+        n0 * (1.0 - barycentrics.x - barycentrics.y) + n1 * barycentrics.x + n2 * barycentrics.y
+    );
+
+    payload.hit_distance = hit_distance;
+    payload.material_index = triangle.material_index;
+    payload.sphere_index = -1;
+    payload.triangle_index = i32(triangle_index);
+    payload.position = ray.origin + ray.direction * hit_distance;
+		// triangles are two sided, with the outside being the side the normals face
+    payload.front_face = dot(normal, ray.direction) < 0.0;
+    payload.normal = select(-normal, normal, payload.front_face);
+
+    return payload;
+}
+
 fn miss(ray: Ray) -> HitPayload {
     var payload: HitPayload;
     payload.hit_distance = -1.0;
+    payload.sphere_index = -1;
+    payload.triangle_index = -1;
 
     return payload;
 }
 
-fn initial_seed(coord: vec2<f32>) -> u32 {
-    let translated = coord * 0.5 + 0.5;
-    let x = translated.x * 1000.0;
-    let y = translated.y * 1000.0;
-    let t = pow(render_info.time, 2.0) * 1000.0;
+struct BsdfSample {
+	direction: vec3<f32>,
+	// the bsdf times the cosine term divided by the probability of the direction, or zero if the
+	// path was absorbed
+	weight: vec3<f32>,
+	// the probability of the direction for the lobes lights are sampled for, or zero otherwise
+	pdf: f32,
+}
+
+struct BrdfEvaluation {
+	// the brdf times the cosine term
+	value: vec3<f32>,
+	// the probability of `sample_brdf` choosing the direction, or zero if it is below the surface
+	pdf: f32,
+}
+
+// Samples a direction to continue a path in, either passing through a transmissive surface or
+// reflecting off of it.
+fn sample_bsdf(material: Material, hit: HitPayload, view: vec3<f32>, rng: ptr<function, u32>) -> BsdfSample {
+    if next_random(rng) < material.transmission {
+        return sample_dielectric(material, hit, view, rng);
+    }
+
+    var sample = sample_brdf(material, hit.normal, view, rng);
+    sample.pdf *= 1.0 - material.transmission;
+
+    return sample;
+}
+
+struct LightSample {
+	direction: vec3<f32>,
+	// the light arriving from the direction, if nothing is in the way
+	radiance: vec3<f32>,
+	// the probability of the direction for this light alone, or zero if it can't be sampled
+	pdf: f32,
+}
+
+// The number of lights sampled directly, with the environment map or the sun of the procedural
+// sky counting as one.
+fn light_count() -> u32 {
+    return lights.count + select(0u, 1u, environment.enabled != 0u || render_info.procedural_sky != 0u);
+}
+
+// Picks a random light and samples a direction towards it, returning the light reaching the
+// surface from it if it is not occluded, weighted against the brdf also finding it.
+fn sample_direct_light(material: Material, hit: HitPayload, view: vec3<f32>, brdf_probability: f32, rng: ptr<function, u32>) -> vec3<f32> {
+    let light_count = light_count();
+    let light_index = min(u32(next_random(rng) * f32(light_count)), light_count - 1u);
+		// the sky comes after every sphere
+    let is_sky = light_index == lights.count;
+
+    var sample: LightSample;
+
+    if is_sky && environment.enabled != 0u {
+        sample = sample_environment(rng);
+    } else if is_sky {
+        sample = sample_sun(rng);
+    } else {
+        sample = sample_sphere_light(spheres[lights.spheres[light_index]], hit.position, rng);
+    }
+
+    if sample.pdf <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+
+    let evaluation = evaluate_brdf(material, hit.normal, view, sample.direction);
+
+    if evaluation.pdf <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+
+    let shadow_hit = trace_ray(Ray(hit.position + hit.normal * 0.0001, sample.direction));
+
+    var visible: bool;
+
+    if is_sky {
+				// the sky is only visible if nothing is hit at all
+        visible = shadow_hit.hit_distance < 0.0;
+    } else {
+        visible = shadow_hit.sphere_index == i32(lights.spheres[light_index]);
+    }
+
+    if !visible {
+        return vec3<f32>(0.0);
+    }
+
+    let light_pdf = sample.pdf / f32(light_count);
+    let weight = power_heuristic(light_pdf, evaluation.pdf * brdf_probability);
+
+    return sample.radiance * evaluation.value * brdf_probability * weight / light_pdf;
+}
+
+// Samples the cone of directions the sphere covers from the position uniformly.
+fn sample_sphere_light(sphere: Sphere, position: vec3<f32>, rng: ptr<function, u32>) -> LightSample {
+    let to_center = sphere.position.xyz - position;
+    let distance_squared = dot(to_center, to_center);
+    let radius_squared = sphere.radius * sphere.radius;
+
+		// the surface is inside of the light
+    if distance_squared <= radius_squared {
+        return LightSample(vec3<f32>(0.0), vec3<f32>(0.0), 0.0);
+    }
+
+    let sin_max_squared = radius_squared / distance_squared;
+    let cos_max = sqrt(max(1.0 - sin_max_squared, 0.0));
+    let one_minus_cos_max = sin_max_squared / (1.0 + cos_max);
+
+    let cos_theta = 1.0 - next_random(rng) * one_minus_cos_max;
+    let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+    let phi = 2.0 * PI * next_random(rng);
+
+    let direction = orthonormal_basis(normalize(to_center)) * vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+
+    let material = materials[sphere.material_index];
+    let emission = material.emission_color * material.emission_strength;
+
+    return LightSample(direction, emission, 1.0 / (2.0 * PI * one_minus_cos_max));
+}
+
+// The probability of `sample_direct_light` choosing a direction that hits the sphere from the
+// position.
+fn sphere_light_pdf(sphere: Sphere, position: vec3<f32>) -> f32 {
+    let to_center = sphere.position.xyz - position;
+    let distance_squared = dot(to_center, to_center);
+    let radius_squared = sphere.radius * sphere.radius;
+
+    if distance_squared <= radius_squared {
+        return 0.0;
+    }
+
+    let sin_max_squared = radius_squared / distance_squared;
+    let cos_max = sqrt(max(1.0 - sin_max_squared, 0.0));
+    let one_minus_cos_max = sin_max_squared / (1.0 + cos_max);
+
+    return 1.0 / (2.0 * PI * one_minus_cos_max * f32(light_count()));
+}
+
+// The light arriving along a ray that doesn't hit anything, given the probability of the bounce
+// that sent it, with the parts of the sky that are also sampled directly weighted against it.
+fn sky_radiance(direction: vec3<f32>, previous_pdf: f32) -> vec3<f32> {
+    if environment.enabled != 0u {
+        var weight = 1.0;
+
+        if previous_pdf > 0.0 {
+            weight = power_heuristic(previous_pdf, environment_pdf(direction) / f32(light_count()));
+        }
+
+        return environment_radiance(direction) * weight;
+    }
+
+    if render_info.procedural_sky != 0u {
+        let sun = sun_radiance(direction);
+        var weight = 1.0;
+
+        if previous_pdf > 0.0 && any(sun > vec3<f32>(0.0)) {
+            weight = power_heuristic(previous_pdf, sun_pdf() / f32(light_count()));
+        }
+
+        return preetham_sky(direction) + sun * weight;
+    }
+
+    return render_info.sky_color;
+}
+
+// The light arriving from the environment map along the direction.
+fn environment_radiance(direction: vec3<f32>) -> vec3<f32> {
+    let pixel = environment_pixel(direction_to_equirect(direction));
+
+    return textureLoad(environment_map, pixel, 0).rgb * environment.intensity;
+}
+
+// Picks a direction with a probability proportional to the brightness of the environment map,
+// by picking a row and then a column within it, as in "Physically Based Rendering" by Pharr et al.
+fn sample_environment(rng: ptr<function, u32>) -> LightSample {
+    let size = textureDimensions(environment_map);
+
+    let row = search_cdf(environment_marginal_cdf, 0u, size.y, next_random(rng));
+    let column = search_cdf(environment_conditional_cdf, row, size.x, next_random(rng));
+
+		// pick a point within the pixel uniformly
+    let uv = (vec2<f32>(f32(column), f32(row)) + vec2<f32>(next_random(rng), next_random(rng))) / vec2<f32>(size);
+    let direction = equirect_to_direction(uv);
+
+    return LightSample(direction, environment_radiance(direction), environment_pdf(direction));
+}
+
+// The probability of `sample_environment` choosing the direction.
+fn environment_pdf(direction: vec3<f32>) -> f32 {
+    let size = textureDimensions(environment_map);
+    let uv = direction_to_equirect(direction);
+    let pixel = environment_pixel(uv);
+
+    let row_probability = cdf_step(environment_marginal_cdf, pixel.y, 0u);
+    let column_probability = cdf_step(environment_conditional_cdf, pixel.x, pixel.y);
+
+		// the density over the image, converted to one over the sphere of directions
+    let image_pdf = row_probability * column_probability * f32(size.x * size.y);
+    let sin_theta = sin(uv.y * PI);
+
+    if sin_theta <= 0.0 {
+        return 0.0;
+    }
 
-    return u32(x) ^ (u32(y) << 16u) ^ u32(t);
+    return image_pdf / (2.0 * PI * PI * sin_theta);
+}
+
+// The light scattered towards the direction by a clear sky, from the Perez distribution fit to the
+// turbidity and the position of the sun in "A Practical Analytic Model for Daylight" by Preetham
+// et al.
+fn preetham_sky(direction: vec3<f32>) -> vec3<f32> {
+    let turbidity = render_info.turbidity;
+    let sun_direction = render_info.sun_direction;
+
+		// the model only covers the upper hemisphere, so everything below shows the horizon
+    let cos_theta = max(direction.y, 0.001);
+    let cos_gamma = clamp(dot(direction, sun_direction), -1.0, 1.0);
+    let gamma = acos(cos_gamma);
+
+    let theta_sun = acos(clamp(sun_direction.y, 0.0, 1.0));
+    let theta_sun_2 = theta_sun * theta_sun;
+    let theta_sun_3 = theta_sun_2 * theta_sun;
+    let turbidity_2 = turbidity * turbidity;
+
+    let chi = (4.0 / 9.0 - turbidity / 120.0) * (PI - 2.0 * theta_sun);
+    let zenith_luminance = (4.0453 * turbidity - 4.9710) * tan(chi) - 0.2155 * turbidity + 2.4192;
+
+    let zenith_x = turbidity_2 * (0.00166 * theta_sun_3 - 0.00375 * theta_sun_2 + 0.00209 * theta_sun)
+        + turbidity * (-0.02903 * theta_sun_3 + 0.06377 * theta_sun_2 - 0.03202 * theta_sun + 0.00394)
+        + (0.11693 * theta_sun_3 - 0.21196 * theta_sun_2 + 0.06052 * theta_sun + 0.25886);
+    let zenith_y = turbidity_2 * (0.00275 * theta_sun_3 - 0.00610 * theta_sun_2 + 0.00317 * theta_sun)
+        + turbidity * (-0.04214 * theta_sun_3 + 0.08970 * theta_sun_2 - 0.04153 * theta_sun + 0.00516)
+        + (0.15346 * theta_sun_3 - 0.26756 * theta_sun_2 + 0.06670 * theta_sun + 0.26688);
+
+		// the coefficients of the distribution for the luminance and both chromaticities
+    let coefficients = PerezCoefficients(
+        vec3<f32>(0.1787, -0.0193, -0.0167) * turbidity + vec3<f32>(-1.4630, -0.2592, -0.2608),
+        vec3<f32>(-0.3554, -0.0665, -0.0950) * turbidity + vec3<f32>(0.4275, 0.0008, 0.0092),
+        vec3<f32>(-0.0227, -0.0004, -0.0079) * turbidity + vec3<f32>(5.3251, 0.2125, 0.2102),
+        vec3<f32>(0.1206, -0.0641, -0.0441) * turbidity + vec3<f32>(-2.5771, -0.8989, -1.6537),
+        vec3<f32>(-0.0670, -0.0033, -0.0109) * turbidity + vec3<f32>(0.3703, 0.0452, 0.0529),
+    );
+
+    let distribution = perez(coefficients, cos_theta, gamma, cos_gamma);
+    let zenith_distribution = perez(coefficients, 1.0, theta_sun, cos(theta_sun));
+
+    let yxy = vec3<f32>(zenith_luminance, zenith_x, zenith_y) * distribution / zenith_distribution;
+    let luminance = yxy.x * SKY_LUMINANCE_SCALE;
+
+    let xyz = vec3<f32>(yxy.y / yxy.z * luminance, luminance, (1.0 - yxy.y - yxy.z) / yxy.z * luminance);
+
+    return max(xyz_to_linear_srgb(xyz), vec3<f32>(0.0));
+}
+
+// The coefficients of the Perez sky distribution, for all three channels at once.
+struct PerezCoefficients {
+	a: vec3<f32>,
+	b: vec3<f32>,
+	c: vec3<f32>,
+	d: vec3<f32>,
+	e: vec3<f32>,
+}
+
+fn perez(coefficients: PerezCoefficients, cos_theta: f32, gamma: f32, cos_gamma: f32) -> vec3<f32> {
+    let a = coefficients.a;
+    let b = coefficients.b;
+    let c = coefficients.c;
+    let d = coefficients.d;
+    let e = coefficients.e;
+
+    return (1.0 + a * exp(b / cos_theta)) * (1.0 + c * exp(d * gamma) + e * cos_gamma * cos_gamma);
+}
+
+fn xyz_to_linear_srgb(xyz: vec3<f32>) -> vec3<f32> {
+    return mat3x3<f32>(
+        vec3<f32>(3.2406, -0.9689, 0.0557),
+        vec3<f32>(-1.5372, 1.8758, -0.2040),
+        vec3<f32>(-0.4986, 0.0415, 1.0570),
+    ) * xyz;
+}
+
+// The light arriving from the sun disk along the direction, or zero outside of it.
+fn sun_radiance(direction: vec3<f32>) -> vec3<f32> {
+    if dot(direction, render_info.sun_direction) < cos(render_info.sun_angular_radius) {
+        return vec3<f32>(0.0);
+    }
+
+    return sun_disk_radiance();
+}
+
+// The light arriving from every point of the sun disk.
+fn sun_disk_radiance() -> vec3<f32> {
+		// spread the illuminance over the disk, so that its size doesn't change how bright it is
+    let sin_radius = sin(render_info.sun_angular_radius);
+    let illuminance = SUN_ILLUMINANCE * SKY_LUMINANCE_SCALE * render_info.sun_intensity;
+
+    return sun_transmittance(render_info.sun_direction.y) * illuminance / (PI * sin_radius * sin_radius);
+}
+
+// The fraction of red, green and blue sunlight making it through the atmosphere, from Rayleigh
+// scattering by air and Mie scattering by aerosols, as in the appendix of Preetham et al.
+fn sun_transmittance(cos_theta: f32) -> vec3<f32> {
+    let theta = acos(clamp(cos_theta, 0.0, 1.0));
+		// the relative length of the path through the atmosphere
+    let air_mass = 1.0 / (cos(theta) + 0.15 * pow(93.885 - degrees(theta), -1.253));
+
+		// the wavelengths of red, green and blue light in micrometers
+    let wavelengths = vec3<f32>(0.680, 0.550, 0.440);
+
+    let beta = 0.04608366 * render_info.turbidity - 0.04586026;
+    let rayleigh = exp(-0.008735 * pow(wavelengths, vec3<f32>(-4.08)) * air_mass);
+    let aerosol = exp(-beta * pow(wavelengths, vec3<f32>(-1.3)) * air_mass);
+
+    return rayleigh * aerosol;
+}
+
+// Samples the cone of directions the sun disk covers uniformly.
+fn sample_sun(rng: ptr<function, u32>) -> LightSample {
+    let sin_half_radius = sin(render_info.sun_angular_radius * 0.5);
+    let one_minus_cos_max = 2.0 * sin_half_radius * sin_half_radius;
+
+    let cos_theta = 1.0 - next_random(rng) * one_minus_cos_max;
+    let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+    let phi = 2.0 * PI * next_random(rng);
+
+    let direction = orthonormal_basis(render_info.sun_direction) * vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+
+    return LightSample(direction, sun_disk_radiance(), sun_pdf());
+}
+
+// The probability of `sample_sun` choosing any direction within the sun disk.
+fn sun_pdf() -> f32 {
+    let sin_half_radius = sin(render_info.sun_angular_radius * 0.5);
+
+    return 1.0 / (4.0 * PI * sin_half_radius * sin_half_radius);
+}
+
+// Maps a direction to a position on the environment map, with the top of the image straight up
+// and its center along the negative Z axis before rotating.
+fn direction_to_equirect(direction: vec3<f32>) -> vec2<f32> {
+    let phi = atan2(direction.x, -direction.z) - environment.rotation;
+    let theta = acos(clamp(direction.y, -1.0, 1.0));
+
+    return vec2<f32>(fract(phi / (2.0 * PI) + 0.5), theta / PI);
+}
+
+// The inverse of `direction_to_equirect`.
+fn equirect_to_direction(uv: vec2<f32>) -> vec3<f32> {
+    let phi = (uv.x - 0.5) * 2.0 * PI + environment.rotation;
+    let theta = uv.y * PI;
+
+    return vec3<f32>(sin(theta) * sin(phi), cos(theta), -sin(theta) * cos(phi));
+}
+
+fn environment_pixel(uv: vec2<f32>) -> vec2<u32> {
+    let size = textureDimensions(environment_map);
+
+    return min(vec2<u32>(uv * vec2<f32>(size)), size - 1u);
+}
+
+// Finds the first entry in a row of a cumulative distribution that is larger than `u`.
+fn search_cdf(cdf: texture_2d<f32>, row: u32, count: u32, u: f32) -> u32 {
+    var low = 0u;
+    var high = count - 1u;
+
+    while low < high {
+        let middle = (low + high) / 2u;
+
+        if textureLoad(cdf, vec2<u32>(middle, row), 0).r > u {
+            high = middle;
+        } else {
+            low = middle + 1u;
+        }
+    }
+
+    return low;
+}
+
+// The probability of picking an entry of a cumulative distribution.
+fn cdf_step(cdf: texture_2d<f32>, index: u32, row: u32) -> f32 {
+    var previous = 0.0;
+
+    if index > 0u {
+        previous = textureLoad(cdf, vec2<u32>(index - 1u, row), 0).r;
+    }
+
+    return textureLoad(cdf, vec2<u32>(index, row), 0).r - previous;
+}
+
+// Weighs a sample from one strategy against another that could have produced it, as in "Optimally
+// Combining Sampling Techniques for Monte Carlo Rendering" by Veach and Guibas.
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let squared = pdf * pdf;
+    return squared / (squared + other_pdf * other_pdf);
+}
+
+// Samples a rough dielectric interface, either reflecting off of a microfacet or refracting
+// through it depending on the fresnel term, following "Microfacet Models for Refraction through
+// Rough Surfaces" by Walter et al.
+fn sample_dielectric(material: Material, hit: HitPayload, view: vec3<f32>, rng: ptr<function, u32>) -> BsdfSample {
+    let alpha = max(material.roughness * material.roughness, MIN_ALPHA);
+		// the ratio of the index of refraction on the side of the ray to the one on the other side
+    let eta = select(material.ior, 1.0 / material.ior, hit.front_face);
+
+    let normal = hit.normal;
+    let microfacet = orthonormal_basis(normal) * sample_ggx(alpha, rng);
+
+    let v_dot_m = dot(view, microfacet);
+
+    var sample: BsdfSample;
+    sample.weight = vec3<f32>(0.0);
+
+    if v_dot_m <= 0.0 {
+        return sample;
+    }
+
+		// the fresnel term is one on total internal reflection, so the refraction is always valid
+    let reflected = next_random(rng) < fresnel_dielectric(v_dot_m, eta);
+
+    if reflected {
+        sample.direction = reflect(-view, microfacet);
+    } else {
+        sample.direction = refract(-view, microfacet, eta);
+    }
+
+    let n_dot_l = dot(normal, sample.direction);
+
+		// ended up on the wrong side of the macro surface
+    if (n_dot_l > 0.0) != reflected || n_dot_l == 0.0 {
+        return sample;
+    }
+
+    let n_dot_v = max(dot(normal, view), 1e-4);
+    let n_dot_m = max(dot(normal, microfacet), 1e-4);
+    let masking = smith_ggx_masking(n_dot_v, alpha) * smith_ggx_masking(abs(n_dot_l), alpha);
+
+    sample.weight = vec3<f32>(v_dot_m * masking / (n_dot_v * n_dot_m));
+
+    return sample;
+}
+
+// The unpolarized fresnel reflectance of an interface between two dielectrics.
+fn fresnel_dielectric(cos_incident: f32, eta: f32) -> f32 {
+    let sin_transmitted_squared = eta * eta * (1.0 - cos_incident * cos_incident);
+
+    if sin_transmitted_squared >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_transmitted = sqrt(1.0 - sin_transmitted_squared);
+
+    let perpendicular = (eta * cos_incident - cos_transmitted) / (eta * cos_incident + cos_transmitted);
+    let parallel = (cos_incident - eta * cos_transmitted) / (cos_incident + eta * cos_transmitted);
+
+    return 0.5 * (perpendicular * perpendicular + parallel * parallel);
+}
+
+// Samples a direction to continue a path in, choosing between the lambertian diffuse lobe and
+// the ggx specular lobe by how much light each of them reflects.
+fn sample_brdf(material: Material, normal: vec3<f32>, view: vec3<f32>, rng: ptr<function, u32>) -> BsdfSample {
+    let alpha = max(material.roughness * material.roughness, MIN_ALPHA);
+    let basis = orthonormal_basis(normal);
+
+    var sample: BsdfSample;
+
+    if next_random(rng) < specular_probability(material, normal, view) {
+        let half_vector = basis * sample_ggx(alpha, rng);
+        sample.direction = reflect(-view, half_vector);
+    } else {
+        sample.direction = basis * sample_cosine_hemisphere(rng);
+    }
+
+    let evaluation = evaluate_brdf(material, normal, view, sample.direction);
+
+		// reflected below the surface
+    if evaluation.pdf <= 0.0 {
+        sample.weight = vec3<f32>(0.0);
+        return sample;
+    }
+
+    sample.weight = evaluation.value / evaluation.pdf;
+    sample.pdf = evaluation.pdf;
+
+    return sample;
+}
+
+fn evaluate_brdf(material: Material, normal: vec3<f32>, view: vec3<f32>, direction: vec3<f32>) -> BrdfEvaluation {
+    var evaluation: BrdfEvaluation;
+
+    let n_dot_l = dot(normal, direction);
+
+    if n_dot_l <= 0.0 {
+        return evaluation;
+    }
+
+    let alpha = max(material.roughness * material.roughness, MIN_ALPHA);
+    let specular_color = mix(vec3<f32>(0.04), material.albedo, material.metallic);
+    let diffuse_color = material.albedo * (1.0 - material.metallic);
+
+    let n_dot_v = max(dot(normal, view), 1e-4);
+
+    let half_vector = normalize(view + direction);
+    let n_dot_h = max(dot(normal, half_vector), 0.0);
+    let v_dot_h = max(dot(view, half_vector), 1e-4);
+
+    let fresnel = fresnel_schlick(specular_color, v_dot_h);
+    let distribution = ggx_distribution(n_dot_h, alpha);
+    let masking = smith_ggx_masking(n_dot_v, alpha) * smith_ggx_masking(n_dot_l, alpha);
+
+    let specular = fresnel * distribution * masking / (4.0 * n_dot_v * n_dot_l);
+    let diffuse = (1.0 - fresnel) * diffuse_color / PI;
+
+		// the probability of the direction with either lobe, weighted by how often it is chosen
+    let specular_pdf = distribution * n_dot_h / (4.0 * v_dot_h);
+    let diffuse_pdf = n_dot_l / PI;
+
+    evaluation.value = (diffuse + specular) * n_dot_l;
+    evaluation.pdf = mix(diffuse_pdf, specular_pdf, specular_probability(material, normal, view));
+
+    return evaluation;
+}
+
+// How often the specular lobe is sampled rather than the diffuse lobe, by how much light each of
+// them reflects.
+fn specular_probability(material: Material, normal: vec3<f32>, view: vec3<f32>) -> f32 {
+    let specular_color = mix(vec3<f32>(0.04), material.albedo, material.metallic);
+    let diffuse_color = material.albedo * (1.0 - material.metallic);
+
+    let n_dot_v = max(dot(normal, view), 1e-4);
+
+    let specular_weight = luminance(fresnel_schlick(specular_color, n_dot_v));
+    let diffuse_weight = luminance(diffuse_color) * (1.0 - specular_weight);
+
+    return specular_weight / max(specular_weight + diffuse_weight, 1e-4);
+}
+
+fn fresnel_schlick(specular_color: vec3<f32>, cos_theta: f32) -> vec3<f32> {
+    return specular_color + (1.0 - specular_color) * pow(1.0 - cos_theta, 5.0);
+}
+
+fn ggx_distribution(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha_squared = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha_squared - 1.0) + 1.0;
+
+    return alpha_squared / (PI * denominator * denominator);
+}
+
+// The separable smith masking function of a single direction.
+fn smith_ggx_masking(n_dot_x: f32, alpha: f32) -> f32 {
+    let alpha_squared = alpha * alpha;
+
+    return 2.0 * n_dot_x / (n_dot_x + sqrt(alpha_squared + (1.0 - alpha_squared) * n_dot_x * n_dot_x));
+}
+
+// Samples a microfacet normal proportionally to the ggx distribution times its cosine, in tangent
+// space.
+fn sample_ggx(alpha: f32, rng: ptr<function, u32>) -> vec3<f32> {
+    let u = next_random(rng);
+    let phi = 2.0 * PI * next_random(rng);
+
+    let cos_theta = sqrt((1.0 - u) / (1.0 + (alpha * alpha - 1.0) * u));
+    let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+
+    return vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+}
+
+// Samples a direction proportionally to its cosine with the normal, in tangent space.
+// Samples a point on the unit disk, or on the regular polygon inscribed in it when the aperture has
+// blades.
+fn sample_aperture(rng: ptr<function, u32>) -> vec2<f32> {
+    let u = next_random(rng);
+    let v = next_random(rng);
+    let blades = render_info.aperture_blades;
+
+    if blades < 3u {
+        let phi = 2.0 * PI * v;
+        return sqrt(u) * vec2<f32>(cos(phi), sin(phi));
+    }
+
+		// pick one of the triangles between the center and an edge of the polygon, then a point in it
+    let scaled = v * f32(blades);
+    let blade = floor(scaled);
+    let angle = 2.0 * PI / f32(blades);
+
+    let start = vec2<f32>(cos(blade * angle), sin(blade * angle));
+    let end = vec2<f32>(cos((blade + 1.0) * angle), sin((blade + 1.0) * angle));
+
+    return sqrt(u) * mix(start, end, scaled - blade);
+}
+
+fn sample_cosine_hemisphere(rng: ptr<function, u32>) -> vec3<f32> {
+    let radius = sqrt(next_random(rng));
+    let phi = 2.0 * PI * next_random(rng);
+
+    let x = radius * cos(phi);
+    let y = radius * sin(phi);
+
+    return vec3<f32>(x, y, sqrt(max(1.0 - x * x - y * y, 0.0)));
+}
+
+// Builds a basis around the normal to bring tangent space directions into world space, following
+// "Building an Orthonormal Basis, Revisited" by Duff et al.
+fn orthonormal_basis(normal: vec3<f32>) -> mat3x3<f32> {
+    let sign_z = select(-1.0, 1.0, normal.z >= 0.0);
+    let a = -1.0 / (sign_z + normal.z);
+    let b = normal.x * normal.y * a;
+
+    let tangent = vec3<f32>(1.0 + sign_z * normal.x * normal.x * a, sign_z * b, -sign_z * normal.x);
+    let bitangent = vec3<f32>(b, sign_z + normal.y * normal.y * a, -normal.y);
+
+    return mat3x3<f32>(tangent, bitangent, normal);
+}
+
+// whether no component is nan or infinite, which can't be compared against reliably
+fn is_finite(value: vec3<f32>) -> bool {
+    return all((bitcast<vec3<u32>>(value) & vec3<u32>(0x7fffffffu)) < vec3<u32>(0x7f800000u));
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// creates a distinct seed for every sample of every pixel
+fn initial_seed(pixel_index: u32, sample: u32) -> u32 {
+    var seed = pixel_index;
+    next_random(&seed);
+
+    return seed ^ (sample * 0x9e3779b9u);
 }
 
 fn next_random(rng: ptr<function, u32>) -> f32 {
@@ -211,11 +1334,3 @@ fn next_random(rng: ptr<function, u32>) -> f32 {
 
     return f32((*rng) & 0x007fffffu) / f32(0x00800000u);
 }
-
-fn next_random_vec3(rng: ptr<function, u32>) -> vec3<f32> {
-    let x = next_random(rng);
-    let y = next_random(rng);
-    let z = next_random(rng);
-
-    return vec3<f32>(x, y, z);
-}
@@ -0,0 +1,63 @@
+use glam::{vec3, Vec3};
+use serde::{Deserialize, Serialize};
+
+/// A physically based model of a clear sky lit by the sun, following "A Practical Analytic Model
+/// for Daylight" by Preetham et al, used in place of a flat sky color.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProceduralSky {
+    /// The angle of the sun above the horizon, in degrees.
+    pub sun_elevation: f32,
+    /// The angle of the sun around the vertical axis in degrees, where zero is along the negative
+    /// Z axis.
+    pub sun_azimuth: f32,
+    /// The haziness of the atmosphere, from around 2 for a very clear sky to 10 for a hazy one.
+    pub turbidity: f32,
+    /// The angular diameter of the sun disk in degrees. The sun keeps the same total brightness
+    /// at any size, so this only changes how soft shadows are.
+    pub sun_size: f32,
+    /// The factor the brightness of the sun disk is multiplied by.
+    pub sun_intensity: f32,
+}
+
+impl ProceduralSky {
+    /// The smallest turbidity the model is fit for.
+    pub const MIN_TURBIDITY: f32 = 1.7;
+    /// The largest turbidity the model is fit for.
+    pub const MAX_TURBIDITY: f32 = 10.0;
+
+    /// The direction pointing towards the center of the sun.
+    pub fn sun_direction(&self) -> Vec3 {
+        let elevation = self.sun_elevation.clamp(0.0, 90.0).to_radians();
+        let azimuth = self.sun_azimuth.to_radians();
+
+        vec3(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        )
+    }
+
+    /// The turbidity, clamped to the range the model is fit for.
+    pub fn model_turbidity(&self) -> f32 {
+        self.turbidity
+            .clamp(Self::MIN_TURBIDITY, Self::MAX_TURBIDITY)
+    }
+
+    /// The angle between the center and the edge of the sun disk, in radians.
+    pub fn sun_angular_radius(&self) -> f32 {
+        (self.sun_size * 0.5).to_radians().max(1e-4)
+    }
+}
+
+impl Default for ProceduralSky {
+    fn default() -> Self {
+        Self {
+            sun_elevation: 45.0,
+            sun_azimuth: 30.0,
+            turbidity: 3.0,
+            sun_size: 0.53,
+            sun_intensity: 1.0,
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use glam::{vec3, Mat3, Vec3};
+use serde::{Deserialize, Serialize};
+
+/// The curve compressing high dynamic range radiance into the range a display can show.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToneMapper {
+    /// No curve at all, clipping any radiance above one.
+    #[default]
+    None,
+    /// The extended Reinhard curve, applied to every channel.
+    Reinhard,
+    /// Stephen Hill's fit of the ACES reference rendering and sRGB output transforms.
+    AcesFilmic,
+    /// Troy Sobotka's AgX, which desaturates bright colors instead of skewing their hue.
+    Agx,
+}
+
+/// How accumulated radiance is turned into displayed colors. This is only applied when the image
+/// is shown or saved as a png, and never changes the accumulated radiance itself.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToneMapping {
+    /// The curve the exposed radiance is mapped through.
+    pub tone_mapper: ToneMapper,
+    /// The exposure in stops, where every stop doubles the brightness.
+    pub exposure: f32,
+    /// The exposed radiance which is mapped to pure white, with anything brighter clipped. This
+    /// does nothing without a tone mapper, which always clips at one.
+    pub white_point: f32,
+}
+
+impl ToneMapper {
+    /// Every tone mapper, in the order they are listed in the ui.
+    pub const ALL: [Self; 4] = [Self::None, Self::Reinhard, Self::AcesFilmic, Self::Agx];
+
+    /// The name shown in the ui.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Reinhard => "reinhard",
+            Self::AcesFilmic => "aces filmic",
+            Self::Agx => "agx",
+        }
+    }
+}
+
+impl ToneMapping {
+    /// The factor radiance is multiplied by before the curve is applied.
+    pub fn exposure_scale(&self) -> f32 {
+        self.exposure.exp2()
+    }
+
+    /// Maps linear radiance to a linear display color in the range [0, 1], as in `blit.wgsl`.
+    pub fn apply(&self, radiance: Vec3) -> Vec3 {
+        let color = radiance.max(Vec3::ZERO) * self.exposure_scale();
+        let white = self.white_point.max(1e-4);
+
+        let mapped = match self.tone_mapper {
+            ToneMapper::None => color,
+            ToneMapper::Reinhard => color * (1.0 + color / (white * white)) / (1.0 + color),
+            ToneMapper::AcesFilmic => aces_filmic(color) / aces_filmic(Vec3::splat(white)),
+            ToneMapper::Agx => agx(color) / agx(Vec3::splat(white)),
+        };
+
+        mapped.clamp(Vec3::ZERO, Vec3::ONE)
+    }
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            tone_mapper: ToneMapper::None,
+            exposure: 0.0,
+            white_point: 8.0,
+        }
+    }
+}
+
+fn aces_filmic(color: Vec3) -> Vec3 {
+    // sRGB to the rendering space of the reference rendering transform, given row by row
+    let input = Mat3::from_cols(
+        vec3(0.59719, 0.35458, 0.04823),
+        vec3(0.07600, 0.90834, 0.01566),
+        vec3(0.02840, 0.13383, 0.83777),
+    )
+    .transpose();
+
+    // the output transform back to sRGB, given row by row
+    let output = Mat3::from_cols(
+        vec3(1.60475, -0.53108, -0.07367),
+        vec3(-0.10208, 1.10813, -0.00605),
+        vec3(-0.00327, -0.07276, 1.07602),
+    )
+    .transpose();
+
+    let v = input * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.432951) + 0.238081;
+
+    output * (a / b)
+}
+
+fn agx(color: Vec3) -> Vec3 {
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    let inset = Mat3::from_cols(
+        vec3(0.842479, 0.0423282, 0.0423757),
+        vec3(0.0784336, 0.878469, 0.0784336),
+        vec3(0.0792237, 0.0791661, 0.879143),
+    );
+
+    let outset = Mat3::from_cols(
+        vec3(1.196879, -0.0528969, -0.0529716),
+        vec3(-0.0980209, 1.151903, -0.0980435),
+        vec3(-0.0990297, -0.0989612, 1.151074),
+    );
+
+    // the log encoding of the inset color, normalized over the range of exposures it covers
+    let encoded = (inset * color)
+        .max(Vec3::splat(1e-10))
+        .map(f32::log2)
+        .clamp(Vec3::splat(MIN_EV), Vec3::splat(MAX_EV));
+    let x = (encoded - MIN_EV) / (MAX_EV - MIN_EV);
+
+    // a polynomial fit of the sigmoid contrast curve
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve =
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232;
+
+    // the curve produces display encoded values, so decode them back to linear
+    (outset * curve).max(Vec3::ZERO).powf(2.2)
+}